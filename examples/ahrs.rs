@@ -0,0 +1,50 @@
+//! Prints the board's roll, pitch and yaw from the Madgwick filter
+//!
+//! The gyro's output data rate paces the loop: each new gyro sample is fused
+//! with the latest accelerometer and magnetometer readings.
+
+#![no_std]
+#![no_main]
+use panic_rtt_target as _; // logs messages to the host stderr; requires a debugger
+
+use cortex_m_rt::entry;
+use rtt_target::{rtt_init_print, rprintln};
+use stm32f3xx_hal::pac;
+use stm32f3disc::{
+    ahrs::{Fusion, Madgwick},
+    board::Board,
+    l3gd20::{Odr, Scale},
+    lsm303dlhc::{AccelOdr, AccelRange, MagGain, MagOdr},
+};
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("AHRS Demo");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
+    let Board { mut gyro, mut lsm303, .. } = Board::init(dp, cp);
+
+    let odr = Odr::Hz95;
+    gyro.init(odr, Scale::Dps500).unwrap();
+    lsm303.init(AccelOdr::Hz100, AccelRange::G2, MagOdr::Hz75, MagGain::G1_3)
+        .unwrap();
+
+    let mut filter = Madgwick::new(odr.hz() as f32, 0.1);
+    let mut count: u32 = 0;
+    loop {
+        while !gyro.data_ready().unwrap() {}
+        let rate = gyro.gyro().unwrap();
+        let accel = lsm303.accel().unwrap();
+        let mag = lsm303.mag().unwrap();
+        filter.update(rate, accel, mag);
+
+        // about twice a second
+        if count % 48 == 0 {
+            let e = filter.euler();
+            rprintln!("roll {:7.1} pitch {:7.1} yaw {:7.1}", e.roll, e.pitch, e.yaw);
+        }
+        count = count.wrapping_add(1);
+    }
+}
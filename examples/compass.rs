@@ -0,0 +1,125 @@
+//! Electronic compass: lights the LED nearest magnetic north
+//!
+//! Reads the LSM303DLHC, removes the board's tilt using the accelerometer
+//! and points the LED ring at north. Keep the board away from magnets and
+//! steel.
+//!
+//! The magnetometer's offsets skew the heading until the board is
+//! calibrated: hold the user button for a second or send `c` on the serial
+//! port. Keep the board still while the LEDs fill up once, then turn it
+//! slowly through every orientation while they fill a second time. The
+//! result is stored in flash and loaded again at boot.
+
+#![no_std]
+#![no_main]
+use panic_rtt_target as _; // logs messages to the host stderr; requires a debugger
+
+use cortex_m_rt::entry;
+use rtt_target::{rtt_init_print, rprintln};
+use stm32f3xx_hal::{pac, prelude::*};
+use stm32f3disc::{
+    board::Board,
+    button::{Button, ButtonEvent},
+    calibration::{self, Calibration, Calibrator, Phase},
+    heading,
+    l3gd20::{Odr, Scale},
+    leds::{CompassLeds, Direction},
+    lsm303dlhc::{AccelOdr, AccelRange, MagGain, MagOdr},
+};
+
+const LOOP_MS: u32 = 10;
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Compass Demo");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
+    let Board { leds, button, mut serial, mut gyro, mut lsm303, mut delay, mut exti, .. } =
+        Board::init(dp, cp);
+    let mut compass = CompassLeds::new(leds);
+    let mut button = Button::new(button, &mut exti);
+
+    lsm303.init(AccelOdr::Hz50, AccelRange::G2, MagOdr::Hz30, MagGain::G1_3)
+        .unwrap();
+    gyro.init(Odr::Hz95, Scale::Dps250).unwrap();
+
+    let mut cal = match calibration::load() {
+        Some(cal) => {
+            rprintln!("calibration loaded {:?}", cal);
+            cal
+        }
+        None => {
+            rprintln!("no calibration stored, hold the button to calibrate");
+            Calibration::IDENTITY
+        }
+    };
+    let mut calibrator: Option<Calibrator> = None;
+
+    let mut now: u32 = 0;
+    let mut count: u32 = 0;
+    loop {
+        delay.delay_ms(LOOP_MS as u16);
+        now = now.wrapping_add(LOOP_MS);
+
+        button.poll(now);
+        let mut start = false;
+        while let Some(event) = button.pop() {
+            if event == ButtonEvent::LongPress {
+                start = true;
+            }
+        }
+        if let Ok(b'c') = serial.read() {
+            start = true;
+        }
+        if start && calibrator.is_none() {
+            rprintln!("calibrating, keep the board still");
+            calibrator = Some(Calibrator::new(now));
+        }
+
+        let accel = lsm303.accel().unwrap();
+        let mag = lsm303.mag().unwrap();
+
+        if let Some(c) = calibrator.as_mut() {
+            let before = c.phase();
+            match c.update(now, gyro.gyro().unwrap(), mag) {
+                Phase::MagRotate => {
+                    if before == Phase::GyroStill {
+                        rprintln!("now turn the board slowly through every orientation");
+                    }
+                    show_progress(&mut compass, c.progress(now));
+                }
+                Phase::GyroStill => show_progress(&mut compass, c.progress(now)),
+                Phase::Done(new) => {
+                    cal = new;
+                    match calibration::save(&cal) {
+                        Ok(()) => rprintln!("calibration saved {:?}", cal),
+                        Err(e) => rprintln!("calibration not saved: {:?}", e),
+                    }
+                    calibrator = None;
+                }
+                Phase::Failed => {
+                    rprintln!("calibration failed, the board wasn't turned enough");
+                    calibrator = None;
+                }
+            }
+            continue;
+        }
+
+        let bearing = heading::north_bearing(accel, cal.apply_mag(mag));
+        compass.point_at(bearing);
+
+        // once a second is plenty over RTT
+        if count % 100 == 0 {
+            rprintln!("north {:6.1} {:?}", bearing, Direction::from_bearing(bearing));
+        }
+        count = count.wrapping_add(1);
+    }
+}
+
+/// Light the ring clockwise from north as `progress` goes 0 to 255
+fn show_progress(compass: &mut CompassLeds, progress: u8) {
+    let lit = (progress as u16 * 9 / 256) as u8;
+    compass.set_mask(((1u16 << lit) - 1) as u8);
+}
@@ -0,0 +1,63 @@
+//! Prints the L3GD20 angular rate over RTT each time the gyro signals
+//! data ready on PE1
+
+#![no_std]
+#![no_main]
+use panic_rtt_target as _; // logs messages to the host stderr; requires a debugger
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m_rt::entry;
+use rtt_target::{rtt_init_print, rprintln};
+use critical_section::Mutex;
+use stm32f3xx_hal::{
+    interrupt,
+    pac::{self, Interrupt, NVIC},
+};
+use stm32f3disc::{
+    board::{Board, GyroDrdyPin},
+    l3gd20::{Odr, Scale},
+};
+
+static DATA_READY: AtomicBool = AtomicBool::new(false);
+
+static DRDY: Mutex<RefCell<Option<GyroDrdyPin>>> = Mutex::new(RefCell::new(None));
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Gyro Demo");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
+    let Board { mut gyro, gyro_drdy, .. } = Board::init(dp, cp);
+    critical_section::with(|cs| *DRDY.borrow(cs).borrow_mut() = Some(gyro_drdy));
+
+    gyro.init(Odr::Hz95, Scale::Dps250).unwrap();
+    gyro.enable_drdy_interrupt(true).unwrap();
+
+    unsafe {
+        NVIC::unmask(Interrupt::EXTI1);
+    }
+
+    // DRDY is level, not pulse; if a sample was already waiting the line is
+    // high and there won't be an edge, so read once to get it going
+    gyro.raw().unwrap();
+
+    loop {
+        cortex_m::asm::wfi();
+        if DATA_READY.swap(false, Ordering::Relaxed) {
+            let [x, y, z] = gyro.gyro().unwrap();
+            rprintln!("x {:8.2} y {:8.2} z {:8.2} dps", x, y, z);
+        }
+    }
+}
+
+#[interrupt]
+fn EXTI1() {
+    critical_section::with(|cs| {
+        DRDY.borrow(cs).borrow_mut().as_mut().unwrap().clear_interrupt();
+    });
+    DATA_READY.store(true, Ordering::Relaxed);
+}
@@ -0,0 +1,276 @@
+//! Answers `stm32f3disc_protocol` requests over the framed serial link
+//!
+//! Each request arrives as one frame from the host and gets one framed
+//! response: the LEDs can be read and set, and the sensors and firmware
+//! version read. User button presses are sent as events.
+
+#![no_std]
+#![no_main]
+use panic_rtt_target as _; // logs messages to the host stderr; requires a debugger
+
+#[allow(unused_imports)]
+use cortex_m_rt::entry;
+use rtt_target::{rtt_init_print, rprintln};
+use stm32f3xx_hal::{
+    pac,
+    prelude::*,
+    interrupt,
+};
+use stm32f3disc::{
+    board::{Board, Gyro, Lsm303},
+    button::{self, Button},
+    flow::FlowControl,
+    framing::{encoded_len, FrameDecoder, FrameEncoder},
+    l3gd20::{Odr, Scale},
+    leds::{CompassLeds, Direction},
+    lsm303dlhc::{AccelOdr, AccelRange, MagGain, MagOdr},
+    serial_dma::{Channels, DmaRx, DmaTx},
+    serial_errors::{ErrorCounters, RxError, RxErrors},
+    spsc::{Producer, Queue},
+    uprint::{Backpressure, SerialWriter},
+};
+use stm32f3disc_protocol::{
+    wire::{self, Decode, Reader},
+    ButtonEvent, ErrorCode, Event, FromDevice, Request, Response, Sensors, ToDevice, Version,
+    MAX_MESSAGE_LEN, PROTOCOL_VERSION,
+};
+
+// transmit queue, filled by main and emptied by DMA
+static mut XMIT_BUF: Queue<u8, 256> = Queue::new();
+static mut DMA_TX: Option<DmaTx<'static, 256>> = None;
+
+// circular DMA receive buffer, copied out on idle line, timeout, half and
+// full into the receive queue, which main empties
+static mut DMA_BUF: [u8; 64] = [0; 64];
+static mut DMA_RX: Option<DmaRx<64>> = None;
+static mut RECV_BUF: Queue<u8, 256> = Queue::new();
+static mut RECV: Option<Producer<'static, u8, 256>> = None;
+
+// receive errors, counted and reported to main by the USART interrupt
+static ERROR_COUNTS: ErrorCounters = ErrorCounters::new();
+static mut ERROR_BUF: Queue<RxError, 8> = Queue::new();
+static mut RX_ERRORS: Option<RxErrors<'static, 8>> = None;
+
+// off until `set_mode`, and then RTS/CTS only, holding the host off while
+// the receive queue is over three quarters full; that takes
+// `Board::flow_pins` and `FLOW.use_pins`. DmaTx doesn't do XON/XOFF
+static FLOW: FlowControl = FlowControl::new(64, 192, pac::Interrupt::DMA1_CH4);
+
+// receiver timeout, in bit times
+const RX_TIMEOUT_BITS: u32 = 20;
+
+const MAX_FRAME: usize = encoded_len(MAX_MESSAGE_LEN);
+
+const LOOP_MS: u32 = 10;
+
+struct Device {
+    leds: CompassLeds,
+    /// The LEDs lit, `CompassLeds` doesn't keep track
+    lit: u8,
+    gyro: Gyro,
+    lsm303: Lsm303,
+}
+
+impl Device {
+    fn answer(&mut self, request: Request) -> Response<'static> {
+        match request {
+            Request::Ping(value) => Response::Pong(value),
+            Request::Version => Response::Version(Version {
+                protocol: PROTOCOL_VERSION,
+                firmware: env!("CARGO_PKG_VERSION"),
+            }),
+            Request::GetLeds => Response::Leds(self.lit),
+            Request::SetLeds(mask) => {
+                self.set_leds(mask);
+                Response::Done
+            }
+            Request::SetLed { index, on } => {
+                if index > 7 {
+                    return Response::Error(ErrorCode::OutOfRange);
+                }
+                let bit = Direction::from_index(index as usize).mask();
+                self.set_leds(if on { self.lit | bit } else { self.lit & !bit });
+                Response::Done
+            }
+            Request::ReadSensors => match self.read_sensors() {
+                Ok(sensors) => Response::Sensors(sensors),
+                Err(()) => Response::Error(ErrorCode::Sensor),
+            },
+        }
+    }
+
+    fn set_leds(&mut self, mask: u8) {
+        self.leds.set_mask(mask);
+        self.lit = mask;
+    }
+
+    fn read_sensors(&mut self) -> Result<Sensors, ()> {
+        Ok(Sensors {
+            accel: self.lsm303.accel().map_err(|_| ())?,
+            gyro: self.gyro.gyro().map_err(|_| ())?,
+            mag: self.lsm303.mag().map_err(|_| ())?,
+            temperature: self.gyro.temperature().map_err(|_| ())?,
+        })
+    }
+}
+
+fn button_event(event: button::ButtonEvent) -> ButtonEvent {
+    match event {
+        button::ButtonEvent::Pressed => ButtonEvent::Pressed,
+        button::ButtonEvent::Released => ButtonEvent::Released,
+        button::ButtonEvent::LongPress => ButtonEvent::LongPress,
+        button::ButtonEvent::DoubleClick => ButtonEvent::DoubleClick,
+        button::ButtonEvent::Click => ButtonEvent::Click,
+    }
+}
+
+/// Encode, frame and queue a message
+fn send<const N: usize>(
+    out: &mut SerialWriter<'_, N>,
+    encoder: &mut FrameEncoder<MAX_FRAME>,
+    message: &FromDevice<'_>,
+) {
+    let mut buf = [0u8; MAX_MESSAGE_LEN];
+    let payload = match wire::to_slice(message, &mut buf) {
+        Ok(payload) => payload,
+        Err(error) => {
+            rprintln!("can't encode {:?}: {}", message, error);
+            return;
+        }
+    };
+    // MAX_FRAME has room for any message
+    let frame = encoder.encode(payload).unwrap();
+    out.write(frame).ok();
+}
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Serial RPC Demo");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
+
+    // USART1 on PC4/PC5
+    // the HAL serial driver is left alone, DMA does the work
+    let Board { leds, button, mut gyro, mut lsm303, dma1, mut delay, mut exti, .. } =
+        Board::init(dp, cp);
+
+    lsm303.init(AccelOdr::Hz50, AccelRange::G2, MagOdr::Hz30, MagGain::G1_3)
+        .unwrap();
+    gyro.init(Odr::Hz95, Scale::Dps250).unwrap();
+    let mut device = Device { leds: CompassLeds::new(leds), lit: 0, gyro, lsm303 };
+    device.set_leds(0);
+    let mut button = Button::new(button, &mut exti);
+
+    let (xmit, xmit_consumer) = unsafe { XMIT_BUF.split() };
+    let (recv_producer, mut recv) = unsafe { RECV_BUF.split() };
+    let (error_producer, mut errors) = unsafe { ERROR_BUF.split() };
+    let channels = Channels::new(dma1);
+
+    unsafe {
+        DMA_TX = Some(DmaTx::new(channels.tx, xmit_consumer));
+        RECV = Some(recv_producer);
+        DMA_RX = Some(DmaRx::new(channels.rx, &mut DMA_BUF, RX_TIMEOUT_BITS));
+        RX_ERRORS = Some(RxErrors::new(&ERROR_COUNTS, error_producer));
+    }
+
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::USART1_EXTI25);
+        pac::NVIC::unmask(pac::Interrupt::DMA1_CH4);
+        pac::NVIC::unmask(pac::Interrupt::DMA1_CH5);
+    }
+
+    // every request must get its response, so wait for room rather than
+    // drop one; main can always be preempted by the DMA interrupt
+    let mut out = SerialWriter::new(xmit, pac::Interrupt::DMA1_CH4, Backpressure::Block);
+
+    let mut decoder: FrameDecoder<MAX_FRAME> = FrameDecoder::new();
+    let mut encoder: FrameEncoder<MAX_FRAME> = FrameEncoder::new();
+    let mut reported = decoder.stats();
+    let mut now: u32 = 0;
+    let mut buffer = [0u8; 32];
+    loop {
+        delay.delay_ms(LOOP_MS as u16);
+        now = now.wrapping_add(LOOP_MS);
+
+        while let Some(error) = errors.get() {
+            rprintln!("receive error: {}, {} in all", error.name(), ERROR_COUNTS.get(error));
+        }
+
+        button.poll(now);
+        while let Some(event) = button.pop() {
+            send(&mut out, &mut encoder, &FromDevice::Event(Event::Button(button_event(event))));
+        }
+
+        let count = recv.pop_into(&mut buffer);
+        FLOW.drained(recv.len());
+        for byte in &buffer[..count] {
+            let payload = match decoder.feed(*byte) {
+                Some(Ok(payload)) => payload,
+                Some(Err(error)) => {
+                    rprintln!("bad frame: {:?}", error);
+                    continue;
+                }
+                None => continue,
+            };
+            let (id, response) = match wire::from_bytes::<ToDevice>(payload) {
+                Ok(ToDevice { id, request }) => (id, device.answer(request)),
+                Err(error) => {
+                    rprintln!("bad request: {}", error);
+                    // answer it if the id at least can be read
+                    match u16::decode(&mut Reader::new(payload)) {
+                        Ok(id) => (id, Response::Error(ErrorCode::Malformed)),
+                        Err(_) => continue,
+                    }
+                }
+            };
+            send(&mut out, &mut encoder, &FromDevice::Response { id, response });
+        }
+
+        let stats = decoder.stats();
+        if stats != reported {
+            reported = stats;
+            rprintln!(
+                "{} frames, {} errors ({} too long, {} malformed, {} bad CRC)",
+                stats.frames,
+                stats.errors(),
+                stats.too_long,
+                stats.malformed,
+                stats.crc
+            );
+        }
+    }
+}
+
+#[interrupt]
+fn DMA1_CH4() {
+    if let Some(dma_tx) = unsafe { DMA_TX.as_mut() } {
+        dma_tx.on_interrupt();
+    }
+}
+
+// move received bytes on to the receive queue, the USART and DMA
+// interrupts share the default priority so they never run at once
+fn receive() {
+    let dma_rx = unsafe { DMA_RX.as_mut().unwrap() };
+    dma_rx.check_errors(unsafe { RX_ERRORS.as_mut().unwrap() });
+    let recv = unsafe { RECV.as_mut().unwrap() };
+    dma_rx.on_interrupt(|bytes| {
+        if recv.push_slice(bytes) < bytes.len() {
+            rprintln!("recv full");
+        }
+    });
+    FLOW.received(recv.len());
+}
+
+#[interrupt]
+fn DMA1_CH5() {
+    receive();
+}
+
+// idle line, receiver timeout and errors
+#[interrupt]
+fn USART1_EXTI25() {
+    receive();
+}
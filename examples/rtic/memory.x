@@ -1,5 +1,7 @@
 MEMORY
 {
-    FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+    /* last page reserved for sensor calibration, as in ../../memory.x */
+    FLASH : ORIGIN = 0x08000000, LENGTH = 254K
+    CALIB : ORIGIN = 0x0803F800, LENGTH = 2K
     RAM : ORIGIN = 0x20000000, LENGTH = 40K
 }
@@ -6,106 +6,265 @@
 use panic_rtt_target as _;
 use rtic::app;
 use rtic_monotonics::systick::prelude::*;
+use rtic_sync::{channel, make_channel};
 use rtt_target::{rprintln, rtt_init_print};
-use stm32f3xx_hal::gpio::{Output, PushPull, PEx, PC4, PC5, AF7};
-use stm32f3xx_hal::{serial,serial::{Serial,
-        Event::{
-            ReceiveDataRegisterNotEmpty,
-            TransmitDataRegisterEmtpy,
-        },
-    },
-};
-use stm32f3xx_hal::pac::USART1;
-use stm32f3xx_hal::prelude::*;
+use stm32f3xx_hal::serial::Event::TransmitDataRegisterEmtpy;
+use stm32f3xx_hal::pac::{Interrupt, USART1};
+use stm32f3xx_hal::serial::{Instance, Serial};
+use embedded_hal::serial;
+use stm32f3disc::baud::{BaudChange, BaudRate};
+use stm32f3disc::board::{self, Board, SerialPins};
+use stm32f3disc::commands::{self, Control};
+use stm32f3disc::flow::{FlowControl, FlowError, FlowMode};
+use stm32f3disc::leds::{CompassLeds, Direction};
+use stm32f3disc::line_editor::{Event, LineEditor};
+use stm32f3disc::pattern::{Pattern, PatternEngine};
+use stm32f3disc::serial_dma::{Channels, DmaRx};
+use stm32f3disc::serial_errors::{ErrorCounters, RxError, RxErrors};
+use stm32f3disc::shell::Shell;
+use stm32f3disc::spsc::{Consumer, Producer, Queue};
+use stm32f3disc::uprint::{Backpressure, SerialWriter};
+use stm32f3disc::uprintln;
 
 systick_monotonic!(Mono, 1000);
 
-pub struct RingBuffer {
-    iptr: usize,
-    optr: usize,
-    buffer: [ u8; 256 ]
+/// Bytes each serial queue holds; enough for the longest console reply,
+/// `help`, which goes out only after the console task yields
+const QUEUE_LEN: usize = 1024;
+
+/// Bytes in the circular receive DMA buffer
+const RX_DMA_LEN: usize = 64;
+/// Receiver timeout, in bit times
+const RX_TIMEOUT_BITS: u32 = 20;
+
+/// Serial queues, one per direction, split between the interrupts and the
+/// tasks so neither side has to lock the other out
+type SerialQueue = Queue<u8, QUEUE_LEN>;
+
+/// Buffered, interrupt driven serial transmitter
+///
+/// Owned by the USART interrupt, which takes bytes to send from the
+/// consumer end of a queue. Tasks hold the producer end and pend the
+/// interrupt after queuing output. XON and XOFF from `flow` go out ahead
+/// of the queue, and the queue waits while the far end has sent XOFF.
+///
+/// Only the transmit side is here, and the only port is `Usart1Port`.
+/// Receiving is done by `Receiver` through `DmaRx`, which only works on
+/// USART1, so this is not a port for USART2 or USART3.
+pub struct SerialPort<USART, PINS> {
+    xmit: Consumer<'static, u8, QUEUE_LEN>,
+    serial: Serial<USART, PINS>,
+    flow: &'static FlowControl,
 }
 
-impl RingBuffer {
-    pub fn new() -> Self {
-        Self {
-            iptr: 0,
-            optr: 0,
-            buffer: [0; 256],
-        }
+impl<USART, PINS> SerialPort<USART, PINS>
+where
+    USART: Instance,
+    Serial<USART, PINS>: serial::Write<u8>,
+{
+    fn new(xmit: Consumer<'static, u8, QUEUE_LEN>, serial: Serial<USART, PINS>,
+           flow: &'static FlowControl) -> SerialPort<USART, PINS> {
+        SerialPort { xmit: xmit, serial: serial, flow: flow }
     }
 
-    pub fn put(&mut self, byte: u8) -> Result<(), ()> {
-        let next = (self.iptr + 1) % 256;
-        if next != self.optr {
-            self.buffer[self.iptr] = byte;
-            self.iptr = next;
-            Ok(())
-        } else {
-            Err(())
+    fn output_byte(&mut self) {
+        if let Some(control) = self.flow.next_control() {
+            let _ = serial::Write::write(&mut self.serial, control);
+            return;
+        }
+        if !self.flow.may_send() {
+            return;
+        }
+        if let Some(byte) = self.xmit.peek() {
+            // only take it off the queue once the USART has it
+            if serial::Write::write(&mut self.serial, byte).is_ok() {
+                let _ = self.xmit.get();
+            }
         }
     }
 
-    pub fn get(&mut self) -> Result<u8, ()> {
-        let byte = self.buffer[self.optr];
-        if self.iptr != self.optr {
-            self.optr = (self.optr + 1) % 256;
-            Ok(byte)
+    /// Body of the USART interrupt for this instance, also pended by tasks
+    /// after they queue output. True once the queue is down to half full,
+    /// for waking a writer waiting for room
+    fn on_interrupt(&mut self) -> bool {
+        if self.serial.triggered_events().contains(
+                TransmitDataRegisterEmtpy) {
+            self.output_byte();
+        }
+        // keep interrupting on TXE only while there is something to send
+        if !self.flow.wants_transmit(!self.xmit.is_empty()) {
+            self.serial.disable_interrupt(TransmitDataRegisterEmtpy);
         } else {
-            Err(())
+            self.serial.enable_interrupt(TransmitDataRegisterEmtpy);
         }
+        self.xmit.len() <= QUEUE_LEN / 2
     }
+}
 
-    pub fn empty(&self) -> bool {
-        self.iptr == self.optr
-    }
+/// The port on the ST-LINK virtual COM pins set up by `Board`
+type Usart1Port = SerialPort<USART1, SerialPins>;
 
-    pub fn full(&self) -> bool {
-        ((self.iptr + 1) % 256) == self.optr
+/// Receive errors queued for the console task to report
+const ERROR_QUEUE_LEN: usize = 8;
+
+/// Receive errors on USART1 since reset
+static ERROR_COUNTS: ErrorCounters = ErrorCounters::new();
+
+/// USART1 flow control, stopping the host at three quarters of the receive
+/// queue and letting it go on at a quarter
+static FLOW: FlowControl = FlowControl::new(QUEUE_LEN / 4, QUEUE_LEN * 3 / 4,
+                                            Interrupt::USART1_EXTI25);
+
+/// Frame lengths queued for the console task
+const FRAME_QUEUE_LEN: usize = 16;
+
+/// USART1 receive: circular DMA, copied on to the producer end of the
+/// receive queue whenever the buffer is half full, full or the line goes
+/// idle. An idle line ends a frame, whose length goes in a queue of its
+/// own after its bytes
+pub struct Receiver {
+    dma: DmaRx<RX_DMA_LEN>,
+    recv: Producer<'static, u8, QUEUE_LEN>,
+    errors: RxErrors<'static, ERROR_QUEUE_LEN>,
+    frames: Producer<'static, usize, FRAME_QUEUE_LEN>,
+    /// Bytes queued since the line was last idle
+    frame_len: usize,
+}
+
+impl Receiver {
+    /// Called from both the USART1 and DMA1_CH5 interrupts
+    fn on_interrupt(&mut self) {
+        self.dma.check_errors(&mut self.errors);
+        let recv = &mut self.recv;
+        let frame_len = &mut self.frame_len;
+        let idle = self.dma.on_interrupt(|bytes| {
+            for byte in bytes.iter().filter_map(|byte| FLOW.filter(*byte)) {
+                // dropped if nobody is reading
+                if recv.put(byte).is_ok() {
+                    *frame_len += 1;
+                }
+            }
+        });
+        FLOW.received(self.recv.len());
+        // with no room for the length the frame runs on into the next one
+        if idle && self.frame_len > 0 && self.frames.put(self.frame_len).is_ok() {
+            self.frame_len = 0;
+        }
     }
 }
 
-pub struct SerialPort {
-    xmit: RingBuffer,
-    recv: RingBuffer,
-    serial: Serial<USART1, (PC4<AF7<PushPull>>, PC5<AF7<PushPull>>)>,
+/// Console lines up to 64 characters, remembering the last 8
+type ConsoleEditor = LineEditor<64, 8>;
+const PROMPT: &str = "stm32f3> ";
+
+/// LED pattern step, in milliseconds
+const FRAME_MS: u32 = 10;
+
+/// What the console commands act on: the LEDs playing a pattern, or set
+/// one at a time while paused, and the USART1 baud rate
+pub struct Controls {
+    leds: CompassLeds,
+    engine: PatternEngine,
+    paused: bool,
+    baud: BaudRate,
+    /// Changes the console makes once the reply is out
+    baud_change: Option<BaudChange>,
+    flow_change: Option<FlowMode>,
+    reset_requested: bool,
 }
 
-impl SerialPort {
-    fn new(xmit: RingBuffer, recv: RingBuffer,
-           serial: Serial<USART1, (PC4<AF7<PushPull>>, PC5<AF7<PushPull>>)>) ->
-                SerialPort {
-        SerialPort { xmit: xmit, recv: recv, serial: serial }
+impl Controls {
+    fn show(&mut self) {
+        self.leds.set_mask(self.engine.frame().mask());
     }
 
-    fn output_byte(&mut self) -> Result<(), ()> {
-        let res = self.xmit.get();
-        match res {
-            Ok(byte) => {   // get a byte from the transmit queue
-                let _error = self.serial.write(byte);
-                Ok(())
-            }
-            Err(()) => {    // xmit queue empty return error
-                Err(())
-            }
+    /// Advance the pattern by `dt_ms` unless paused
+    fn tick(&mut self, dt_ms: u32) {
+        if !self.paused && self.engine.tick(dt_ms) {
+            self.show();
         }
     }
+}
 
-    fn input_byte(&mut self) -> Result<u8, ()> {
-        let res = self.serial.read();
-        match res {
-            Ok(byte) => {   // get byte for serial port
-                let _ = self.recv.put(byte);    // add to recv queue
-                let _ = self.xmit.put(byte);    // echo
-                Ok(byte)
-            }
-            Err(_error) => {    //
-                Err(())
-            }
+impl Control for Controls {
+    fn set_led(&mut self, index: usize, on: bool) {
+        self.paused = true;
+        self.leds.set(Direction::from_index(index), on);
+    }
+
+    fn pattern(&self) -> Pattern {
+        self.engine.pattern()
+    }
+
+    fn set_pattern(&mut self, pattern: Pattern) {
+        self.engine.set_pattern(pattern);
+        self.paused = false;
+        self.show();
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.show();
+    }
+
+    fn uptime_ms(&self) -> u32 {
+        Mono::now().duration_since_epoch().to_millis()
+    }
+
+    fn status(&self, out: &mut dyn core::fmt::Write) {
+        write!(out, "receive errors {}\r\n", ERROR_COUNTS.total()).ok();
+    }
+
+    fn reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    fn baud_rate(&self) -> u32 {
+        self.baud.get()
+    }
+
+    fn change_baud(&mut self, change: BaudChange) {
+        self.baud_change = Some(change);
+    }
+
+    fn flow_mode(&self) -> FlowMode {
+        FLOW.mode()
+    }
+
+    fn change_flow_mode(&mut self, mode: FlowMode) -> Result<(), FlowError> {
+        FLOW.check(mode)?;
+        self.flow_change = Some(mode);
+        Ok(())
+    }
+}
+
+/// Transmit side of USART1 as the tasks see it
+type Usart1Writer = SerialWriter<'static, QUEUE_LEN>;
+
+/// Room in the transmit queue, sent by the USART interrupt; one is enough
+/// to wake the writer
+const ROOM_WAKEUPS: usize = 1;
+type RoomWaker = channel::Sender<'static, (), ROOM_WAKEUPS>;
+type RoomWakeup = channel::Receiver<'static, (), ROOM_WAKEUPS>;
+
+/// Queue all of `bytes`, sleeping until the USART interrupt has made room
+/// rather than dropping any, whatever the writer's policy
+async fn write_all(xmit: &mut impl rtic::Mutex<T = Usart1Writer>, room: &mut RoomWakeup,
+                   mut bytes: &[u8]) {
+    while !bytes.is_empty() {
+        // a wakeup from before there was anything to wait for
+        while room.try_recv().is_ok() {}
+        let queued = xmit.lock(|xmit| xmit.write_some(bytes));
+        bytes = &bytes[queued..];
+        if !bytes.is_empty() {
+            room.recv().await.ok();
         }
     }
 }
-            
 
 #[app(device = stm32f3xx_hal::pac, peripherals = true, dispatchers = [SPI1, SPI2])]
 mod app {
@@ -113,143 +272,191 @@ mod app {
 
     #[shared]
     struct Shared {
-        serial_port: SerialPort,
+        /// Only tasks at one priority write, so locking it costs nothing
+        xmit: Usart1Writer,
+        /// LEDs played by `blink`, changed from the console; both at
+        /// priority 1
+        controls: Controls,
+        /// Shared by the two interrupts that deliver received bytes, both
+        /// at priority 1
+        receiver: Receiver,
     }
 
     #[local]
     struct Local {
-        leds: [ PEx<Output<PushPull>>; 8 ],
+        serial_port: Usart1Port,
+        room_waker: RoomWaker,
+        room: RoomWakeup,
+        recv: Consumer<'static, u8, QUEUE_LEN>,
+        rx_errors: Consumer<'static, RxError, ERROR_QUEUE_LEN>,
+        frames: Consumer<'static, usize, FRAME_QUEUE_LEN>,
     }
 
-    #[init]
+    #[init(local = [
+        xmit_queue: SerialQueue = SerialQueue::new(),
+        recv_queue: SerialQueue = SerialQueue::new(),
+        rx_dma_buf: [u8; RX_DMA_LEN] = [0; RX_DMA_LEN],
+        error_queue: Queue<RxError, ERROR_QUEUE_LEN> = Queue::new(),
+        frame_queue: Queue<usize, FRAME_QUEUE_LEN> = Queue::new(),
+    ])]
     fn init(cx: init::Context) -> (Shared, Local) {
         rtt_init_print!();
-        let xmit = RingBuffer::new();
-        let recv = RingBuffer::new();
-        // Setup clocks
-        let mut flash = cx.device.FLASH.constrain();
-        let mut rcc = cx.device.RCC.constrain();
+        let (xmit_producer, xmit_consumer) = cx.local.xmit_queue.split();
+        let (recv_producer, recv) = cx.local.recv_queue.split();
+        let (error_producer, rx_errors) = cx.local.error_queue.split();
+        let (frame_producer, frames) = cx.local.frame_queue.split();
+        let Board { leds, serial, dma1, delay, .. } =
+                Board::init(cx.device, cx.core);
 
-        // Initialize the systick interrupt
-        Mono::start(cx.core.SYST, 36_000_000); // default STM32F303 clock-rate
-                                               // is 36MHz
+        // Initialize the systick interrupt, taking SYST back from the delay
+        Mono::start(delay.free(), board::SYSCLK_HZ);
 
         rprintln!("init");
 
-        let clocks = rcc
-            .cfgr
-            .use_hse(8.MHz())
-            .sysclk(36.MHz())
-            .pclk1(36.MHz())
-            .freeze(&mut flash.acr);
-
-        // Setup LED
-        let mut gpioe = cx.device.GPIOE.split(&mut rcc.ahb);
-        let mut leds = [
-            gpioe.pe8.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe9.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe10.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe11.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe12.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe13.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe14.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-            gpioe.pe15.
-                    into_push_pull_output(&mut gpioe.moder,
-                                  &mut gpioe.otyper).downgrade(),
-        ];
-        for led in leds.iter_mut() {
-            led.set_low().unwrap();
-        }
+        let serial_port = SerialPort::new(xmit_consumer, serial, &FLOW);
+        let (room_waker, room) = make_channel!((), ROOM_WAKEUPS);
+        // the USART interrupt can't preempt the tasks, so they can't block
+        // on a full queue; whole lines are dropped instead of mangled
+        let xmit = SerialWriter::new(xmit_producer,
+                Interrupt::USART1_EXTI25, Backpressure::AllOrNothing);
+        let channels = Channels::new(dma1);
+        let receiver = Receiver {
+            dma: DmaRx::new(channels.rx, cx.local.rx_dma_buf, RX_TIMEOUT_BITS),
+            recv: recv_producer,
+            errors: RxErrors::new(&ERROR_COUNTS, error_producer),
+            frames: frame_producer,
+            frame_len: 0,
+        };
 
-        let mut gpioc = cx.device.GPIOC.split(&mut rcc.ahb);
-        let rx = gpioc.pc5.into_af_push_pull::<7>(
-                &mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-        let tx = gpioc.pc4.into_af_push_pull::<7>(
-                &mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-        let serial = serial::Serial::new(
-            cx.device.USART1,
-            (tx, rx),
-            115200.Bd(), // Set your desired baud rate
-            clocks,
-            &mut rcc.apb2,
-        );
-
-        let mut serial_port = SerialPort::new(xmit, recv, serial);
-
-        // enable serial interrupts
-        serial_port.serial.enable_interrupt(ReceiveDataRegisterNotEmpty);
-        
         // enqueue "Hello World"
         enqueue::spawn("Hello World\r\n".as_bytes()).ok();
 
-        // Schedule the blinking task
+        let mut controls = Controls {
+            leds: CompassLeds::new(leds),
+            engine: PatternEngine::new(Pattern::Chase),
+            paused: false,
+            baud: BaudRate::new(board::PCLK2_HZ),
+            baud_change: None,
+            flow_change: None,
+            reset_requested: false,
+        };
+        controls.show();
+
+        // Schedule the blinking and console tasks
         blink::spawn().ok();
+        console::spawn().ok();
 
-        (Shared { serial_port }, Local { leds })
+        (Shared { xmit, controls, receiver },
+         Local { serial_port, room_waker, room, recv, rx_errors, frames })
     }
 
-    #[task(shared = [serial_port])]
+    #[task(shared = [xmit], local = [room])]
     async fn enqueue(mut cx: enqueue::Context, str: &[u8]) {
-        cx.shared.serial_port.lock(|serial_port| {
-            for i in str {
-                serial_port.xmit.put(*i).unwrap();
-            }
-            serial_port.serial.enable_interrupt(TransmitDataRegisterEmtpy);
-        });
+        write_all(&mut cx.shared.xmit, cx.local.room, str).await;
     }
 
-    #[task(local = [leds])]
-    async fn blink(cx: blink::Context) {
+    /// Command shell on USART1, taking input a frame at a time once the
+    /// line has gone quiet; also logs receive errors
+    #[task(shared = [xmit, controls], local = [
+        recv,
+        rx_errors,
+        frames,
+        editor: ConsoleEditor = ConsoleEditor::new(PROMPT),
+        shell: Shell<Controls, { commands::SHELL_SIZE }> = Shell::new(),
+    ])]
+    async fn console(mut cx: console::Context) {
+        let editor = cx.local.editor;
+        let shell = cx.local.shell;
+        shell.register_all(&commands::builtin()).ok();
+        let mut buf = [0; 16];
+        // bytes of the current frame still in the receive queue
+        let mut frame_left = 0;
+        Mono::delay(10.millis()).await;    // after "Hello World"
+        cx.shared.xmit.lock(|xmit| editor.prompt(xmit));
         loop {
-            for led in cx.local.leds.iter_mut() {
-                rprintln!("blink");
-                led.set_high().unwrap();
-                Mono::delay(1000.millis()).await;
-                led.set_low().unwrap();
+            while let Some(error) = cx.local.rx_errors.get() {
+                rprintln!("receive error: {}, {} in all",
+                        error.name(), ERROR_COUNTS.get(error));
             }
-
-        }
-    }
-
-    #[task(binds = USART1_EXTI25, shared = [serial_port])]
-    fn usart1(mut cx: usart1::Context) {
-        cx.shared.serial_port.lock(|serial_port| {
-            if serial_port.serial.triggered_events().contains(
-                    TransmitDataRegisterEmtpy) {
-                match serial_port.output_byte() {
-                    Ok(()) => {
-                    }
-                    Err(_error) => {    // transmit queue empty set flag 
-                        serial_port.serial.disable_interrupt(
-                                TransmitDataRegisterEmtpy);
-                    }
+            let detected = cx.shared.controls.lock(|controls| controls.baud.poll());
+            match detected {
+                Some(Ok(rate)) => {
+                    rprintln!("detected {} baud", rate);
+                    cx.shared.xmit.lock(|xmit| uprintln!(xmit, "detected {} baud", rate).ok());
                 }
+                Some(Err(_)) => rprintln!("baud rate detection failed, trying again"),
+                None => (),
             }
-            if serial_port.serial.triggered_events().contains(
-                    ReceiveDataRegisterNotEmpty) {
-                match serial_port.input_byte() {
-                    Ok(_byte) => {
-                        serial_port.serial.enable_interrupt(
-                                TransmitDataRegisterEmtpy);
-                    }
-                    Err(_error) => {
+            if frame_left == 0 {
+                if let Some(len) = cx.local.frames.get() {
+                    rprintln!("frame of {} bytes", len);
+                    frame_left = len;
+                }
+            }
+            let take = frame_left.min(buf.len());
+            let count = cx.local.recv.pop_into(&mut buf[..take]);
+            frame_left -= count;
+            FLOW.drained(cx.local.recv.len());
+            let controls = &mut cx.shared.controls;
+            let changes = cx.shared.xmit.lock(|xmit| controls.lock(|controls| {
+                for byte in &buf[..count] {
+                    if let Some(Event::Line(line)) = editor.feed(*byte, xmit) {
+                        rprintln!("command: {}", line);
+                        let _ = shell.run(line, controls, xmit);
+                        editor.prompt(xmit);
                     }
                 }
+                (controls.baud_change.take(), controls.flow_change.take(),
+                 controls.reset_requested)
+            }));
+            let (baud_change, flow_change, reset) = changes;
+            if baud_change.is_some() || flow_change.is_some() || reset {
+                // let the reply, and the prompt, go out first
+                while cx.shared.xmit.lock(|xmit| xmit.pending()) > 0 {
+                    Mono::delay(1.millis()).await;
+                }
             }
-        });
+            if reset {
+                Mono::delay(2.millis()).await;      // the last byte
+                cortex_m::peripheral::SCB::sys_reset();
+            }
+            if let Some(change) = baud_change {
+                cx.shared.controls.lock(|controls| match change {
+                    BaudChange::To(rate) => {
+                        controls.baud.set(rate).ok();
+                    }
+                    BaudChange::Detect => controls.baud.detect(),
+                });
+            }
+            if let Some(mode) = flow_change {
+                FLOW.set_mode(mode).ok();
+            }
+            Mono::delay(5.millis()).await;
+        }
+    }
+
+    /// Play the LED pattern
+    #[task(shared = [controls])]
+    async fn blink(mut cx: blink::Context) {
+        loop {
+            cx.shared.controls.lock(|controls| controls.tick(FRAME_MS));
+            Mono::delay(FRAME_MS.millis()).await;
+        }
+    }
+
+    #[task(binds = USART1_EXTI25, local = [serial_port, room_waker], shared = [receiver])]
+    fn usart1(mut cx: usart1::Context) {
+        if cx.local.serial_port.on_interrupt() {
+            // already full if nobody has woken to take the last one
+            cx.local.room_waker.try_send(()).ok();
+        }
+        // idle line and receiver timeout
+        cx.shared.receiver.lock(|receiver| receiver.on_interrupt());
+    }
+
+    /// Receive DMA half and full
+    #[task(binds = DMA1_CH5, shared = [receiver])]
+    fn dma1_ch5(mut cx: dma1_ch5::Context) {
+        cx.shared.receiver.lock(|receiver| receiver.on_interrupt());
     }
 }
@@ -4,7 +4,12 @@ use panic_rtt_target as _; // logs messages to the host stderr; requires a debug
 
 use cortex_m_rt::entry;
 use rtt_target::{rtt_init_print, rprintln};
-use stm32f3xx_hal::{pac, prelude::*, serial};
+use stm32f3xx_hal::{pac, prelude::*};
+use stm32f3disc::{
+    board::Board,
+    serial_errors::{ErrorCounters, RxError, RxErrors},
+    spsc::Queue,
+};
 
 #[entry]
 fn main() -> ! {
@@ -12,25 +17,23 @@ fn main() -> ! {
     rprintln!("Serial Demo");
 
     let dp = pac::Peripherals::take().unwrap();
-    let mut rcc = dp.RCC.constrain();
-    let mut flash = dp.FLASH.constrain();
-    let clocks = rcc.cfgr.sysclk(48.MHz()).freeze(&mut flash.acr);
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
 
-    // Configure GPIO pins PC4 and PC5 for UART alternate function
-    let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
-    let tx = gpioc.pc4.into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-    let rx = gpioc.pc5.into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
+    // USART1 on PC4/PC5
+    let Board { mut serial, .. } = Board::init(dp, cp);
 
-    // Configure UART1 (or your desired UART)
-    let mut serial = serial::Serial::new(
-        dp.USART1,
-        (tx, rx),
-        115200.Bd(), // Set your desired baud rate
-        clocks,
-        &mut rcc.apb2,
-    );
+    let counters = ErrorCounters::new();
+    let mut queue: Queue<RxError, 8> = Queue::new();
+    let (producer, mut errors) = queue.split();
+    let mut rx_errors = RxErrors::new(&counters, producer);
+    let usart = unsafe { &*pac::USART1::ptr() };
 
     loop {
+        if rx_errors.check(usart) {
+            while let Some(error) = errors.get() {
+                rprintln!("receive error: {}, {} in all", error.name(), counters.get(error));
+            }
+        }
         match serial.read() {
             Ok(byte) => {
                 serial.write(byte).unwrap();
@@ -0,0 +1,169 @@
+#![no_std]
+#![no_main]
+use panic_rtt_target as _; // logs messages to the host stderr; requires a debugger
+
+#[allow(unused_imports)]
+use cortex_m_rt::entry;
+use rtt_target::{rtt_init_print, rprintln};
+use stm32f3xx_hal::{
+    pac,
+    prelude::*,
+    interrupt,
+};
+use stm32f3disc::{
+    board::Board,
+    flow::FlowControl,
+    framing::{encoded_len, FrameDecoder, FrameEncoder},
+    serial_dma::{Channels, DmaRx, DmaTx},
+    serial_errors::{ErrorCounters, RxError, RxErrors},
+    spsc::{Producer, Queue},
+    uprint::{Backpressure, SerialWriter},
+};
+
+// transmit queue, filled by main and emptied by DMA
+static mut XMIT_BUF: Queue<u8, 256> = Queue::new();
+static mut DMA_TX: Option<DmaTx<'static, 256>> = None;
+
+// circular DMA receive buffer, copied out on idle line, timeout, half and
+// full into the receive queue, which main empties
+static mut DMA_BUF: [u8; 64] = [0; 64];
+static mut DMA_RX: Option<DmaRx<64>> = None;
+static mut RECV_BUF: Queue<u8, 256> = Queue::new();
+static mut RECV: Option<Producer<'static, u8, 256>> = None;
+
+// receive errors, counted and reported to main by the USART interrupt
+static ERROR_COUNTS: ErrorCounters = ErrorCounters::new();
+static mut ERROR_BUF: Queue<RxError, 8> = Queue::new();
+static mut RX_ERRORS: Option<RxErrors<'static, 8>> = None;
+
+// off until `set_mode`, and then RTS/CTS only, holding the host off while
+// the receive queue is over three quarters full; that takes
+// `Board::flow_pins` and `FLOW.use_pins`. DmaTx doesn't do XON/XOFF
+static FLOW: FlowControl = FlowControl::new(64, 192, pac::Interrupt::DMA1_CH4);
+
+// receiver timeout, in bit times
+const RX_TIMEOUT_BITS: u32 = 20;
+
+// largest payload echoed back
+const MAX_PAYLOAD: usize = 128;
+const MAX_FRAME: usize = encoded_len(MAX_PAYLOAD);
+
+#[entry]
+fn main() -> ! {
+    rtt_init_print!();
+    rprintln!("Serial Frames Demo");
+
+    let dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
+
+    // USART1 on PC4/PC5
+    // the HAL serial driver is left alone, DMA does the work
+    let Board { dma1, mut delay, .. } = Board::init(dp, cp);
+
+    let (xmit, xmit_consumer) = unsafe { XMIT_BUF.split() };
+    let (recv_producer, mut recv) = unsafe { RECV_BUF.split() };
+    let (error_producer, mut errors) = unsafe { ERROR_BUF.split() };
+    let channels = Channels::new(dma1);
+
+    unsafe {
+        DMA_TX = Some(DmaTx::new(channels.tx, xmit_consumer));
+        RECV = Some(recv_producer);
+        DMA_RX = Some(DmaRx::new(channels.rx, &mut DMA_BUF, RX_TIMEOUT_BITS));
+        RX_ERRORS = Some(RxErrors::new(&ERROR_COUNTS, error_producer));
+    }
+
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::USART1_EXTI25);
+        pac::NVIC::unmask(pac::Interrupt::DMA1_CH4);
+        pac::NVIC::unmask(pac::Interrupt::DMA1_CH5);
+    }
+
+    // a frame goes into the transmit queue whole or not at all, so the
+    // host never sees half of one
+    let mut out = SerialWriter::new(xmit, pac::Interrupt::DMA1_CH4, Backpressure::AllOrNothing);
+
+    let mut decoder: FrameDecoder<MAX_FRAME> = FrameDecoder::new();
+    let mut encoder: FrameEncoder<MAX_FRAME> = FrameEncoder::new();
+    let mut reported = decoder.stats();
+    let mut ms: u32 = 0;
+    let mut buffer = [0u8; 32];
+    loop {
+        delay.delay_ms(10u16);
+        ms += 10;
+
+        while let Some(error) = errors.get() {
+            rprintln!("receive error: {}, {} in all", error.name(), ERROR_COUNTS.get(error));
+        }
+
+        // echo every packet that arrives intact
+        let count = recv.pop_into(&mut buffer);
+        FLOW.drained(recv.len());
+        for byte in &buffer[..count] {
+            match decoder.feed(*byte) {
+                Some(Ok(payload)) => {
+                    // the decoder takes a little more than MAX_PAYLOAD,
+                    // the encoder says if it won't fit
+                    match encoder.encode(payload) {
+                        Ok(frame) => {
+                            if out.write(frame).is_err() {
+                                rprintln!("xmit full, dropped a reply");
+                            }
+                        }
+                        Err(error) => rprintln!("can't echo: {:?}", error),
+                    }
+                }
+                Some(Err(error)) => rprintln!("bad frame: {:?}", error),
+                None => {}
+            }
+        }
+
+        if ms < 1000 {
+            continue;
+        }
+        ms = 0;
+        let stats = decoder.stats();
+        if stats != reported {
+            reported = stats;
+            rprintln!(
+                "{} frames, {} errors ({} too long, {} malformed, {} bad CRC)",
+                stats.frames,
+                stats.errors(),
+                stats.too_long,
+                stats.malformed,
+                stats.crc
+            );
+        }
+    }
+}
+
+#[interrupt]
+fn DMA1_CH4() {
+    if let Some(dma_tx) = unsafe { DMA_TX.as_mut() } {
+        dma_tx.on_interrupt();
+    }
+}
+
+// move received bytes on to the receive queue, the USART and DMA
+// interrupts share the default priority so they never run at once
+fn receive() {
+    let dma_rx = unsafe { DMA_RX.as_mut().unwrap() };
+    dma_rx.check_errors(unsafe { RX_ERRORS.as_mut().unwrap() });
+    let recv = unsafe { RECV.as_mut().unwrap() };
+    dma_rx.on_interrupt(|bytes| {
+        if recv.push_slice(bytes) < bytes.len() {
+            rprintln!("recv full");
+        }
+    });
+    FLOW.received(recv.len());
+}
+
+#[interrupt]
+fn DMA1_CH5() {
+    receive();
+}
+
+// idle line, receiver timeout and errors
+#[interrupt]
+fn USART1_EXTI25() {
+    receive();
+}
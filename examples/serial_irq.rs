@@ -9,18 +9,21 @@ use stm32f3xx_hal::{
     pac,
     prelude::*,
     serial,
-    serial::Serial,
     interrupt,
-    pac::USART1,
-    gpio::{
-        gpioc::
-        {PC4, PC5}, PushPull, AF7},
 };
-
-type SerialType = Serial<USART1, (PC4<AF7<PushPull>>, PC5<AF7<PushPull>>)>;
+use stm32f3disc::{
+    board::{Board, SerialType},
+    serial_errors::{ErrorCounters, RxError, RxErrors},
+    spsc::Queue,
+};
 
 static mut SERIAL: Option<SerialType> = None;
 
+// receive errors, counted and reported to main by the interrupt
+static ERROR_COUNTS: ErrorCounters = ErrorCounters::new();
+static mut ERROR_BUF: Queue<RxError, 8> = Queue::new();
+static mut RX_ERRORS: Option<RxErrors<'static, 8>> = None;
+
 unsafe fn get_serial() -> &'static mut SerialType {
     if let Some(ref mut gpioc) = SERIAL { &mut *gpioc } else { panic!() }
 }
@@ -31,36 +34,29 @@ fn main() -> ! {
     rprintln!("Serial Interrupt Demo");
 
     let dp = pac::Peripherals::take().unwrap();
-    let mut rcc = dp.RCC.constrain();
-    let mut flash = dp.FLASH.constrain();
-    let clocks = rcc.cfgr.sysclk(48.MHz()).freeze(&mut flash.acr);
+    let cp = cortex_m::peripheral::Peripherals::take().unwrap();
 
-    // Configure GPIO pins PC4 and PC5 for UART alternate function
-    let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
-    let tx = gpioc.pc4.into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-    let rx = gpioc.pc5.into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-
-    // Configure UART1 (or your desired UART)
-    let serial = serial::Serial::new(
-        dp.USART1,
-        (tx, rx),
-        115200.Bd(), // Set your desired baud rate
-        clocks,
-        &mut rcc.apb2,
-    );
+    // USART1 on PC4/PC5
+    let Board { serial, .. } = Board::init(dp, cp);
 
     unsafe {
         pac::NVIC::unmask(pac::Interrupt::USART1_EXTI25);
     }
 
+    let (error_producer, mut errors) = unsafe { ERROR_BUF.split() };
+
     unsafe {
         SERIAL = Some(serial);
+        RX_ERRORS = Some(RxErrors::new(&ERROR_COUNTS, error_producer));
     }
 
     let serial = unsafe { get_serial() };
     serial.enable_interrupt(serial::Event::ReceiveDataRegisterNotEmpty);
 
     loop {
+        while let Some(error) = errors.get() {
+            rprintln!("receive error: {}, {} in all", error.name(), ERROR_COUNTS.get(error));
+        }
     }
 }
 
@@ -68,6 +64,9 @@ fn main() -> ! {
 fn USART1_EXTI25() {
     rprintln!("USART1");
     let serial = unsafe { get_serial() };
+    // RXNEIE also interrupts on overrun, clear it or the interrupt repeats
+    let rx_errors = unsafe { RX_ERRORS.as_mut().unwrap() };
+    rx_errors.check(unsafe { &*pac::USART1::ptr() });
     match serial.read() {
         Ok(byte) => {
             serial.write(byte).unwrap();
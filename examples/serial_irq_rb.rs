@@ -8,148 +8,161 @@ use rtt_target::{rtt_init_print, rprintln};
 use stm32f3xx_hal::{
     pac,
     prelude::*,
-    serial,
-    serial::{Serial,
-        Event:: {
-            ReceiveDataRegisterNotEmpty,
-            TransmitDataRegisterEmtpy,
-        },
-    },
     interrupt,
-    pac::USART1,
-    gpio::{
-        gpioc::
-        {PC4, PC5}, PushPull, AF7},
-    delay::Delay,
 };
-use fring;
-use format_no_std;
+use stm32f3disc::{
+    board::Board,
+    flow::FlowControl,
+    serial_dma::{Channels, DmaRx, DmaTx},
+    serial_errors::{ErrorCounters, RxError, RxErrors},
+    spsc::{Producer, Queue},
+    uprint::{Backpressure, SerialWriter},
+    uprintln,
+};
 
-type SerialType = Serial<USART1, (PC4<AF7<PushPull>>, PC5<AF7<PushPull>>)>;
+// transmit queue, filled by main and emptied by DMA
+static mut XMIT_BUF: Queue<u8, 256> = Queue::new();
+static mut DMA_TX: Option<DmaTx<'static, 256>> = None;
 
-static mut SERIAL: Option<SerialType> = None;
+// circular DMA receive buffer, copied out on idle line, timeout, half and
+// full into the receive queue, which main empties
+static mut DMA_BUF: [u8; 64] = [0; 64];
+static mut DMA_RX: Option<DmaRx<64>> = None;
+static mut RECV_BUF: Queue<u8, 256> = Queue::new();
+static mut RECV: Option<Producer<'static, u8, 256>> = None;
 
-static XMIT_BUF: fring::Buffer::<256> = fring::Buffer::new();
+// receive errors, counted and reported to main by the USART interrupt
+static ERROR_COUNTS: ErrorCounters = ErrorCounters::new();
+static mut ERROR_BUF: Queue<RxError, 8> = Queue::new();
+static mut RX_ERRORS: Option<RxErrors<'static, 8>> = None;
 
-unsafe fn get_serial() -> &'static mut SerialType {
-    if let Some(ref mut gpioc) = SERIAL { &mut *gpioc } else { panic!() }
-}
+// off until `set_mode`, and then RTS/CTS only, holding the host off while
+// the receive queue is over three quarters full; that takes
+// `Board::flow_pins` and `FLOW.use_pins`. DmaTx doesn't do XON/XOFF
+static FLOW: FlowControl = FlowControl::new(64, 192, pac::Interrupt::DMA1_CH4);
 
-// put byte into xmit queue
-fn put_byte(byte: u8) -> Result<(), ()> {
-    let mut xmit = unsafe { XMIT_BUF.producer() };
-    let mut w = xmit.write(1);
-    if w.len() == 0 {
-        Err(())
-    } else {
-        w[0] = byte;
-        Ok(())
-    }
-}
+// lengths of the frames ended by an idle line, for main to split the
+// receive queue by
+static mut FRAME_BUF: Queue<usize, 8> = Queue::new();
+static mut FRAMES: Option<Producer<'static, usize, 8>> = None;
+// bytes queued since the line was last idle
+static mut FRAME_LEN: usize = 0;
+
+// receiver timeout, in bit times
+const RX_TIMEOUT_BITS: u32 = 20;
 
 #[entry]
 fn main() -> ! {
     rtt_init_print!();
-    rprintln!("Serial Interrupt Demo");
+    rprintln!("Serial DMA Demo");
 
     let dp = pac::Peripherals::take().unwrap();
     let cp = cortex_m::peripheral::Peripherals::take().unwrap();
-    let mut rcc = dp.RCC.constrain();
-    let mut flash = dp.FLASH.constrain();
-    let clocks = rcc.cfgr.sysclk(48.MHz()).freeze(&mut flash.acr);
-    let mut delay = Delay::new(cp.SYST, clocks);
-
-    // Configure GPIO pins PC4 and PC5 for UART alternate function
-    let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
-    let tx = gpioc.pc4.into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-    let rx = gpioc.pc5.into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
-
-    // Configure UART1 (or your desired UART)
-    let serial = serial::Serial::new(
-        dp.USART1,
-        (tx, rx),
-        115200.Bd(), // Set your desired baud rate
-        clocks,
-        &mut rcc.apb2,
-    );
+
+    // USART1 on PC4/PC5
+    // the HAL serial driver is left alone, DMA does the work
+    let Board { dma1, mut delay, .. } = Board::init(dp, cp);
+
+    let (xmit, xmit_consumer) = unsafe { XMIT_BUF.split() };
+    let (recv_producer, mut recv) = unsafe { RECV_BUF.split() };
+    let (error_producer, mut errors) = unsafe { ERROR_BUF.split() };
+    let (frame_producer, mut frames) = unsafe { FRAME_BUF.split() };
+    let channels = Channels::new(dma1);
 
     unsafe {
-        pac::NVIC::unmask(pac::Interrupt::USART1_EXTI25);
+        DMA_TX = Some(DmaTx::new(channels.tx, xmit_consumer));
+        RECV = Some(recv_producer);
+        FRAMES = Some(frame_producer);
+        DMA_RX = Some(DmaRx::new(channels.rx, &mut DMA_BUF, RX_TIMEOUT_BITS));
+        RX_ERRORS = Some(RxErrors::new(&ERROR_COUNTS, error_producer));
     }
 
     unsafe {
-        SERIAL = Some(serial);
+        pac::NVIC::unmask(pac::Interrupt::USART1_EXTI25);
+        pac::NVIC::unmask(pac::Interrupt::DMA1_CH4);
+        pac::NVIC::unmask(pac::Interrupt::DMA1_CH5);
     }
 
-    let serial = unsafe { get_serial() };
-    serial.enable_interrupt(ReceiveDataRegisterNotEmpty);
+    // main can always be preempted by the DMA interrupt, so it can wait
+    // for room rather than lose output
+    let mut out = SerialWriter::new(xmit, pac::Interrupt::DMA1_CH4, Backpressure::Block);
 
     let mut tick: u32 = 0;
+    let mut ms: u32 = 0;
     let mut buffer = [0u8; 32];
     loop {
-        delay.delay_ms(1000u16);
-        rprintln!("Tick {}", tick);
-        let b = format_no_std::show(
-            &mut buffer,
-            format_args!("Tick {}\r\n", tick),
-            ).unwrap().as_bytes();
-        for i in 0..b.len() {
-            match put_byte(b[i]) {
-                Ok(()) => {
-                    continue;
-                }
-                Err(()) => {
-                    rprintln!("xmit full");
+        delay.delay_ms(10u16);
+        ms += 10;
+
+        while let Some(error) = errors.get() {
+            rprintln!("receive error: {}, {} in all", error.name(), ERROR_COUNTS.get(error));
+        }
+
+        // echo each frame once the line has gone quiet after it; its bytes
+        // are queued before its length
+        while let Some(len) = frames.get() {
+            let mut left = len;
+            while left > 0 {
+                let count = recv.pop_into(&mut buffer[..left.min(buffer.len())]);
+                if count == 0 {
                     break;
                 }
-            };
+                left -= count;
+                out.write(&buffer[..count]).ok();
+            }
+            rprintln!("frame of {} bytes", len);
+            uprintln!(out, " ({} bytes)", len).ok();
+        }
+        FLOW.drained(recv.len());
+
+        if ms < 1000 {
+            continue;
         }
-        // enable transmit interrupt, even if nothing added to queue becaus it's full
-        serial.enable_interrupt(TransmitDataRegisterEmtpy);
+        ms = 0;
+        rprintln!("Tick {}", tick);
+        uprintln!(out, "Tick {}", tick).ok();
         tick += 1;
     }
 }
 
 #[interrupt]
-fn USART1_EXTI25() {
-    let serial = unsafe { get_serial() };
-    // check for transmit ready for next byte
-    if serial.triggered_events().contains(TransmitDataRegisterEmtpy) {
-        let mut xmit = unsafe { XMIT_BUF.consumer() };
-        let r = xmit.read(1);
-        if r.len() > 0 {
-            match r.first() {
-                Some(byte) => {
-                    serial.write(*byte).unwrap();
-                }
-                None => {
-                    ();
-                }
-            };
+fn DMA1_CH4() {
+    if let Some(dma_tx) = unsafe { DMA_TX.as_mut() } {
+        dma_tx.on_interrupt();
+    }
+}
+
+// move received bytes on to the receive queue, the USART and DMA
+// interrupts share the default priority so they never run at once
+fn receive() {
+    let dma_rx = unsafe { DMA_RX.as_mut().unwrap() };
+    dma_rx.check_errors(unsafe { RX_ERRORS.as_mut().unwrap() });
+    let recv = unsafe { RECV.as_mut().unwrap() };
+    let frame_len = unsafe { &mut FRAME_LEN };
+    let idle = dma_rx.on_interrupt(|bytes| {
+        let queued = recv.push_slice(bytes);
+        *frame_len += queued;
+        if queued < bytes.len() {
+            rprintln!("recv full");
         }
-        drop(r);
-        // if consumer empty turn off transmit interrupt
-        if xmit.data_size() < 1 {
-            serial.disable_interrupt(TransmitDataRegisterEmtpy);
+    });
+    FLOW.received(recv.len());
+    // with no room for the length the frame runs on into the next one
+    if idle && *frame_len > 0 {
+        let frames = unsafe { FRAMES.as_mut().unwrap() };
+        if frames.put(*frame_len).is_ok() {
+            *frame_len = 0;
         }
     }
-    // check for input ready
-    if serial.triggered_events().contains(ReceiveDataRegisterNotEmpty) {
-        // read byte and add it to ring buffer
-        match serial.read() {
-            Ok(byte) => {
-                match put_byte(byte) {
-                    Ok(()) => {
-                        serial.enable_interrupt(TransmitDataRegisterEmtpy);
-                    }
-                    Err(()) => {
-                        rprintln!("xmit full");
-                    }
-                };
-            }
-            Err(_error) => {
-                ();
-            }
-        };
-    }
+}
+
+#[interrupt]
+fn DMA1_CH5() {
+    receive();
+}
+
+// idle line, receiver timeout and errors
+#[interrupt]
+fn USART1_EXTI25() {
+    receive();
 }
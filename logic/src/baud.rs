@@ -0,0 +1,81 @@
+//! Baud rate arithmetic for the USART
+//!
+//! The register side, `BaudRate`, is in the firmware's `baud` module, which
+//! re-exports this one.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BaudError {
+    /// The divider would be below 16 or above 0xffff for this clock
+    OutOfRange,
+    /// The detection character was too fast or too slow to time; detection
+    /// carries on with the next one
+    Detection,
+}
+
+/// A change of rate asked for from the console, carried out once the reply
+/// has gone
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BaudChange {
+    To(u32),
+    Detect,
+}
+
+/// BRR value for `rate` from a `clock_hz` USART clock, rounded to nearest,
+/// oversampling by 16
+pub fn divider(clock_hz: u32, rate: u32) -> Option<u16> {
+    if rate == 0 {
+        return None;
+    }
+    // in 64 bits so that the rounding can't overflow
+    let div = (clock_hz as u64 + rate as u64 / 2) / rate as u64;
+    if (16..=0xffff).contains(&div) { Some(div as u16) } else { None }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// As `Board::init` sets it up
+    const PCLK2_HZ: u32 = 48_000_000;
+
+    #[test]
+    fn common_rates() {
+        assert_eq!(divider(PCLK2_HZ, 115_200), Some(417));
+        assert_eq!(divider(PCLK2_HZ, 9600), Some(5000));
+        assert_eq!(divider(8_000_000, 115_200), Some(69));
+    }
+
+    #[test]
+    fn zero_rate() {
+        assert_eq!(divider(PCLK2_HZ, 0), None);
+    }
+
+    #[test]
+    fn range() {
+        // 16 is the smallest divider, 0xffff the largest
+        assert_eq!(divider(PCLK2_HZ, PCLK2_HZ / 16), Some(16));
+        assert_eq!(divider(PCLK2_HZ, PCLK2_HZ / 15), None);
+        assert_eq!(divider(PCLK2_HZ, PCLK2_HZ), None);
+        assert_eq!(divider(0xffff * 100, 100), Some(0xffff));
+        assert_eq!(divider(0x10000 * 100, 100), None);
+        assert_eq!(divider(PCLK2_HZ, 1), None);
+        // the slowest rate that fits
+        assert_eq!(divider(PCLK2_HZ, 732), None);
+        assert_eq!(divider(PCLK2_HZ, 733), Some(65484));
+    }
+
+    #[test]
+    fn rounds_to_nearest() {
+        // 48 MHz / 115200 is 416.67, 48 MHz / 230400 is 208.33
+        assert_eq!(divider(PCLK2_HZ, 230_400), Some(208));
+        // 48 MHz / 921600 is 52.08
+        assert_eq!(divider(PCLK2_HZ, 921_600), Some(52));
+        // halves round up
+        assert_eq!(divider(33, 2), Some(17));
+        // 100 / 6 is 16.67, 97 / 6 is 16.17
+        assert_eq!(divider(100, 6), Some(17));
+        assert_eq!(divider(97, 6), Some(16));
+        // no overflow near the top of the clock range
+        assert_eq!(divider(u32::MAX, u32::MAX / 16), Some(16));
+    }
+}
@@ -0,0 +1,212 @@
+//! Button debouncing with press, click, long-press and double-click events
+//!
+//! A short press is only reported as a `Click` once `DOUBLE_CLICK_MS` has
+//! passed without a second press, so that the first half of a double click
+//! never counts as a click of its own.
+//!
+//! `Debouncer` holds the timing logic and only sees pin levels and
+//! millisecond timestamps, so it can be driven from any pin or from a test.
+//! `stm32f3disc::button::Button` wraps it around the user button.
+
+use crate::ring_buffer::RingBuffer;
+
+/// A level must hold this long before it counts
+pub const DEBOUNCE_MS: u32 = 20;
+/// Held this long is a long press
+pub const LONG_PRESS_MS: u32 = 800;
+/// A press within this long of the last release is a double click
+pub const DOUBLE_CLICK_MS: u32 = 300;
+
+pub const QUEUE_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    /// Still held `LONG_PRESS_MS` after `Pressed`, sent once per press
+    LongPress,
+    /// Sent after the second `Pressed` of a double click
+    DoubleClick,
+    /// A press and release that was neither a long press nor part of a
+    /// double click, sent `DOUBLE_CLICK_MS` after its `Released`
+    Click,
+}
+
+pub struct Debouncer {
+    raw: bool,
+    raw_since: u32,
+    pressed: bool,
+    pressed_at: u32,
+    long_sent: bool,
+    double_sent: bool,
+    /// Release that is a `Click` unless another press follows in time
+    last_release: Option<u32>,
+}
+
+impl Debouncer {
+    pub const fn new() -> Debouncer {
+        Debouncer {
+            raw: false,
+            raw_since: 0,
+            pressed: false,
+            pressed_at: 0,
+            long_sent: false,
+            double_sent: false,
+            last_release: None,
+        }
+    }
+
+    /// Debounced state
+    pub fn is_pressed(&self) -> bool {
+        self.pressed
+    }
+
+    /// Feed the pin level (true while pressed) at `now_ms`, passing any
+    /// resulting events to `emit`
+    pub fn update(&mut self, level: bool, now_ms: u32,
+                  mut emit: impl FnMut(ButtonEvent)) {
+        if let Some(at) = self.last_release {
+            if now_ms.wrapping_sub(at) > DOUBLE_CLICK_MS {
+                emit(ButtonEvent::Click);
+                self.last_release = None;
+            }
+        }
+
+        if level != self.raw {
+            // still bouncing, restart the wait
+            self.raw = level;
+            self.raw_since = now_ms;
+            return;
+        }
+
+        if self.raw != self.pressed
+                && now_ms.wrapping_sub(self.raw_since) >= DEBOUNCE_MS {
+            self.pressed = self.raw;
+            if self.pressed {
+                emit(ButtonEvent::Pressed);
+                // still within the window, or it would have been a click
+                self.double_sent = self.last_release.take().is_some();
+                if self.double_sent {
+                    emit(ButtonEvent::DoubleClick);
+                }
+                self.pressed_at = now_ms;
+                self.long_sent = false;
+            } else {
+                emit(ButtonEvent::Released);
+                // the end of a long press or a double click is no click
+                let click = !self.long_sent && !self.double_sent;
+                self.last_release = if click { Some(now_ms) } else { None };
+            }
+        }
+
+        if self.pressed && !self.long_sent
+                && now_ms.wrapping_sub(self.pressed_at) >= LONG_PRESS_MS {
+            emit(ButtonEvent::LongPress);
+            self.long_sent = true;
+        }
+    }
+}
+
+impl Default for Debouncer {
+    fn default() -> Self {
+        Debouncer::new()
+    }
+}
+
+/// Queue of events not yet handled, the oldest is dropped when full
+pub type EventQueue = RingBuffer<ButtonEvent, QUEUE_LEN>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feeds `level` every millisecond from `from` up to but not including
+    /// `to`, collecting the events with their times
+    fn hold(debouncer: &mut Debouncer, level: bool, from: u32, to: u32,
+            events: &mut Vec<(u32, ButtonEvent)>) {
+        for now in from..to {
+            debouncer.update(level, now, |event| events.push((now, event)));
+        }
+    }
+
+    fn kinds(events: &[(u32, ButtonEvent)]) -> Vec<ButtonEvent> {
+        events.iter().map(|(_, event)| *event).collect()
+    }
+
+    #[test]
+    fn click_waits_for_the_double_click_window() {
+        use ButtonEvent::*;
+        let mut d = Debouncer::new();
+        let mut events = Vec::new();
+        hold(&mut d, true, 0, 100, &mut events);
+        hold(&mut d, false, 100, 1000, &mut events);
+        assert_eq!(kinds(&events), [Pressed, Released, Click]);
+        let released = events[1].0;
+        assert_eq!(events[0].0, DEBOUNCE_MS);
+        assert_eq!(released, 100 + DEBOUNCE_MS);
+        assert_eq!(events[2].0, released + DOUBLE_CLICK_MS + 1);
+    }
+
+    #[test]
+    fn double_click_sends_no_click() {
+        use ButtonEvent::*;
+        let mut d = Debouncer::new();
+        let mut events = Vec::new();
+        hold(&mut d, true, 0, 80, &mut events);
+        hold(&mut d, false, 80, 200, &mut events);
+        hold(&mut d, true, 200, 280, &mut events);
+        hold(&mut d, false, 280, 2000, &mut events);
+        assert_eq!(kinds(&events), [Pressed, Released, Pressed, DoubleClick, Released]);
+    }
+
+    #[test]
+    fn second_press_after_the_window_is_two_clicks() {
+        use ButtonEvent::*;
+        let mut d = Debouncer::new();
+        let mut events = Vec::new();
+        hold(&mut d, true, 0, 80, &mut events);
+        hold(&mut d, false, 80, 500, &mut events);
+        hold(&mut d, true, 500, 580, &mut events);
+        hold(&mut d, false, 580, 1500, &mut events);
+        assert_eq!(kinds(&events), [Pressed, Released, Click, Pressed, Released, Click]);
+    }
+
+    #[test]
+    fn long_press_sends_no_click() {
+        use ButtonEvent::*;
+        let mut d = Debouncer::new();
+        let mut events = Vec::new();
+        hold(&mut d, true, 0, 2000, &mut events);
+        hold(&mut d, false, 2000, 3000, &mut events);
+        assert_eq!(kinds(&events), [Pressed, LongPress, Released]);
+        assert_eq!(events[1].0, DEBOUNCE_MS + LONG_PRESS_MS);
+    }
+
+    #[test]
+    fn bounces_are_ignored() {
+        use ButtonEvent::*;
+        let mut d = Debouncer::new();
+        let mut events = Vec::new();
+        for now in 0..50 {
+            d.update(now % 3 == 0, now, |event| events.push((now, event)));
+        }
+        assert!(events.is_empty());
+        assert!(!d.is_pressed());
+        hold(&mut d, true, 50, 100, &mut events);
+        assert!(d.is_pressed());
+        assert_eq!(kinds(&events), [Pressed]);
+    }
+
+    #[test]
+    fn timestamps_wrap() {
+        use ButtonEvent::*;
+        let mut d = Debouncer::new();
+        let mut events = Vec::new();
+        let start = u32::MAX - 50;
+        for step in 0..1000u32 {
+            let now = start.wrapping_add(step);
+            d.update(step < 100, now, |event| events.push((now, event)));
+        }
+        assert_eq!(kinds(&events), [Pressed, Released, Click]);
+    }
+}
@@ -0,0 +1,336 @@
+//! Gyro bias and magnetometer hard/soft-iron calibration
+//!
+//! `Calibrator` walks through a guided calibration: hold the board still
+//! while the gyro bias is averaged, then turn it slowly through every
+//! orientation while the magnetometer's extremes on each axis are recorded.
+//! The hard-iron offset is the centre of those extremes and the soft-iron
+//! correction a diagonal scale bringing each axis to the mean radius.
+//!
+//! `to_bytes` gives the record `stm32f3disc::calibration::save` keeps in
+//! flash, with a magic number, version and CRC-32 so that `from_bytes`
+//! ignores a blank or stale page.
+
+use crate::crc::crc32;
+
+/// How long to average the gyro with the board still
+pub const GYRO_STILL_MS: u32 = 2_000;
+/// How long to collect magnetometer extremes while it is turned around
+pub const MAG_ROTATE_MS: u32 = 20_000;
+
+const MAGIC: u32 = 0x4341_4c31; // "CAL1"
+const VERSION: u16 = 1;
+const FLOATS: usize = 3 + 3 + 9;
+/// Bytes in the stored record: header, floats, CRC
+pub const RECORD_LEN: usize = 8 + FLOATS * 4 + 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calibration {
+    /// Subtracted from the gyro, dps
+    pub gyro_bias: [f32; 3],
+    /// Hard-iron offset subtracted from the field, same unit as the samples
+    pub mag_offset: [f32; 3],
+    /// Soft-iron correction applied after the offset, row major
+    pub mag_scale: [[f32; 3]; 3],
+}
+
+impl Calibration {
+    /// No correction
+    pub const IDENTITY: Calibration = Calibration {
+        gyro_bias: [0.0; 3],
+        mag_offset: [0.0; 3],
+        mag_scale: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    };
+
+    pub fn apply_gyro(&self, gyro: [f32; 3]) -> [f32; 3] {
+        [
+            gyro[0] - self.gyro_bias[0],
+            gyro[1] - self.gyro_bias[1],
+            gyro[2] - self.gyro_bias[2],
+        ]
+    }
+
+    pub fn apply_mag(&self, mag: [f32; 3]) -> [f32; 3] {
+        let m = [
+            mag[0] - self.mag_offset[0],
+            mag[1] - self.mag_offset[1],
+            mag[2] - self.mag_offset[2],
+        ];
+        let mut out = [0.0; 3];
+        for (o, row) in out.iter_mut().zip(self.mag_scale.iter()) {
+            *o = row[0] * m[0] + row[1] * m[1] + row[2] * m[2];
+        }
+        out
+    }
+
+    pub fn to_bytes(&self) -> [u8; RECORD_LEN] {
+        let mut buf = [0; RECORD_LEN];
+        buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&VERSION.to_le_bytes());
+        let floats = self.gyro_bias.iter()
+            .chain(self.mag_offset.iter())
+            .chain(self.mag_scale.iter().flatten());
+        for (i, f) in floats.enumerate() {
+            buf[8 + i * 4..12 + i * 4].copy_from_slice(&f.to_le_bytes());
+        }
+        let crc = crc32(&buf[..RECORD_LEN - 4]);
+        buf[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// `None` unless `bytes` starts with a record of this version and its
+    /// CRC checks out
+    pub fn from_bytes(bytes: &[u8]) -> Option<Calibration> {
+        if bytes.len() < RECORD_LEN {
+            return None;
+        }
+        let bytes = &bytes[..RECORD_LEN];
+        let word = |at: usize| [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+        if u32::from_le_bytes(word(0)) != MAGIC
+                || u16::from_le_bytes([bytes[4], bytes[5]]) != VERSION
+                || u32::from_le_bytes(word(RECORD_LEN - 4)) != crc32(&bytes[..RECORD_LEN - 4]) {
+            return None;
+        }
+        let mut floats = [0.0; FLOATS];
+        for (i, f) in floats.iter_mut().enumerate() {
+            *f = f32::from_le_bytes(word(8 + i * 4));
+        }
+        let mut cal = Calibration::IDENTITY;
+        cal.gyro_bias.copy_from_slice(&floats[0..3]);
+        cal.mag_offset.copy_from_slice(&floats[3..6]);
+        for (row, chunk) in cal.mag_scale.iter_mut().zip(floats[6..].chunks(3)) {
+            row.copy_from_slice(chunk);
+        }
+        Some(cal)
+    }
+}
+
+/// Mean of the gyro samples taken with the board still
+pub struct GyroBiasCollector {
+    sum: [f32; 3],
+    count: u32,
+}
+
+impl GyroBiasCollector {
+    pub const fn new() -> GyroBiasCollector {
+        GyroBiasCollector { sum: [0.0; 3], count: 0 }
+    }
+
+    pub fn add(&mut self, gyro: [f32; 3]) {
+        for (s, g) in self.sum.iter_mut().zip(gyro.iter()) {
+            *s += g;
+        }
+        self.count += 1;
+    }
+
+    pub fn bias(&self) -> Option<[f32; 3]> {
+        if self.count == 0 {
+            return None;
+        }
+        let n = self.count as f32;
+        Some([self.sum[0] / n, self.sum[1] / n, self.sum[2] / n])
+    }
+}
+
+impl Default for GyroBiasCollector {
+    fn default() -> Self {
+        GyroBiasCollector::new()
+    }
+}
+
+/// Extremes of the field on each axis while the board is turned around
+pub struct MagCollector {
+    min: [f32; 3],
+    max: [f32; 3],
+    count: u32,
+}
+
+impl MagCollector {
+    pub const fn new() -> MagCollector {
+        MagCollector { min: [f32::MAX; 3], max: [f32::MIN; 3], count: 0 }
+    }
+
+    pub fn add(&mut self, mag: [f32; 3]) {
+        for ((min, max), m) in self.min.iter_mut().zip(self.max.iter_mut()).zip(mag.iter()) {
+            *min = min.min(*m);
+            *max = max.max(*m);
+        }
+        self.count += 1;
+    }
+
+    /// Hard-iron offset and soft-iron scale, `None` until every axis has
+    /// seen some spread
+    pub fn result(&self) -> Option<([f32; 3], [[f32; 3]; 3])> {
+        let mut offset = [0.0; 3];
+        let mut radius = [0.0; 3];
+        for i in 0..3 {
+            radius[i] = (self.max[i] - self.min[i]) / 2.0;
+            if self.count == 0 || radius[i] <= 0.0 {
+                return None;
+            }
+            offset[i] = (self.max[i] + self.min[i]) / 2.0;
+        }
+        let mean = (radius[0] + radius[1] + radius[2]) / 3.0;
+        let mut scale = Calibration::IDENTITY.mag_scale;
+        for i in 0..3 {
+            scale[i][i] = mean / radius[i];
+        }
+        Some((offset, scale))
+    }
+}
+
+impl Default for MagCollector {
+    fn default() -> Self {
+        MagCollector::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase {
+    /// Keep the board still
+    GyroStill,
+    /// Turn the board slowly through every orientation
+    MagRotate,
+    /// Finished, with the new calibration
+    Done(Calibration),
+    /// The magnetometer didn't move on some axis
+    Failed,
+}
+
+/// Guided calibration, fed samples with a millisecond timestamp
+pub struct Calibrator {
+    phase: Phase,
+    phase_start: u32,
+    gyro: GyroBiasCollector,
+    mag: MagCollector,
+}
+
+impl Calibrator {
+    pub fn new(now_ms: u32) -> Calibrator {
+        Calibrator {
+            phase: Phase::GyroStill,
+            phase_start: now_ms,
+            gyro: GyroBiasCollector::new(),
+            mag: MagCollector::new(),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// How far through the current phase, 0 to 255
+    pub fn progress(&self, now_ms: u32) -> u8 {
+        let length = match self.phase {
+            Phase::GyroStill => GYRO_STILL_MS,
+            Phase::MagRotate => MAG_ROTATE_MS,
+            Phase::Done(_) | Phase::Failed => return 255,
+        };
+        let elapsed = now_ms.wrapping_sub(self.phase_start).min(length);
+        (elapsed * 255 / length) as u8
+    }
+
+    /// Feed uncorrected gyro (dps) and magnetometer samples
+    pub fn update(&mut self, now_ms: u32, gyro: [f32; 3], mag: [f32; 3]) -> Phase {
+        let elapsed = now_ms.wrapping_sub(self.phase_start);
+        match self.phase {
+            Phase::GyroStill => {
+                self.gyro.add(gyro);
+                if elapsed >= GYRO_STILL_MS {
+                    self.phase = Phase::MagRotate;
+                    self.phase_start = now_ms;
+                }
+            }
+            Phase::MagRotate => {
+                self.mag.add(mag);
+                if elapsed >= MAG_ROTATE_MS {
+                    self.phase = match (self.gyro.bias(), self.mag.result()) {
+                        (Some(gyro_bias), Some((mag_offset, mag_scale))) => {
+                            Phase::Done(Calibration { gyro_bias, mag_offset, mag_scale })
+                        }
+                        _ => Phase::Failed,
+                    };
+                }
+            }
+            Phase::Done(_) | Phase::Failed => (),
+        }
+        self.phase
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> Calibration {
+        Calibration {
+            gyro_bias: [0.75, -1.25, 0.003],
+            mag_offset: [-0.12, 0.07, 0.31],
+            mag_scale: [[1.02, 0.01, -0.02], [0.0, 0.97, 0.0], [-0.005, 0.0, 1.01]],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = calibration().to_bytes();
+        assert_eq!(bytes.len(), RECORD_LEN);
+        assert_eq!(Calibration::from_bytes(&bytes), Some(calibration()));
+        let identity = Calibration::IDENTITY.to_bytes();
+        assert_eq!(Calibration::from_bytes(&identity), Some(Calibration::IDENTITY));
+    }
+
+    #[test]
+    fn record_layout() {
+        let bytes = calibration().to_bytes();
+        assert_eq!(&bytes[0..4], b"1LAC");
+        assert_eq!(&bytes[4..8], [1, 0, 0, 0]);
+        assert_eq!(&bytes[8..12], 0.75f32.to_le_bytes());
+        let crc = crc32(&bytes[..RECORD_LEN - 4]);
+        assert_eq!(&bytes[RECORD_LEN - 4..], crc.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_a_record_at_the_start_of_a_page() {
+        let mut page = [0xff; 256];
+        page[..RECORD_LEN].copy_from_slice(&calibration().to_bytes());
+        assert_eq!(Calibration::from_bytes(&page), Some(calibration()));
+    }
+
+    #[test]
+    fn rejects_any_flipped_bit() {
+        let bytes = calibration().to_bytes();
+        for i in 0..RECORD_LEN {
+            for bit in 0..8 {
+                let mut corrupt = bytes;
+                corrupt[i] ^= 1 << bit;
+                assert_eq!(Calibration::from_bytes(&corrupt), None, "byte {} bit {}", i, bit);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_blank_short_and_stale_records() {
+        assert_eq!(Calibration::from_bytes(&[0xff; RECORD_LEN]), None);
+        assert_eq!(Calibration::from_bytes(&[0; RECORD_LEN]), None);
+        let bytes = calibration().to_bytes();
+        assert_eq!(Calibration::from_bytes(&bytes[..RECORD_LEN - 1]), None);
+        assert_eq!(Calibration::from_bytes(&[]), None);
+        // another version, with a CRC that matches it
+        let mut stale = bytes;
+        stale[4] = 2;
+        let crc = crc32(&stale[..RECORD_LEN - 4]);
+        stale[RECORD_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(Calibration::from_bytes(&stale), None);
+    }
+
+    #[test]
+    fn applies_the_correction() {
+        let cal = Calibration {
+            gyro_bias: [1.0, -2.0, 0.5],
+            mag_offset: [0.1, 0.2, -0.3],
+            mag_scale: [[2.0, 0.0, 0.0], [0.0, 0.5, 0.0], [0.0, 0.0, 1.0]],
+        };
+        assert_eq!(cal.apply_gyro([1.0, 0.0, 0.5]), [0.0, 2.0, 0.0]);
+        assert_eq!(cal.apply_mag([0.6, 0.2, 0.2]), [1.0, 0.0, 0.5]);
+        assert_eq!(Calibration::IDENTITY.apply_mag([0.1, 0.2, 0.3]), [0.1, 0.2, 0.3]);
+    }
+}
@@ -0,0 +1,274 @@
+//! Flow control decisions for a serial receive queue
+//!
+//! `FlowState` keeps the mode and who has stopped whom, and says what
+//! should happen as the queue fills and drains and as XON and XOFF arrive.
+//! It doesn't touch the pins or the USART itself; the firmware's `flow`
+//! module wraps it in `FlowControl`, which drives RTS and wakes the
+//! transmitter as told, and re-exports this module.
+
+pub const XON: u8 = 0x11;
+pub const XOFF: u8 = 0x13;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowMode {
+    None,
+    /// RTS and CTS on PA12 and PA11
+    Hardware,
+    /// XON and XOFF in band
+    XonXoff,
+}
+
+impl FlowMode {
+    pub const ALL: [FlowMode; 3] = [FlowMode::None, FlowMode::Hardware, FlowMode::XonXoff];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FlowMode::None => "none",
+            FlowMode::Hardware => "rtscts",
+            FlowMode::XonXoff => "xonxoff",
+        }
+    }
+}
+
+/// What the caller has to do after a change
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    None,
+    /// Set RTS, `true` asking the far end to stop
+    Rts(bool),
+    /// Wake the transmitter, for a control byte or because it may send
+    /// again
+    Kick,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlowState {
+    low: usize,
+    high: usize,
+    mode: FlowMode,
+    /// We have asked the far end to stop
+    throttled: bool,
+    /// XON or XOFF to send before any data
+    control: Option<u8>,
+    /// The far end has sent XOFF
+    stopped: bool,
+}
+
+impl FlowState {
+    /// Stop the far end at `high` bytes queued, let it go on at `low`.
+    /// Starts in `FlowMode::None`
+    pub const fn new(low: usize, high: usize) -> FlowState {
+        FlowState {
+            low,
+            high,
+            mode: FlowMode::None,
+            throttled: false,
+            control: None,
+            stopped: false,
+        }
+    }
+
+    pub fn mode(&self) -> FlowMode {
+        self.mode
+    }
+
+    /// Switch mode, forgetting who had stopped whom. If the far end had
+    /// been sent XOFF, XON goes out next so it doesn't wait for ever; RTS
+    /// is for the caller to lower
+    pub fn set_mode(&mut self, mode: FlowMode) {
+        let resume = self.mode == FlowMode::XonXoff && self.throttled;
+        *self = FlowState { mode, ..FlowState::new(self.low, self.high) };
+        if resume {
+            self.control = Some(XON);
+        }
+    }
+
+    /// We have asked the far end to stop
+    pub fn is_throttled(&self) -> bool {
+        self.throttled
+    }
+
+    /// The far end has asked us to stop
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    /// `queued` bytes after adding to the queue
+    pub fn received(&mut self, queued: usize) -> Signal {
+        if queued >= self.high { self.throttle(true) } else { Signal::None }
+    }
+
+    /// `queued` bytes after taking from the queue
+    pub fn drained(&mut self, queued: usize) -> Signal {
+        if queued <= self.low { self.throttle(false) } else { Signal::None }
+    }
+
+    fn throttle(&mut self, stop: bool) -> Signal {
+        if self.throttled == stop {
+            return Signal::None;
+        }
+        self.throttled = stop;
+        match self.mode {
+            FlowMode::None => Signal::None,
+            FlowMode::Hardware => Signal::Rts(stop),
+            FlowMode::XonXoff => {
+                self.control = Some(if stop { XOFF } else { XON });
+                Signal::Kick
+            }
+        }
+    }
+
+    /// Received `byte`, unless it was XON or XOFF for us
+    pub fn filter(&mut self, byte: u8) -> (Option<u8>, Signal) {
+        if self.mode != FlowMode::XonXoff {
+            return (Some(byte), Signal::None);
+        }
+        match byte {
+            XOFF => {
+                self.stopped = true;
+                (None, Signal::None)
+            }
+            XON => {
+                let kick = if self.stopped { Signal::Kick } else { Signal::None };
+                self.stopped = false;
+                (None, kick)
+            }
+            _ => (Some(byte), Signal::None),
+        }
+    }
+
+    /// XON or XOFF to send now, ahead of any data
+    pub fn next_control(&mut self) -> Option<u8> {
+        self.control.take()
+    }
+
+    /// Data may be sent; in hardware mode the USART itself waits for CTS
+    pub fn may_send(&self) -> bool {
+        !self.stopped
+    }
+
+    /// Whether the transmitter has anything to do, with `data_queued` true
+    /// if there is output waiting
+    pub fn wants_transmit(&self, data_queued: bool) -> bool {
+        self.control.is_some() || (data_queued && !self.stopped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(mode: FlowMode) -> FlowState {
+        let mut state = FlowState::new(16, 48);
+        state.set_mode(mode);
+        state
+    }
+
+    #[test]
+    fn watermarks() {
+        let mut s = state(FlowMode::Hardware);
+        assert_eq!(s.received(47), Signal::None);
+        assert!(!s.is_throttled());
+        assert_eq!(s.received(48), Signal::Rts(true));
+        assert!(s.is_throttled());
+        // only once, however much more arrives
+        assert_eq!(s.received(60), Signal::None);
+        // nothing between the watermarks
+        assert_eq!(s.drained(17), Signal::None);
+        assert_eq!(s.received(40), Signal::None);
+        assert!(s.is_throttled());
+        assert_eq!(s.drained(16), Signal::Rts(false));
+        assert!(!s.is_throttled());
+        assert_eq!(s.drained(0), Signal::None);
+        assert_eq!(s.received(20), Signal::None);
+        // hardware mode never holds up our own sending
+        assert!(s.may_send());
+        assert_eq!(s.next_control(), None);
+    }
+
+    #[test]
+    fn no_flow_control() {
+        let mut s = state(FlowMode::None);
+        assert_eq!(s.received(64), Signal::None);
+        // tracked all the same, though nothing is sent for it
+        assert!(s.is_throttled());
+        assert_eq!(s.filter(XOFF), (Some(XOFF), Signal::None));
+        assert!(s.may_send());
+        assert_eq!(s.next_control(), None);
+        assert_eq!(s.drained(0), Signal::None);
+
+        // a mode switch forgets it, the far end was never told to stop;
+        // the next high watermark in the new mode does that
+        s.received(64);
+        s.set_mode(FlowMode::Hardware);
+        assert!(!s.is_throttled());
+        assert_eq!(s.next_control(), None);
+        assert_eq!(s.received(64), Signal::Rts(true));
+    }
+
+    #[test]
+    fn sends_xoff_and_xon() {
+        let mut s = state(FlowMode::XonXoff);
+        assert!(!s.wants_transmit(false));
+        assert_eq!(s.received(48), Signal::Kick);
+        assert!(s.wants_transmit(false));
+        assert_eq!(s.next_control(), Some(XOFF));
+        assert_eq!(s.next_control(), None);
+        assert!(!s.wants_transmit(false));
+        assert_eq!(s.received(49), Signal::None);
+        assert_eq!(s.next_control(), None);
+        assert_eq!(s.drained(10), Signal::Kick);
+        assert_eq!(s.next_control(), Some(XON));
+        // only the latest goes out if the transmitter hadn't got to it
+        s.received(48);
+        s.drained(0);
+        assert_eq!(s.next_control(), Some(XON));
+        assert_eq!(s.next_control(), None);
+    }
+
+    #[test]
+    fn obeys_xoff_and_xon() {
+        let mut s = state(FlowMode::XonXoff);
+        assert_eq!(s.filter(b'a'), (Some(b'a'), Signal::None));
+        assert!(s.wants_transmit(true));
+        assert_eq!(s.filter(XOFF), (None, Signal::None));
+        assert!(s.is_stopped());
+        assert!(!s.may_send());
+        assert!(!s.wants_transmit(true));
+        // other bytes still come through while stopped
+        assert_eq!(s.filter(b'b'), (Some(b'b'), Signal::None));
+        // XOFF while throttled still goes out, control bytes aren't held
+        s.received(48);
+        assert!(s.wants_transmit(false));
+        assert_eq!(s.next_control(), Some(XOFF));
+        assert_eq!(s.filter(XON), (None, Signal::Kick));
+        assert!(s.may_send());
+        assert!(s.wants_transmit(true));
+        // a second XON has nothing to wake
+        assert_eq!(s.filter(XON), (None, Signal::None));
+    }
+
+    #[test]
+    fn changing_mode() {
+        // a far end told to stop with XOFF is let go when the mode changes
+        let mut s = state(FlowMode::XonXoff);
+        s.received(48);
+        assert_eq!(s.next_control(), Some(XOFF));
+        s.filter(XOFF);
+        s.set_mode(FlowMode::None);
+        assert_eq!(s.mode(), FlowMode::None);
+        assert!(!s.is_throttled());
+        assert!(!s.is_stopped());
+        assert_eq!(s.next_control(), Some(XON));
+
+        // from hardware mode it's up to the caller to lower RTS
+        let mut s = state(FlowMode::Hardware);
+        s.received(48);
+        s.set_mode(FlowMode::XonXoff);
+        assert!(!s.is_throttled());
+        assert_eq!(s.next_control(), None);
+        // and the watermarks carry on as before
+        assert_eq!(s.received(48), Signal::Kick);
+        assert_eq!(s.next_control(), Some(XOFF));
+    }
+}
@@ -0,0 +1,401 @@
+//! Packets over the serial link
+//!
+//! Each packet goes out as its payload followed by a big-endian CRC-16
+//! (`crc::crc16`), COBS encoded so that it contains no zero bytes, and
+//! ended by a single zero. The receiver can always find the start of the
+//! next packet, whatever was lost or garbled before it.
+//!
+//! `FrameEncoder` builds a frame to queue with `SerialWriter::write`;
+//! under `Backpressure::AllOrNothing` it goes into the transmit queue
+//! whole or not at all. `FrameDecoder` takes bytes from the receive queue
+//! one at a time and hands back each payload that arrives intact, counting
+//! the frames it has to throw away in `FrameStats`.
+//!
+//! Nothing here touches the hardware.
+
+use crate::crc::crc16;
+
+/// Bytes added to the payload: the CRC
+pub const TRAILER_LEN: usize = 2;
+
+/// Longest frame, delimiter included, for a payload of `len` bytes
+pub const fn encoded_len(len: usize) -> usize {
+    let data = len + TRAILER_LEN;
+    // a code byte for every 254 data bytes or part of them, then the zero
+    data + data / 254 + 1 + 1
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameError {
+    /// Payload too big for the encoder, or frame too big for the decoder
+    TooLong,
+    /// Not valid COBS, or too short to hold a CRC
+    Malformed,
+    /// CRC didn't match, the frame was corrupted
+    Crc,
+}
+
+/// Frames seen by a `FrameDecoder`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// Delivered intact
+    pub frames: u32,
+    pub too_long: u32,
+    pub malformed: u32,
+    pub crc: u32,
+}
+
+impl FrameStats {
+    /// Frames thrown away
+    pub fn errors(&self) -> u32 {
+        self.too_long.wrapping_add(self.malformed).wrapping_add(self.crc)
+    }
+
+    fn count(&mut self, error: FrameError) {
+        let count = match error {
+            FrameError::TooLong => &mut self.too_long,
+            FrameError::Malformed => &mut self.malformed,
+            FrameError::Crc => &mut self.crc,
+        };
+        *count = count.wrapping_add(1);
+    }
+}
+
+/// Builds frames of up to `N` bytes, delimiter included
+pub struct FrameEncoder<const N: usize> {
+    buf: [u8; N],
+}
+
+impl<const N: usize> FrameEncoder<N> {
+    pub const fn new() -> Self {
+        FrameEncoder { buf: [0; N] }
+    }
+
+    /// The frame for `payload`, ready to send
+    pub fn encode(&mut self, payload: &[u8]) -> Result<&[u8], FrameError> {
+        if encoded_len(payload.len()) > N {
+            return Err(FrameError::TooLong);
+        }
+        let crc = crc16(payload).to_be_bytes();
+        let out = &mut self.buf;
+        // `code` counts the block's data bytes plus one, and goes in at
+        // `code_at` once the block ends
+        let mut code_at = 0;
+        let mut code = 1u8;
+        let mut len = 1;
+        for byte in payload.iter().chain(crc.iter()) {
+            if *byte == 0 {
+                out[code_at] = code;
+                code_at = len;
+                len += 1;
+                code = 1;
+            } else {
+                out[len] = *byte;
+                len += 1;
+                code += 1;
+                if code == 0xff {
+                    out[code_at] = code;
+                    code_at = len;
+                    len += 1;
+                    code = 1;
+                }
+            }
+        }
+        out[code_at] = code;
+        out[len] = 0;
+        Ok(&out[..len + 1])
+    }
+}
+
+impl<const N: usize> Default for FrameEncoder<N> {
+    fn default() -> Self {
+        FrameEncoder::new()
+    }
+}
+
+/// Collects frames of up to `N` encoded bytes, delimiter not included
+pub struct FrameDecoder<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// The frame so far didn't fit, drop it at the delimiter
+    overflow: bool,
+    stats: FrameStats,
+}
+
+impl<const N: usize> FrameDecoder<N> {
+    pub const fn new() -> Self {
+        FrameDecoder {
+            buf: [0; N],
+            len: 0,
+            overflow: false,
+            stats: FrameStats { frames: 0, too_long: 0, malformed: 0, crc: 0 },
+        }
+    }
+
+    pub fn stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.stats = FrameStats::default();
+    }
+
+    /// Take one received byte. At the end of a frame, its payload or why
+    /// it was dropped; empty frames, as from back to back delimiters, are
+    /// skipped
+    pub fn feed(&mut self, byte: u8) -> Option<Result<&[u8], FrameError>> {
+        if byte != 0 {
+            if self.len < N {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflow = true;
+            }
+            return None;
+        }
+        let len = self.len;
+        let overflow = self.overflow;
+        self.len = 0;
+        self.overflow = false;
+        if len == 0 && !overflow {
+            return None;
+        }
+        let res = if overflow {
+            Err(FrameError::TooLong)
+        } else {
+            self.check(len)
+        };
+        match res {
+            Ok(payload_len) => {
+                self.stats.frames = self.stats.frames.wrapping_add(1);
+                Some(Ok(&self.buf[..payload_len]))
+            }
+            Err(error) => {
+                self.stats.count(error);
+                Some(Err(error))
+            }
+        }
+    }
+
+    /// Decode the first `len` bytes in place and check the CRC, giving the
+    /// payload length
+    fn check(&mut self, len: usize) -> Result<usize, FrameError> {
+        let decoded = cobs_decode_in_place(&mut self.buf[..len])?;
+        if decoded < TRAILER_LEN {
+            return Err(FrameError::Malformed);
+        }
+        let payload_len = decoded - TRAILER_LEN;
+        let trailer = [self.buf[payload_len], self.buf[payload_len + 1]];
+        if crc16(&self.buf[..payload_len]) != u16::from_be_bytes(trailer) {
+            return Err(FrameError::Crc);
+        }
+        Ok(payload_len)
+    }
+}
+
+impl<const N: usize> Default for FrameDecoder<N> {
+    fn default() -> Self {
+        FrameDecoder::new()
+    }
+}
+
+/// Undo COBS on a frame without its delimiter, returning the decoded
+/// length; the output never gets ahead of the input, so one buffer does
+fn cobs_decode_in_place(buf: &mut [u8]) -> Result<usize, FrameError> {
+    let len = buf.len();
+    let mut read = 0;
+    let mut write = 0;
+    while read < len {
+        let code = buf[read] as usize;
+        read += 1;
+        if code == 0 || read + code - 1 > len {
+            return Err(FrameError::Malformed);
+        }
+        buf.copy_within(read..read + code - 1, write);
+        read += code - 1;
+        write += code - 1;
+        // every block but a full one and the last stood for a zero
+        if code != 0xff && read < len {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+    Ok(write)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    /// Feed a whole stream, collecting what comes out
+    fn decode_all<const N: usize>(decoder: &mut FrameDecoder<N>, bytes: &[u8]) -> Vec<Result<Vec<u8>, FrameError>> {
+        let mut results = Vec::new();
+        for byte in bytes {
+            if let Some(res) = decoder.feed(*byte) {
+                results.push(res.map(|payload| payload.to_vec()));
+            }
+        }
+        results
+    }
+
+    /// A payload of `len` non-zero bytes whose CRC has no zero byte either,
+    /// so that the frame has no blocks but the full ones
+    fn zero_free(len: usize) -> Vec<u8> {
+        (1..=255u8)
+            .map(|fill| vec![fill; len])
+            .find(|payload| !crc16(payload).to_be_bytes().contains(&0))
+            .unwrap()
+    }
+
+    #[test]
+    fn small_frames() {
+        let mut encoder: FrameEncoder<16> = FrameEncoder::new();
+        // the CRC of nothing is 0xffff
+        assert_eq!(encoder.encode(&[]).unwrap(), [3, 0xff, 0xff, 0]);
+        // "123456789" has CRC 0x29b1
+        let mut expected = vec![12];
+        expected.extend_from_slice(b"123456789\x29\xb1\x00");
+        assert_eq!(encoder.encode(b"123456789").unwrap(), &expected[..]);
+        // zeros become the codes
+        let crc = crc16(&[0, 0x11, 0]).to_be_bytes();
+        assert_eq!(encoder.encode(&[0, 0x11, 0]).unwrap(), [1, 2, 0x11, 3, crc[0], crc[1], 0]);
+    }
+
+    #[test]
+    fn full_block_boundary() {
+        let mut encoder: FrameEncoder<300> = FrameEncoder::new();
+        let mut decoder: FrameDecoder<300> = FrameDecoder::new();
+        // 252 bytes and the CRC fill one block exactly, and the next block
+        // is empty: a trailing 0x01 code and no zero added
+        let payload = zero_free(252);
+        let frame = encoder.encode(&payload).unwrap().to_vec();
+        assert_eq!(frame.len(), 257);
+        assert_eq!(frame.len(), encoded_len(252));
+        assert_eq!(frame[0], 0xff);
+        assert_eq!(&frame[1..253], &payload[..]);
+        assert_eq!(frame[255..], [0x01, 0x00]);
+        assert_eq!(decode_all(&mut decoder, &frame), [Ok(payload)]);
+
+        // one byte less: a single block
+        let payload = zero_free(251);
+        let frame = encoder.encode(&payload).unwrap().to_vec();
+        assert_eq!(frame.len(), 255);
+        assert_eq!(frame[0], 0xfe);
+        assert_eq!(frame[254], 0);
+        assert_eq!(decode_all(&mut decoder, &frame), [Ok(payload)]);
+
+        // one more: a full block, then a block of one
+        let payload = zero_free(253);
+        let frame = encoder.encode(&payload).unwrap().to_vec();
+        assert_eq!(frame.len(), 258);
+        assert_eq!(frame[0], 0xff);
+        assert_eq!(frame[255], 0x02);
+        assert_eq!(decode_all(&mut decoder, &frame), [Ok(payload)]);
+
+        // a zero right after a full block is a block of its own
+        let mut payload = zero_free(255);
+        payload[254] = 0;
+        let frame = encoder.encode(&payload).unwrap().to_vec();
+        assert_eq!(frame[0], 0xff);
+        assert_eq!(frame[255], 0x01);
+        assert_eq!(decode_all(&mut decoder, &frame), [Ok(payload)]);
+        assert_eq!(decoder.stats().frames, 4);
+        assert_eq!(decoder.stats().errors(), 0);
+    }
+
+    #[test]
+    fn encoder_too_long() {
+        let mut encoder: FrameEncoder<10> = FrameEncoder::new();
+        // 6 bytes, CRC, code and delimiter take all 10
+        assert_eq!(encoded_len(6), 10);
+        assert!(encoder.encode(&[1; 6]).is_ok());
+        assert_eq!(encoder.encode(&[1; 7]), Err(FrameError::TooLong));
+    }
+
+    #[test]
+    fn decoder_too_long() {
+        let mut encoder: FrameEncoder<64> = FrameEncoder::new();
+        let mut decoder: FrameDecoder<10> = FrameDecoder::new();
+        // 10 encoded bytes fit, not counting the delimiter
+        let fits = encoder.encode(&[7; 7]).unwrap().to_vec();
+        assert_eq!(fits.len(), 11);
+        assert_eq!(decode_all(&mut decoder, &fits), [Ok(vec![7; 7])]);
+        let too_long = encoder.encode(&[7; 8]).unwrap().to_vec();
+        assert_eq!(decode_all(&mut decoder, &too_long), [Err(FrameError::TooLong)]);
+        // it carries on with the next frame
+        assert_eq!(decode_all(&mut decoder, &fits), [Ok(vec![7; 7])]);
+        assert_eq!(decoder.stats(), FrameStats { frames: 2, too_long: 1, malformed: 0, crc: 0 });
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut encoder: FrameEncoder<32> = FrameEncoder::new();
+        let mut decoder: FrameDecoder<32> = FrameDecoder::new();
+        let frame = encoder.encode(b"hello").unwrap().to_vec();
+        // change any byte of the payload or the CRC, short of making a zero
+        // or touching the code
+        for i in 1..frame.len() - 1 {
+            let mut bad = frame.clone();
+            bad[i] ^= if bad[i] == 0x20 { 0x40 } else { 0x20 };
+            assert_eq!(decode_all(&mut decoder, &bad), [Err(FrameError::Crc)], "{}", i);
+        }
+        let crc = frame.len() as u32 - 2;
+        assert_eq!(decoder.stats(), FrameStats { frames: 0, too_long: 0, malformed: 0, crc });
+        assert_eq!(decode_all(&mut decoder, &frame), [Ok(b"hello".to_vec())]);
+    }
+
+    #[test]
+    fn malformed() {
+        let mut decoder: FrameDecoder<32> = FrameDecoder::new();
+        // a code running past the end of the frame
+        assert_eq!(decode_all(&mut decoder, &[5, 1, 2, 0]), [Err(FrameError::Malformed)]);
+        // too short for a CRC
+        assert_eq!(decode_all(&mut decoder, &[2, 9, 0]), [Err(FrameError::Malformed)]);
+        assert_eq!(decode_all(&mut decoder, &[1, 0]), [Err(FrameError::Malformed)]);
+        assert_eq!(decoder.stats().malformed, 3);
+    }
+
+    #[test]
+    fn skips_empty_frames_and_resynchronises() {
+        let mut encoder: FrameEncoder<32> = FrameEncoder::new();
+        let mut decoder: FrameDecoder<32> = FrameDecoder::new();
+        let mut stream = vec![0, 0, 0x41, 0x42];
+        stream.push(0);
+        stream.extend_from_slice(encoder.encode(b"ok").unwrap());
+        stream.extend_from_slice(&[0, 0]);
+        let results = decode_all(&mut decoder, &stream);
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_err());
+        assert_eq!(results[1], Ok(b"ok".to_vec()));
+        decoder.reset_stats();
+        assert_eq!(decoder.stats(), FrameStats::default());
+    }
+
+    proptest! {
+        /// Whatever the payload, the frame has no zeros but the last, fits
+        /// `encoded_len` and decodes back, even after noise on the line
+        #[test]
+        fn round_trip(
+            noise in proptest::collection::vec(any::<u8>(), 0..20),
+            payloads in proptest::collection::vec(
+                proptest::collection::vec(prop_oneof![Just(0u8), any::<u8>()], 0..600), 1..4),
+        ) {
+            let mut encoder: FrameEncoder<620> = FrameEncoder::new();
+            let mut decoder: FrameDecoder<620> = FrameDecoder::new();
+            let mut stream = noise.clone();
+            stream.push(0);
+            for payload in &payloads {
+                let frame = encoder.encode(payload).unwrap();
+                prop_assert!(frame.len() <= encoded_len(payload.len()));
+                prop_assert_eq!(frame.iter().position(|b| *b == 0), Some(frame.len() - 1));
+                stream.extend_from_slice(frame);
+            }
+            let results = decode_all(&mut decoder, &stream);
+            let good: Vec<_> = results.iter().skip(results.len() - payloads.len()).cloned().collect();
+            let expected: Vec<_> = payloads.iter().map(|p| Ok(p.clone())).collect();
+            prop_assert_eq!(good, expected);
+        }
+    }
+}
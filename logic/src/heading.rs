@@ -0,0 +1,143 @@
+//! Tilt compensated compass heading from accelerometer and magnetometer
+//!
+//! Both halves of the LSM303DLHC share one set of axes, so the tilt worked
+//! out from gravity can be taken straight back out of the magnetic field
+//! before the heading is computed (Freescale AN4248). With the board flat
+//! this reduces to `atan2(y, x)` of the field.
+//!
+//! Bearings are in degrees clockwise from the board's north LED (LD3), the
+//! same convention as `CompassLeds::point_at`.
+
+use core::f32::consts::PI;
+
+use libm::{atan2f, cosf, sinf};
+
+/// Roll and pitch in radians from acceleration in any unit
+pub fn tilt(accel: [f32; 3]) -> (f32, f32) {
+    let [x, y, z] = accel;
+    let roll = atan2f(y, z);
+    let pitch = atan2f(-x, y * sinf(roll) + z * cosf(roll));
+    (roll, pitch)
+}
+
+/// Angle in radians of the horizontal component of the field, after
+/// undoing the tilt given by `accel`
+pub fn tilt_compensated_angle(accel: [f32; 3], mag: [f32; 3]) -> f32 {
+    let (roll, pitch) = tilt(accel);
+    let [mx, my, mz] = mag;
+    let (sin_roll, cos_roll) = (sinf(roll), cosf(roll));
+    let (sin_pitch, cos_pitch) = (sinf(pitch), cosf(pitch));
+    let bx = mx * cos_pitch + my * sin_pitch * sin_roll + mz * sin_pitch * cos_roll;
+    let by = mz * sin_roll - my * cos_roll;
+    atan2f(-by, bx)
+}
+
+/// Bearing of magnetic north in degrees, 0 to 360, from the board's north
+/// LED clockwise
+pub fn north_bearing(accel: [f32; 3], mag: [f32; 3]) -> f32 {
+    let bearing = 180.0 - tilt_compensated_angle(accel, mag) * 180.0 / PI;
+    if bearing >= 360.0 {
+        bearing - 360.0
+    } else {
+        bearing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Earth's field at the board, in gauss: 0.21 horizontal and 0.43
+    /// down, as in central Europe
+    const HORIZONTAL: f32 = 0.21;
+    const DOWN: f32 = 0.43;
+    const DEG_TO_RAD: f32 = PI / 180.0;
+
+    fn angle_error(a: f32, b: f32) -> f32 {
+        let d = (a - b).rem_euclid(360.0);
+        d.min(360.0 - d)
+    }
+
+    /// Gravity and the field as the sensors see them with the board level
+    /// and north at `bearing` clockwise from the north LED. The LED lies
+    /// along -x and clockwise from above turns -x towards +y
+    fn level(bearing: f32) -> ([f32; 3], [f32; 3]) {
+        let b = bearing * DEG_TO_RAD;
+        ([0.0, 0.0, 1.0], [-HORIZONTAL * cosf(b), HORIZONTAL * sinf(b), -DOWN])
+    }
+
+    /// `v` seen from the board pitched by `pitch` then rolled by `roll`
+    /// degrees, as in AN4248
+    fn tilted(v: [f32; 3], roll: f32, pitch: f32) -> [f32; 3] {
+        let (sr, cr) = (sinf(roll * DEG_TO_RAD), cosf(roll * DEG_TO_RAD));
+        let (sp, cp) = (sinf(pitch * DEG_TO_RAD), cosf(pitch * DEG_TO_RAD));
+        let [x, y, z] = v;
+        let (x, z) = (x * cp - z * sp, x * sp + z * cp);
+        let (y, z) = (y * cr + z * sr, -y * sr + z * cr);
+        [x, y, z]
+    }
+
+    #[test]
+    fn level_board() {
+        // north LED pointing north, west, south and east
+        for (bearing, name) in [(0.0, "N"), (90.0, "W"), (180.0, "S"), (270.0, "E")] {
+            let (accel, mag) = level(bearing);
+            let got = north_bearing(accel, mag);
+            assert!(angle_error(got, bearing) < 0.01, "{}: {} want {}", name, got, bearing);
+            assert!((0.0..360.0).contains(&got), "{}: {}", name, got);
+        }
+    }
+
+    #[test]
+    fn tilt_of_a_level_board() {
+        let (roll, pitch) = tilt([0.0, 0.0, 1.0]);
+        assert_eq!((roll, pitch), (0.0, 0.0));
+        // only the direction of gravity matters, not its size
+        let (roll, pitch) = tilt(tilted([0.0, 0.0, 9.81], 30.0, -30.0));
+        assert!((roll / DEG_TO_RAD - 30.0).abs() < 0.01);
+        assert!((pitch / DEG_TO_RAD + 30.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn tilt_keeps_the_bearing() {
+        for bearing in [0.0, 45.0, 90.0, 135.0, 180.0, 225.0, 270.0, 315.0] {
+            let (accel, mag) = level(bearing);
+            let tilts = [(30.0, 0.0), (-30.0, 0.0), (0.0, 30.0), (0.0, -30.0), (30.0, -30.0), (-20.0, 25.0)];
+            for (roll, pitch) in tilts {
+                let got = north_bearing(tilted(accel, roll, pitch), tilted(mag, roll, pitch));
+                assert!(
+                    angle_error(got, bearing) < 0.05,
+                    "{} at roll {} pitch {}: {}",
+                    bearing,
+                    roll,
+                    pitch,
+                    got
+                );
+            }
+            // whereas leaving the tilt in throws the bearing off, so the
+            // compensation is doing something
+            let flat = [0.0, 0.0, 1.0];
+            let got = north_bearing(flat, tilted(mag, 30.0, 30.0));
+            assert!(angle_error(got, bearing) > 5.0, "{}: {}", bearing, got);
+        }
+    }
+
+    #[test]
+    fn wraps_at_north() {
+        let just_west = north_bearing(level(359.0).0, level(359.0).1);
+        let just_east = north_bearing(level(1.0).0, level(1.0).1);
+        assert!((just_west - 359.0).abs() < 0.01, "{}", just_west);
+        assert!((just_east - 1.0).abs() < 0.01, "{}", just_east);
+        // straight ahead is 0, never 360
+        let (accel, mag) = level(0.0);
+        for mag in [mag, [mag[0], -0.0, mag[2]], [mag[0], 0.0, mag[2]]] {
+            let got = north_bearing(accel, mag);
+            assert!((0.0..360.0).contains(&got) && got < 0.01, "{}", got);
+        }
+        for bearing in (0..3600).map(|tenths| tenths as f32 / 10.0) {
+            let (accel, mag) = level(bearing);
+            let got = north_bearing(tilted(accel, 10.0, 10.0), tilted(mag, 10.0, 10.0));
+            assert!((0.0..360.0).contains(&got), "{}: {}", bearing, got);
+        }
+    }
+}
@@ -0,0 +1,25 @@
+//! The hardware-free half of the STM32F3DISCOVERY board support
+//!
+//! Queues, framing, baud rate dividers, flow control, the console's line
+//! editor and shell, LED patterns and PWM duty cycles, the button debouncer
+//! and mode machine, sensor fusion and calibration. None of it touches a
+//! register, so it lives in a crate of its own that builds and is tested on
+//! the host; `stm32f3disc` re-exports each module under the same name.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod ahrs;
+pub mod baud;
+pub mod button;
+pub mod calibration;
+pub mod crc;
+pub mod flow;
+pub mod framing;
+pub mod heading;
+pub mod line_editor;
+pub mod mode;
+pub mod pattern;
+pub mod pwm;
+pub mod ring_buffer;
+pub mod shell;
+pub mod spsc;
@@ -0,0 +1,574 @@
+//! Line discipline for a serial console
+//!
+//! `LineEditor` takes received bytes one at a time, echoes them and keeps
+//! the line being typed, and hands the application a finished line when
+//! Enter is pressed. It understands what a VT100-style terminal sends,
+//! with the cursor keys in normal or application mode:
+//!
+//! * Backspace (BS or DEL) and the Delete key remove characters
+//! * Left and Right move the cursor, Home and End (or Ctrl-A, Ctrl-E) jump
+//! * Up and Down step through the last `H` lines entered
+//! * Ctrl-U clears back to the start of the line, Ctrl-C abandons it
+//!
+//! Only printable ASCII goes into the line, so it is always valid `str`.
+//! The echo goes to any `core::fmt::Write`, usually a `SerialWriter`; the
+//! editor itself needs nothing but `core`.
+
+use core::fmt::Write;
+
+const BS: u8 = 0x08;
+const DEL: u8 = 0x7f;
+const ESC: u8 = 0x1b;
+const CTRL_A: u8 = 0x01;
+const CTRL_C: u8 = 0x03;
+const CTRL_E: u8 = 0x05;
+const CTRL_U: u8 = 0x15;
+
+/// What a byte finished
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event<'a> {
+    /// Enter pressed, the line without its ending; print the prompt again
+    /// once it has been dealt with
+    Line(&'a str),
+    /// Ctrl-C pressed, the line was dropped and a new prompt printed
+    Interrupt,
+}
+
+/// Where we are in an escape sequence
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// ESC seen
+    Start,
+    /// ESC [ seen, with any numeric parameter so far
+    Csi(u8),
+    /// ESC O seen, as the cursor keys send in application mode
+    Ss3,
+}
+
+#[derive(Clone, Copy)]
+struct Entry<const N: usize> {
+    text: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> Entry<N> {
+    const EMPTY: Entry<N> = Entry { text: [0; N], len: 0 };
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.text[..self.len]
+    }
+}
+
+/// Lines of up to `N` characters, remembering the last `H`
+pub struct LineEditor<const N: usize, const H: usize> {
+    prompt: &'static str,
+    line: Entry<N>,
+    cursor: usize,
+    escape: Escape,
+    /// Last byte was CR, so a following LF is the same line ending
+    after_cr: bool,
+    history: [Entry<N>; H],
+    /// Entries in `history`, the newest at `(newest + H - i) % H`
+    stored: usize,
+    newest: usize,
+    /// How far back Up has gone, 0 for the line being typed
+    recall: usize,
+    /// The line being typed, kept while browsing history
+    draft: Entry<N>,
+}
+
+impl<const N: usize, const H: usize> LineEditor<N, H> {
+    pub const fn new(prompt: &'static str) -> Self {
+        LineEditor {
+            prompt,
+            line: Entry::EMPTY,
+            cursor: 0,
+            escape: Escape::None,
+            after_cr: false,
+            history: [Entry::EMPTY; H],
+            stored: 0,
+            newest: 0,
+            recall: 0,
+            draft: Entry::EMPTY,
+        }
+    }
+
+    pub fn set_prompt(&mut self, prompt: &'static str) {
+        self.prompt = prompt;
+    }
+
+    /// The line typed so far
+    pub fn line(&self) -> &str {
+        // only printable ASCII is ever stored
+        core::str::from_utf8(self.line.as_bytes()).unwrap_or("")
+    }
+
+    /// Print the prompt, for the start of the session or after output
+    /// that didn't come from the editor
+    pub fn prompt(&self, out: &mut impl Write) {
+        out.write_str(self.prompt).ok();
+    }
+
+    /// Print the prompt and the line so far again, after other output
+    pub fn redraw(&self, out: &mut impl Write) {
+        out.write_str("\r\n").ok();
+        self.prompt(out);
+        self.write_bytes(out, self.line.as_bytes());
+        self.back(out, self.line.len - self.cursor);
+    }
+
+    /// Handle one received byte, echoing to `out`
+    pub fn feed(&mut self, byte: u8, out: &mut impl Write) -> Option<Event<'_>> {
+        let after_cr = self.after_cr;
+        self.after_cr = byte == b'\r';
+        match self.escape {
+            Escape::Start => {
+                self.escape = match byte {
+                    b'[' => Escape::Csi(0),
+                    b'O' => Escape::Ss3,
+                    _ => Escape::None,
+                };
+                return None;
+            }
+            Escape::Csi(param) => {
+                if byte.is_ascii_digit() {
+                    // too big to mean anything, but mustn't wrap to a key
+                    let param = param.saturating_mul(10).saturating_add(byte - b'0');
+                    self.escape = Escape::Csi(param);
+                } else {
+                    self.escape = Escape::None;
+                    self.escape_sequence(param, byte, out);
+                }
+                return None;
+            }
+            Escape::Ss3 => {
+                self.escape = Escape::None;
+                // only the letter keys, the `~` ones are CSI either way
+                if byte.is_ascii_alphabetic() {
+                    self.escape_sequence(0, byte, out);
+                }
+                return None;
+            }
+            Escape::None => (),
+        }
+        match byte {
+            b'\r' | b'\n' => {
+                if byte == b'\n' && after_cr {
+                    return None;
+                }
+                out.write_str("\r\n").ok();
+                self.remember();
+                let len = self.line.len;
+                self.line.len = 0;
+                self.cursor = 0;
+                self.recall = 0;
+                let text = &self.line.text[..len];
+                return Some(Event::Line(core::str::from_utf8(text).unwrap_or("")));
+            }
+            CTRL_C => {
+                out.write_str("^C\r\n").ok();
+                self.line.len = 0;
+                self.cursor = 0;
+                self.recall = 0;
+                self.prompt(out);
+                return Some(Event::Interrupt);
+            }
+            CTRL_U => {
+                let count = self.cursor;
+                self.back(out, count);
+                self.delete_from(0, count, out);
+            }
+            CTRL_A => self.move_to(0, out),
+            CTRL_E => self.move_to(self.line.len, out),
+            BS | DEL if self.cursor > 0 => {
+                self.back(out, 1);
+                self.delete_from(self.cursor - 1, 1, out);
+            }
+            ESC => self.escape = Escape::Start,
+            0x20..=0x7e => self.insert(byte, out),
+            _ => (),
+        }
+        None
+    }
+
+    fn escape_sequence(&mut self, param: u8, byte: u8, out: &mut impl Write) {
+        match (param, byte) {
+            (_, b'A') => self.history_step(true, out),
+            (_, b'B') => self.history_step(false, out),
+            (_, b'C') => self.move_to((self.cursor + 1).min(self.line.len), out),
+            (_, b'D') => self.move_to(self.cursor.saturating_sub(1), out),
+            (_, b'H') | (1, b'~') => self.move_to(0, out),
+            (_, b'F') | (4, b'~') => self.move_to(self.line.len, out),
+            (3, b'~') if self.cursor < self.line.len => {
+                self.delete_from(self.cursor, 1, out);
+            }
+            _ => (),
+        }
+    }
+
+    fn write_bytes(&self, out: &mut impl Write, bytes: &[u8]) {
+        out.write_str(core::str::from_utf8(bytes).unwrap_or("")).ok();
+    }
+
+    /// Move the terminal cursor left `count` places
+    fn back(&self, out: &mut impl Write, count: usize) {
+        for _ in 0..count {
+            out.write_char(BS as char).ok();
+        }
+    }
+
+    fn move_to(&mut self, pos: usize, out: &mut impl Write) {
+        if pos < self.cursor {
+            self.back(out, self.cursor - pos);
+        } else {
+            self.write_bytes(out, &self.line.text[self.cursor..pos]);
+        }
+        self.cursor = pos;
+    }
+
+    fn insert(&mut self, byte: u8, out: &mut impl Write) {
+        if self.line.len == N {
+            return;
+        }
+        self.line.text.copy_within(self.cursor..self.line.len, self.cursor + 1);
+        self.line.text[self.cursor] = byte;
+        self.line.len += 1;
+        // echo from the new character to the end, then back up to it
+        self.write_bytes(out, &self.line.text[self.cursor..self.line.len]);
+        self.cursor += 1;
+        self.back(out, self.line.len - self.cursor);
+    }
+
+    /// Remove `count` characters at `pos`, the terminal cursor already at
+    /// `pos`, and redraw the rest of the line
+    fn delete_from(&mut self, pos: usize, count: usize, out: &mut impl Write) {
+        self.line.text.copy_within(pos + count..self.line.len, pos);
+        self.line.len -= count;
+        self.cursor = pos;
+        self.write_bytes(out, &self.line.text[pos..self.line.len]);
+        for _ in 0..count {
+            out.write_char(' ').ok();
+        }
+        self.back(out, self.line.len - pos + count);
+    }
+
+    /// Replace the whole line on screen and in the buffer
+    fn replace(&mut self, entry: Entry<N>, out: &mut impl Write) {
+        self.back(out, self.cursor);
+        let old_len = self.line.len;
+        self.line = entry;
+        self.write_bytes(out, self.line.as_bytes());
+        let extra = old_len.saturating_sub(self.line.len);
+        for _ in 0..extra {
+            out.write_char(' ').ok();
+        }
+        self.back(out, extra);
+        self.cursor = self.line.len;
+    }
+
+    fn history_step(&mut self, older: bool, out: &mut impl Write) {
+        if older && self.recall < self.stored {
+            if self.recall == 0 {
+                self.draft = self.line;
+            }
+            self.recall += 1;
+        } else if !older && self.recall > 0 {
+            self.recall -= 1;
+        } else {
+            return;
+        }
+        let entry = if self.recall == 0 {
+            self.draft
+        } else {
+            self.history[(self.newest + H - (self.recall - 1)) % H]
+        };
+        self.replace(entry, out);
+    }
+
+    fn remember(&mut self) {
+        if H == 0 || self.line.len == 0 {
+            return;
+        }
+        let newest = self.history[self.newest];
+        if self.stored > 0 && newest.as_bytes() == self.line.as_bytes() {
+            return;
+        }
+        self.newest = if self.stored == 0 { 0 } else { (self.newest + 1) % H };
+        self.history[self.newest] = self.line;
+        self.stored = (self.stored + 1).min(H);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    /// Just enough of a terminal to follow the echo: one row, a cursor,
+    /// CR, LF and backspace
+    #[derive(Default)]
+    struct Screen {
+        row: Vec<u8>,
+        col: usize,
+        /// Rows finished with LF
+        lines: Vec<String>,
+    }
+
+    impl Write for Screen {
+        fn write_str(&mut self, s: &str) -> core::fmt::Result {
+            for byte in s.bytes() {
+                match byte {
+                    b'\r' => self.col = 0,
+                    b'\n' => {
+                        self.lines.push(String::from_utf8(self.row.clone()).unwrap());
+                        self.row.clear();
+                    }
+                    BS => self.col = self.col.saturating_sub(1),
+                    _ => {
+                        if self.col == self.row.len() {
+                            self.row.push(byte);
+                        } else {
+                            self.row[self.col] = byte;
+                        }
+                        self.col += 1;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl Screen {
+        /// The row without the blanks left by deletions
+        fn text(&self) -> &str {
+            core::str::from_utf8(&self.row).unwrap().trim_end()
+        }
+    }
+
+    const PROMPT: &str = "> ";
+
+    fn editor() -> (LineEditor<16, 3>, Screen) {
+        let editor = LineEditor::new(PROMPT);
+        let mut screen = Screen::default();
+        editor.prompt(&mut screen);
+        (editor, screen)
+    }
+
+    /// Feed `bytes`, returning the lines entered
+    fn type_bytes(editor: &mut LineEditor<16, 3>, screen: &mut Screen, bytes: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for byte in bytes {
+            if let Some(Event::Line(line)) = editor.feed(*byte, screen) {
+                lines.push(line.to_string());
+                editor.prompt(screen);
+            }
+        }
+        lines
+    }
+
+    /// The screen shows the prompt and the line, with the cursor in place
+    fn assert_shown(editor: &LineEditor<16, 3>, screen: &Screen) {
+        assert_eq!(screen.text(), format!("{}{}", PROMPT, editor.line()).trim_end());
+        assert_eq!(screen.col, PROMPT.len() + editor.cursor);
+    }
+
+    #[test]
+    fn enter_finishes_the_line() {
+        let (mut e, mut s) = editor();
+        assert_eq!(type_bytes(&mut e, &mut s, b"led 1 on\r"), ["led 1 on"]);
+        assert_eq!(s.lines, ["> led 1 on"]);
+        // CR LF is one line ending, LF alone is another
+        assert_eq!(type_bytes(&mut e, &mut s, b"a\r\nb\n\n"), ["a", "b", ""]);
+        assert_eq!(e.line(), "");
+        assert_shown(&e, &s);
+    }
+
+    #[test]
+    fn backspace() {
+        let (mut e, mut s) = editor();
+        type_bytes(&mut e, &mut s, b"abc\x08");
+        assert_eq!(e.line(), "ab");
+        assert_shown(&e, &s);
+        type_bytes(&mut e, &mut s, b"\x7f\x7f\x7f\x08");
+        assert_eq!(e.line(), "");
+        assert_eq!(s.text(), ">");
+        assert_shown(&e, &s);
+        assert_eq!(type_bytes(&mut e, &mut s, b"xy\x08z\r"), ["xz"]);
+    }
+
+    #[test]
+    fn cursor_movement_and_editing() {
+        let (mut e, mut s) = editor();
+        // left twice, insert
+        type_bytes(&mut e, &mut s, b"helo\x1b[D\x1b[Dl");
+        assert_eq!(e.line(), "hello");
+        assert_eq!(e.cursor, 3);
+        assert_shown(&e, &s);
+        // backspace in the middle
+        type_bytes(&mut e, &mut s, b"\x08");
+        assert_eq!(e.line(), "helo");
+        assert_shown(&e, &s);
+        // Home, Delete key, End
+        type_bytes(&mut e, &mut s, b"\x1b[H\x1b[3~");
+        assert_eq!((e.line(), e.cursor), ("elo", 0));
+        assert_shown(&e, &s);
+        type_bytes(&mut e, &mut s, b"\x1b[F!");
+        assert_eq!(e.line(), "elo!");
+        assert_shown(&e, &s);
+        // the other Home and End sequences, and Ctrl-A and Ctrl-E
+        type_bytes(&mut e, &mut s, b"\x1b[1~");
+        assert_eq!(e.cursor, 0);
+        type_bytes(&mut e, &mut s, b"\x1b[4~");
+        assert_eq!(e.cursor, 4);
+        type_bytes(&mut e, &mut s, b"\x01");
+        assert_eq!(e.cursor, 0);
+        type_bytes(&mut e, &mut s, b"\x05");
+        assert_eq!(e.cursor, 4);
+        // Right stops at the end, Left at the start, Delete at the end does
+        // nothing
+        type_bytes(&mut e, &mut s, b"\x1b[C\x1b[3~");
+        assert_eq!((e.line(), e.cursor), ("elo!", 4));
+        type_bytes(&mut e, &mut s, b"\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D");
+        assert_eq!(e.cursor, 0);
+        assert_shown(&e, &s);
+    }
+
+    #[test]
+    fn application_cursor_keys() {
+        // ESC O rather than ESC [, once the terminal has been put in
+        // application cursor mode
+        let (mut e, mut s) = editor();
+        type_bytes(&mut e, &mut s, b"one\r");
+        type_bytes(&mut e, &mut s, b"helo\x1bOD\x1bODl");
+        assert_eq!((e.line(), e.cursor), ("hello", 3));
+        type_bytes(&mut e, &mut s, b"\x1bOH\x1bOC");
+        assert_eq!(e.cursor, 1);
+        type_bytes(&mut e, &mut s, b"\x1bOF");
+        assert_eq!(e.cursor, 5);
+        assert_shown(&e, &s);
+        type_bytes(&mut e, &mut s, b"\x1bOA");
+        assert_eq!(e.line(), "one");
+        type_bytes(&mut e, &mut s, b"\x1bOB");
+        assert_eq!(e.line(), "hello");
+        assert_shown(&e, &s);
+    }
+
+    #[test]
+    fn ctrl_u_and_ctrl_c() {
+        let (mut e, mut s) = editor();
+        type_bytes(&mut e, &mut s, b"pattern chase\x1b[D\x1b[D\x15");
+        assert_eq!((e.line(), e.cursor), ("se", 0));
+        assert_shown(&e, &s);
+        type_bytes(&mut e, &mut s, b"xyz");
+        assert_eq!(e.feed(CTRL_C, &mut s), Some(Event::Interrupt));
+        assert_eq!(e.line(), "");
+        // ^C goes where the cursor was
+        assert_eq!(s.lines.last().unwrap().trim_end(), "> xyz^C");
+        assert_shown(&e, &s);
+    }
+
+    #[test]
+    fn ignores_what_it_doesnt_understand() {
+        let (mut e, mut s) = editor();
+        // control characters, bytes above ASCII, unknown and unfinished
+        // escape sequences
+        type_bytes(&mut e, &mut s, b"a\x00\x07\x80\xffb\x1b[Zc\x1bxd\x1b[99~e\x1bO~f\x1bOZg");
+        assert_eq!(e.line(), "abcdefg");
+        assert_shown(&e, &s);
+        // a parameter past 255 stays out of range rather than wrapping
+        // round to 3, Delete, or 1, Home
+        type_bytes(&mut e, &mut s, b"\x01\x1b[259~");
+        assert_eq!((e.line(), e.cursor), ("abcdefg", 0));
+        type_bytes(&mut e, &mut s, b"\x05\x1b[257~\x1b[99999999999999999999~");
+        assert_eq!((e.line(), e.cursor), ("abcdefg", 7));
+    }
+
+    #[test]
+    fn line_stops_at_its_length() {
+        let (mut e, mut s) = editor();
+        type_bytes(&mut e, &mut s, b"0123456789abcdefXYZ");
+        assert_eq!(e.line(), "0123456789abcdef");
+        assert_shown(&e, &s);
+        // nor does inserting in the middle push anything off the end
+        type_bytes(&mut e, &mut s, b"\x01!");
+        assert_eq!(e.line(), "0123456789abcdef");
+    }
+
+    #[test]
+    fn history() {
+        let (mut e, mut s) = editor();
+        type_bytes(&mut e, &mut s, b"one\rtwo\r\rtwo\r");
+        // empty lines and repeats aren't kept
+        type_bytes(&mut e, &mut s, b"dra\x1b[A");
+        assert_eq!(e.line(), "two");
+        assert_shown(&e, &s);
+        type_bytes(&mut e, &mut s, b"\x1b[A");
+        assert_eq!(e.line(), "one");
+        // no further back
+        type_bytes(&mut e, &mut s, b"\x1b[A");
+        assert_eq!(e.line(), "one");
+        assert_shown(&e, &s);
+        // down again to the line being typed
+        type_bytes(&mut e, &mut s, b"\x1b[B\x1b[B");
+        assert_eq!(e.line(), "dra");
+        type_bytes(&mut e, &mut s, b"\x1b[B");
+        assert_eq!(e.line(), "dra");
+        assert_shown(&e, &s);
+        // a recalled line can be edited and entered
+        assert_eq!(type_bytes(&mut e, &mut s, b"\x15\x1b[A!\r"), ["two!"]);
+    }
+
+    #[test]
+    fn history_wraps() {
+        let (mut e, mut s) = editor();
+        type_bytes(&mut e, &mut s, b"a\rb\rc\rd\re\r");
+        let mut recalled = Vec::new();
+        for _ in 0..5 {
+            type_bytes(&mut e, &mut s, b"\x1b[A");
+            recalled.push(e.line().to_string());
+        }
+        // only the last three
+        assert_eq!(recalled, ["e", "d", "c", "c", "c"]);
+        for expected in ["d", "e", ""] {
+            type_bytes(&mut e, &mut s, b"\x1b[B");
+            assert_eq!(e.line(), expected);
+        }
+        assert_shown(&e, &s);
+    }
+
+    fn key() -> impl Strategy<Value = Vec<u8>> {
+        prop_oneof![
+            6 => (0x20u8..0x7f).prop_map(|c| vec![c]),
+            1 => Just(vec![BS]),
+            1 => Just(vec![DEL]),
+            1 => Just(vec![CTRL_A]),
+            1 => Just(vec![CTRL_E]),
+            1 => Just(vec![CTRL_U]),
+            1 => Just(b"\r".to_vec()),
+            1 => Just(b"\x1b[A".to_vec()),
+            1 => Just(b"\x1b[B".to_vec()),
+            2 => Just(b"\x1b[C".to_vec()),
+            2 => Just(b"\x1b[D".to_vec()),
+            1 => Just(b"\x1b[3~".to_vec()),
+            1 => Just(b"\x1bOC".to_vec()),
+            1 => Just(b"\x1bOD".to_vec()),
+        ]
+    }
+
+    proptest! {
+        /// Whatever is typed, the echo leaves the terminal showing the line
+        /// the editor holds, with the cursor in the same place
+        #[test]
+        fn screen_matches_the_line(keys in proptest::collection::vec(key(), 0..100)) {
+            let (mut e, mut s) = editor();
+            for key in keys {
+                type_bytes(&mut e, &mut s, &key);
+                let shown = format!("{}{}", PROMPT, e.line());
+                prop_assert_eq!(s.text(), shown.trim_end());
+                prop_assert_eq!(s.col, PROMPT.len() + e.cursor);
+            }
+        }
+    }
+}
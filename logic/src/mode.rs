@@ -0,0 +1,254 @@
+//! Application mode, driven by button events
+//!
+//! Every transition is in `ModeMachine::handle`. Short actions happen on
+//! `Click`, which the debouncer only sends once a press has been released
+//! and no second press followed within `DOUBLE_CLICK_MS`, so neither a long
+//! press nor the first half of a double click also counts as a click.
+//! `Pressed` and `Released` change nothing.
+//!
+//! | mode          | click         | long press    | double click |
+//! |---------------|---------------|---------------|--------------|
+//! | Running       | Paused        | PatternSelect | Settings     |
+//! | Paused        | Running       | PatternSelect | Settings     |
+//! | PatternSelect | next pattern  | Running       | Settings     |
+//! | Settings      | next setting  | Running       | Running      |
+//!
+//! The serial console's `pause`, `led` and `pattern` commands `set` the
+//! mode directly.
+//!
+//! `SharedMode` keeps the machine in a critical section mutex so it can be
+//! driven from an interrupt and read from the main loop.
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+
+use crate::button::ButtonEvent;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppMode {
+    /// Pattern playing
+    Running,
+    /// Pattern frozen on the current frame
+    Paused,
+    /// Clicks step through the patterns
+    PatternSelect,
+    /// Clicks step the current setting
+    Settings,
+}
+
+/// What the application should do besides changing mode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    NextPattern,
+    NextSetting,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub from: AppMode,
+    pub to: AppMode,
+    pub action: Option<Action>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ModeMachine {
+    mode: AppMode,
+}
+
+impl ModeMachine {
+    pub const fn new() -> ModeMachine {
+        ModeMachine { mode: AppMode::Running }
+    }
+
+    pub fn mode(&self) -> AppMode {
+        self.mode
+    }
+
+    /// Go straight to `mode`, for changes that don't come from the button
+    pub fn set(&mut self, mode: AppMode) {
+        self.mode = mode;
+    }
+
+    pub fn handle(&mut self, event: ButtonEvent) -> Transition {
+        use AppMode::*;
+        use ButtonEvent::*;
+
+        let from = self.mode;
+        let (to, action) = match (from, event) {
+            (_, Pressed) | (_, Released) => (from, None),
+            (Running, Click) => (Paused, None),
+            (Paused, Click) => (Running, None),
+            (PatternSelect, Click) => (PatternSelect, Some(Action::NextPattern)),
+            (Settings, Click) => (Settings, Some(Action::NextSetting)),
+            (Running, LongPress) | (Paused, LongPress) => (PatternSelect, None),
+            (PatternSelect, LongPress) | (Settings, LongPress) => (Running, None),
+            (Settings, DoubleClick) => (Running, None),
+            (_, DoubleClick) => (Settings, None),
+        };
+        self.mode = to;
+        Transition { from, to, action }
+    }
+}
+
+impl Default for ModeMachine {
+    fn default() -> Self {
+        ModeMachine::new()
+    }
+}
+
+/// `ModeMachine` shareable between interrupts and the main loop
+pub struct SharedMode {
+    machine: Mutex<Cell<ModeMachine>>,
+}
+
+impl SharedMode {
+    pub const fn new() -> SharedMode {
+        SharedMode { machine: Mutex::new(Cell::new(ModeMachine::new())) }
+    }
+
+    pub fn get(&self) -> AppMode {
+        critical_section::with(|cs| self.machine.borrow(cs).get().mode())
+    }
+
+    pub fn set(&self, mode: AppMode) {
+        critical_section::with(|cs| {
+            let cell = self.machine.borrow(cs);
+            let mut machine = cell.get();
+            machine.set(mode);
+            cell.set(machine);
+        })
+    }
+
+    pub fn handle(&self, event: ButtonEvent) -> Transition {
+        critical_section::with(|cs| {
+            let cell = self.machine.borrow(cs);
+            let mut machine = cell.get();
+            let transition = machine.handle(event);
+            cell.set(machine);
+            transition
+        })
+    }
+}
+
+impl Default for SharedMode {
+    fn default() -> Self {
+        SharedMode::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::button::{Debouncer, DEBOUNCE_MS};
+    use AppMode::*;
+    use ButtonEvent::*;
+
+    fn machine_in(mode: AppMode) -> ModeMachine {
+        let mut machine = ModeMachine::new();
+        machine.set(mode);
+        machine
+    }
+
+    #[test]
+    fn transition_table() {
+        let table = [
+            (Running, Click, Paused, None),
+            (Paused, Click, Running, None),
+            (PatternSelect, Click, PatternSelect, Some(Action::NextPattern)),
+            (Settings, Click, Settings, Some(Action::NextSetting)),
+            (Running, LongPress, PatternSelect, None),
+            (Paused, LongPress, PatternSelect, None),
+            (PatternSelect, LongPress, Running, None),
+            (Settings, LongPress, Running, None),
+            (Running, DoubleClick, Settings, None),
+            (Paused, DoubleClick, Settings, None),
+            (PatternSelect, DoubleClick, Settings, None),
+            (Settings, DoubleClick, Running, None),
+        ];
+        for &(from, event, to, action) in table.iter() {
+            let mut machine = machine_in(from);
+            assert_eq!(machine.handle(event), Transition { from, to, action }, "{:?}", event);
+            assert_eq!(machine.mode(), to);
+        }
+    }
+
+    #[test]
+    fn press_and_release_change_nothing() {
+        for &mode in [Running, Paused, PatternSelect, Settings].iter() {
+            for &event in [Pressed, Released].iter() {
+                let mut machine = machine_in(mode);
+                assert_eq!(machine.handle(event), Transition { from: mode, to: mode, action: None });
+            }
+        }
+    }
+
+    /// Runs a button level sequence of (level, milliseconds) through the
+    /// debouncer into a machine starting in `mode`
+    fn press(mode: AppMode, levels: &[(bool, u32)]) -> (ModeMachine, Vec<Transition>) {
+        let mut machine = machine_in(mode);
+        let mut debouncer = Debouncer::new();
+        let mut transitions = Vec::new();
+        let mut now = 0;
+        for &(level, ms) in levels.iter().chain([(false, 1000)].iter()) {
+            for _ in 0..ms {
+                debouncer.update(level, now, |event| {
+                    let transition = machine.handle(event);
+                    if transition.from != transition.to || transition.action.is_some() {
+                        transitions.push(transition);
+                    }
+                });
+                now += 1;
+            }
+        }
+        (machine, transitions)
+    }
+
+    #[test]
+    fn double_click_does_not_click_first() {
+        let double = [(true, 80), (false, 100), (true, 80)];
+        let (machine, transitions) = press(Running, &double);
+        assert_eq!(machine.mode(), Settings);
+        assert_eq!(transitions, [Transition { from: Running, to: Settings, action: None }]);
+
+        let (machine, transitions) = press(PatternSelect, &double);
+        assert_eq!(machine.mode(), Settings);
+        assert_eq!(transitions.len(), 1);
+        assert_eq!(transitions[0].action, None);
+
+        let (machine, transitions) = press(Settings, &double);
+        assert_eq!(machine.mode(), Running);
+        assert_eq!(transitions, [Transition { from: Settings, to: Running, action: None }]);
+    }
+
+    #[test]
+    fn single_click_acts_once() {
+        let (machine, transitions) = press(Running, &[(true, 80)]);
+        assert_eq!(machine.mode(), Paused);
+        assert_eq!(transitions.len(), 1);
+
+        let (machine, transitions) = press(PatternSelect, &[(true, 80), (false, 500), (true, 80)]);
+        assert_eq!(machine.mode(), PatternSelect);
+        let actions: Vec<_> = transitions.iter().map(|t| t.action).collect();
+        assert_eq!(actions, [Some(Action::NextPattern), Some(Action::NextPattern)]);
+    }
+
+    #[test]
+    fn long_press_release_does_not_click() {
+        let (machine, transitions) = press(Running, &[(true, 1000)]);
+        assert_eq!(machine.mode(), PatternSelect);
+        assert_eq!(transitions, [Transition { from: Running, to: PatternSelect, action: None }]);
+        let (machine, _) = press(Settings, &[(true, DEBOUNCE_MS + 1000)]);
+        assert_eq!(machine.mode(), Running);
+    }
+
+    #[test]
+    fn shared_mode_matches_the_machine() {
+        let shared = SharedMode::new();
+        assert_eq!(shared.get(), Running);
+        assert_eq!(shared.handle(LongPress).to, PatternSelect);
+        assert_eq!(shared.handle(Click).action, Some(Action::NextPattern));
+        shared.set(Paused);
+        assert_eq!(shared.handle(Click).to, Running);
+    }
+}
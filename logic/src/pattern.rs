@@ -0,0 +1,332 @@
+//! LED patterns as data
+//!
+//! A `Pattern` is a cycle of `Frame`s, each holding a brightness for every
+//! LED (in compass order) and how long to show it. `PatternEngine` steps
+//! through the frames from a millisecond tick, so the caller never blocks in
+//! `delay_ms` and can swap patterns at any time. Nothing here touches the
+//! hardware; the caller renders `PatternEngine::frame()` onto the LEDs.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    /// Brightness of each LED, 0 off to 255 full
+    pub levels: [u8; 8],
+    pub duration_ms: u16,
+}
+
+impl Frame {
+    /// Frame with the LEDs whose bits are set at full brightness
+    pub const fn from_mask(mask: u8, duration_ms: u16) -> Frame {
+        let mut levels = [0; 8];
+        let mut i = 0;
+        while i < 8 {
+            if mask & (1 << i) != 0 {
+                levels[i] = 255;
+            }
+            i += 1;
+        }
+        Frame { levels, duration_ms }
+    }
+
+    /// Frame with every LED at the same brightness
+    pub const fn uniform(level: u8, duration_ms: u16) -> Frame {
+        Frame { levels: [level; 8], duration_ms }
+    }
+
+    /// On/off view for LEDs without brightness control, on from half level
+    pub fn mask(&self) -> u8 {
+        let mut mask = 0;
+        for (i, level) in self.levels.iter().enumerate() {
+            if *level >= 128 {
+                mask |= 1 << i;
+            }
+        }
+        mask
+    }
+}
+
+const BLANK: Frame = Frame::uniform(0, 0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// One LED at a time, clockwise
+    Chase,
+    /// One LED sweeping clockwise and back
+    Bounce,
+    /// All LEDs on, then all off
+    BlinkAll,
+    /// All LEDs ramping up and down together
+    Breathe,
+    /// The LEDs count up in binary, north as the low bit
+    BinaryCounter,
+    Custom(&'static [Frame]),
+}
+
+impl Pattern {
+    /// Patterns selectable at runtime, in selection order
+    pub const BUILTIN: [Pattern; 5] = [
+        Pattern::Chase,
+        Pattern::Bounce,
+        Pattern::BlinkAll,
+        Pattern::Breathe,
+        Pattern::BinaryCounter,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Pattern::Chase => "chase",
+            Pattern::Bounce => "bounce",
+            Pattern::BlinkAll => "blink",
+            Pattern::Breathe => "breathe",
+            Pattern::BinaryCounter => "counter",
+            Pattern::Custom(_) => "custom",
+        }
+    }
+
+    /// Look up one of the `BUILTIN` patterns by `name()`
+    pub fn from_name(name: &str) -> Option<Pattern> {
+        Pattern::BUILTIN.iter().copied().find(|p| p.name() == name)
+    }
+
+    /// The `BUILTIN` pattern after this one, wrapping; custom goes to the first
+    pub fn next(&self) -> Pattern {
+        match Pattern::BUILTIN.iter().position(|p| p == self) {
+            Some(i) => Pattern::BUILTIN[(i + 1) % Pattern::BUILTIN.len()],
+            None => Pattern::BUILTIN[0],
+        }
+    }
+
+    /// Number of frames in one cycle
+    pub fn len(&self) -> usize {
+        match self {
+            Pattern::Chase => 16,
+            Pattern::Bounce => 14,
+            Pattern::BlinkAll => 2,
+            Pattern::Breathe => 32,
+            Pattern::BinaryCounter => 256,
+            Pattern::Custom(frames) => frames.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Frame `step` of the cycle, wrapping
+    pub fn frame(&self, step: usize) -> Frame {
+        if self.is_empty() {
+            return BLANK;
+        }
+        let step = step % self.len();
+        match self {
+            // 450 ms on, 50 ms gap, as the original chase loop did
+            Pattern::Chase => {
+                if step.is_multiple_of(2) {
+                    Frame::from_mask(1 << (step / 2), 450)
+                } else {
+                    Frame::from_mask(0, 50)
+                }
+            }
+            Pattern::Bounce => {
+                let pos = if step < 8 { step } else { 14 - step };
+                Frame::from_mask(1 << pos, 100)
+            }
+            Pattern::BlinkAll => {
+                Frame::from_mask(if step == 0 { 0xff } else { 0 }, 500)
+            }
+            Pattern::Breathe => {
+                let level = if step < 16 { step } else { 31 - step };
+                Frame::uniform(level as u8 * 17, 50)
+            }
+            Pattern::BinaryCounter => Frame::from_mask(step as u8, 250),
+            Pattern::Custom(frames) => frames[step],
+        }
+    }
+}
+
+pub struct PatternEngine {
+    pattern: Pattern,
+    step: usize,
+    elapsed_ms: u32,
+}
+
+impl PatternEngine {
+    pub fn new(pattern: Pattern) -> PatternEngine {
+        PatternEngine { pattern, step: 0, elapsed_ms: 0 }
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    /// Switch pattern, starting at its first frame
+    pub fn set_pattern(&mut self, pattern: Pattern) {
+        self.pattern = pattern;
+        self.step = 0;
+        self.elapsed_ms = 0;
+    }
+
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    pub fn frame(&self) -> Frame {
+        self.pattern.frame(self.step)
+    }
+
+    /// Advance the pattern by `dt_ms`, returns true if the frame changed
+    pub fn tick(&mut self, dt_ms: u32) -> bool {
+        if self.pattern.is_empty() {
+            return false;
+        }
+        self.elapsed_ms += dt_ms;
+        let mut changed = false;
+        loop {
+            let duration = self.frame().duration_ms as u32;
+            if self.elapsed_ms < duration {
+                break;
+            }
+            self.elapsed_ms -= duration;
+            self.step = (self.step + 1) % self.pattern.len();
+            changed = true;
+            // a cycle of zero length frames would never catch up
+            if duration == 0 && self.step == 0 {
+                self.elapsed_ms = 0;
+                break;
+            }
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masks(pattern: Pattern) -> Vec<u8> {
+        (0..pattern.len()).map(|step| pattern.frame(step).mask()).collect()
+    }
+
+    #[test]
+    fn frame_changes_once_its_duration_is_up() {
+        let mut engine = PatternEngine::new(Pattern::Chase);
+        assert_eq!(engine.frame(), Frame::from_mask(0b1, 450));
+        assert!(!engine.tick(449));
+        assert_eq!(engine.step(), 0);
+        assert!(engine.tick(1));
+        assert_eq!(engine.step(), 1);
+        assert_eq!(engine.frame(), Frame::from_mask(0, 50));
+    }
+
+    #[test]
+    fn long_tick_steps_several_frames_and_keeps_the_remainder() {
+        let mut engine = PatternEngine::new(Pattern::Chase);
+        // 450 + 50 + 450 and 30 into the next gap
+        assert!(engine.tick(980));
+        assert_eq!(engine.step(), 3);
+        assert!(!engine.tick(19));
+        assert!(engine.tick(1));
+        assert_eq!(engine.step(), 4);
+        assert_eq!(engine.frame().mask(), 0b100);
+    }
+
+    #[test]
+    fn wraps_after_a_cycle() {
+        let mut engine = PatternEngine::new(Pattern::Chase);
+        for _ in 0..(Pattern::Chase.len() - 1) {
+            engine.tick(engine.frame().duration_ms as u32);
+        }
+        assert_eq!(engine.step(), 15);
+        assert!(engine.tick(50));
+        assert_eq!(engine.step(), 0);
+        // a whole cycle at once lands back where it started
+        assert!(engine.tick(8 * 500));
+        assert_eq!(engine.step(), 0);
+    }
+
+    #[test]
+    fn chase_lights_each_led_in_turn() {
+        let expected: Vec<u8> = (0..8).flat_map(|i| [1 << i, 0]).collect();
+        assert_eq!(masks(Pattern::Chase), expected);
+    }
+
+    #[test]
+    fn bounce_goes_out_and_back() {
+        assert_eq!(
+            masks(Pattern::Bounce),
+            [1, 2, 4, 8, 16, 32, 64, 128, 64, 32, 16, 8, 4, 2]
+        );
+        // and round again without lingering at either end
+        assert_eq!(Pattern::Bounce.frame(14).mask(), 1);
+        assert_eq!(Pattern::Bounce.frame(15).mask(), 2);
+    }
+
+    #[test]
+    fn breathe_ramps_up_then_down() {
+        let levels: Vec<u8> = (0..32).map(|step| Pattern::Breathe.frame(step).levels[0]).collect();
+        assert_eq!(levels[0], 0);
+        assert_eq!(levels[15], 255);
+        assert_eq!(levels[16], 255);
+        assert_eq!(levels[31], 0);
+        assert!(levels[..16].windows(2).all(|w| w[1] == w[0] + 17));
+        assert!(levels[16..].windows(2).all(|w| w[1] + 17 == w[0]));
+        for step in 0..32 {
+            let frame = Pattern::Breathe.frame(step);
+            assert!(frame.levels.iter().all(|l| *l == frame.levels[0]));
+        }
+    }
+
+    #[test]
+    fn blink_and_counter() {
+        assert_eq!(masks(Pattern::BlinkAll), [0xff, 0]);
+        assert_eq!(Pattern::BinaryCounter.frame(0).mask(), 0);
+        assert_eq!(Pattern::BinaryCounter.frame(0xa5).mask(), 0xa5);
+        assert_eq!(Pattern::BinaryCounter.frame(256).mask(), 0);
+    }
+
+    #[test]
+    fn set_pattern_starts_from_the_first_frame() {
+        let mut engine = PatternEngine::new(Pattern::Bounce);
+        engine.tick(350);
+        assert_eq!(engine.step(), 3);
+        engine.set_pattern(Pattern::BlinkAll);
+        assert_eq!(engine.pattern(), Pattern::BlinkAll);
+        assert_eq!(engine.step(), 0);
+        // time spent in the old pattern doesn't carry over
+        assert!(!engine.tick(499));
+        assert!(engine.tick(1));
+        assert_eq!(engine.step(), 1);
+    }
+
+    #[test]
+    fn custom_patterns() {
+        static FRAMES: [Frame; 2] = [Frame::from_mask(0x0f, 10), Frame::from_mask(0xf0, 20)];
+        let mut engine = PatternEngine::new(Pattern::Custom(&FRAMES));
+        assert_eq!(engine.frame().mask(), 0x0f);
+        assert!(engine.tick(10));
+        assert_eq!(engine.frame().mask(), 0xf0);
+        assert!(engine.tick(20));
+        assert_eq!(engine.step(), 0);
+
+        // nothing to show
+        let mut engine = PatternEngine::new(Pattern::Custom(&[]));
+        assert!(!engine.tick(1000));
+        assert_eq!(engine.frame(), Frame::uniform(0, 0));
+
+        // frames of no length don't spin forever
+        static INSTANT: [Frame; 2] = [Frame::uniform(1, 0), Frame::uniform(2, 0)];
+        let mut engine = PatternEngine::new(Pattern::Custom(&INSTANT));
+        assert!(engine.tick(5));
+        assert_eq!(engine.step(), 0);
+    }
+
+    #[test]
+    fn names() {
+        for pattern in Pattern::BUILTIN.iter() {
+            assert_eq!(Pattern::from_name(pattern.name()), Some(*pattern));
+        }
+        assert_eq!(Pattern::from_name("custom"), None);
+        assert_eq!(Pattern::Chase.next(), Pattern::Bounce);
+        assert_eq!(Pattern::BinaryCounter.next(), Pattern::Chase);
+        assert_eq!(Pattern::Custom(&[]).next(), Pattern::Chase);
+    }
+}
@@ -0,0 +1,147 @@
+//! Software PWM duty cycles for eight LEDs
+//!
+//! `SoftPwm` keeps a duty cycle per LED and, stepped once per timer slot,
+//! says which LEDs are on for that slot. A cycle is 255 slots, so a duty
+//! of 0 is always off and 255 always on. `GAMMA` maps perceived brightness
+//! to duty cycle. Nothing here touches the hardware; the firmware's `pwm`
+//! module drives the LEDs from TIM7 and re-exports this one.
+
+/// Perceived brightness 0-255 to duty cycle 0-255, gamma 2.2
+pub static GAMMA: [u8; 256] = [
+      0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   0,   1,
+      1,   1,   1,   1,   1,   1,   1,   1,   1,   2,   2,   2,   2,   2,   2,   2,
+      3,   3,   3,   3,   3,   4,   4,   4,   4,   5,   5,   5,   5,   6,   6,   6,
+      6,   7,   7,   7,   8,   8,   8,   9,   9,   9,  10,  10,  11,  11,  11,  12,
+     12,  13,  13,  13,  14,  14,  15,  15,  16,  16,  17,  17,  18,  18,  19,  19,
+     20,  20,  21,  22,  22,  23,  23,  24,  25,  25,  26,  26,  27,  28,  28,  29,
+     30,  30,  31,  32,  33,  33,  34,  35,  35,  36,  37,  38,  39,  39,  40,  41,
+     42,  43,  43,  44,  45,  46,  47,  48,  49,  49,  50,  51,  52,  53,  54,  55,
+     56,  57,  58,  59,  60,  61,  62,  63,  64,  65,  66,  67,  68,  69,  70,  71,
+     73,  74,  75,  76,  77,  78,  79,  81,  82,  83,  84,  85,  87,  88,  89,  90,
+     91,  93,  94,  95,  97,  98,  99, 100, 102, 103, 105, 106, 107, 109, 110, 111,
+    113, 114, 116, 117, 119, 120, 121, 123, 124, 126, 127, 129, 130, 132, 133, 135,
+    137, 138, 140, 141, 143, 145, 146, 148, 149, 151, 153, 154, 156, 158, 159, 161,
+    163, 165, 166, 168, 170, 172, 173, 175, 177, 179, 181, 182, 184, 186, 188, 190,
+    192, 194, 196, 197, 199, 201, 203, 205, 207, 209, 211, 213, 215, 217, 219, 221,
+    223, 225, 227, 229, 231, 234, 236, 238, 240, 242, 244, 246, 248, 251, 253, 255,
+];
+
+/// Duty cycle bookkeeping, independent of the timer and pins
+pub struct SoftPwm {
+    duty: [u8; 8],
+    slot: u8,
+}
+
+impl SoftPwm {
+    pub const fn new() -> SoftPwm {
+        SoftPwm { duty: [0; 8], slot: 0 }
+    }
+
+    /// Set LED `index` (compass order) to a perceived brightness
+    pub fn set_level(&mut self, index: usize, level: u8) {
+        self.duty[index] = GAMMA[level as usize];
+    }
+
+    pub fn set_levels(&mut self, levels: &[u8; 8]) {
+        for (index, level) in levels.iter().enumerate() {
+            self.set_level(index, *level);
+        }
+    }
+
+    /// Set LED `index` to a duty cycle, bypassing the gamma table
+    pub fn set_duty(&mut self, index: usize, duty: u8) {
+        self.duty[index] = duty;
+    }
+
+    /// Whether the next `step` starts a new cycle
+    pub fn cycle_start(&self) -> bool {
+        self.slot == 0
+    }
+
+    /// Advance one slot, returns the mask of LEDs that are on for it
+    pub fn step(&mut self) -> u8 {
+        let mut mask = 0;
+        for (index, duty) in self.duty.iter().enumerate() {
+            if self.slot < *duty {
+                mask |= 1 << index;
+            }
+        }
+        // 255 slots rather than 256, so a duty of 255 is always on
+        self.slot = if self.slot == 254 { 0 } else { self.slot + 1 };
+        mask
+    }
+}
+
+impl Default for SoftPwm {
+    fn default() -> Self {
+        SoftPwm::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// How many slots of one cycle each LED is on for
+    fn on_slots(pwm: &mut SoftPwm) -> [u32; 8] {
+        let mut on = [0; 8];
+        assert!(pwm.cycle_start());
+        for _ in 0..255 {
+            let mask = pwm.step();
+            for (index, count) in on.iter_mut().enumerate() {
+                if mask & (1 << index) != 0 {
+                    *count += 1;
+                }
+            }
+        }
+        on
+    }
+
+    #[test]
+    fn duty_is_slots_on_per_cycle() {
+        let mut pwm = SoftPwm::new();
+        let duties = [0, 1, 2, 64, 127, 128, 254, 255];
+        for (index, duty) in duties.iter().enumerate() {
+            pwm.set_duty(index, *duty);
+        }
+        for _ in 0..3 {
+            assert_eq!(on_slots(&mut pwm), duties.map(u32::from));
+        }
+    }
+
+    #[test]
+    fn on_at_the_start_of_the_cycle() {
+        let mut pwm = SoftPwm::new();
+        pwm.set_duty(0, 3);
+        pwm.set_duty(7, 255);
+        let masks: Vec<u8> = (0..255).map(|_| pwm.step()).collect();
+        assert_eq!(masks[..4], [0x81, 0x81, 0x81, 0x80]);
+        assert!(masks.iter().all(|mask| mask & 0x80 != 0));
+        assert!(pwm.cycle_start());
+        pwm.step();
+        assert!(!pwm.cycle_start());
+    }
+
+    #[test]
+    fn levels_go_through_the_gamma_table() {
+        let mut pwm = SoftPwm::new();
+        pwm.set_levels(&[0, 255, 128, 15, 16, 200, 1, 254]);
+        assert_eq!(on_slots(&mut pwm), [0, 255, 56, 1, 1, 149, 0, 253]);
+        pwm.set_level(0, 64);
+        assert_eq!(on_slots(&mut pwm)[0], GAMMA[64] as u32);
+    }
+
+    #[test]
+    fn gamma() {
+        assert_eq!(GAMMA[0], 0);
+        assert_eq!(GAMMA[255], 255);
+        assert!(GAMMA.windows(2).all(|pair| pair[0] <= pair[1]));
+        // close to (level / 255)^2.2 everywhere
+        for (level, duty) in GAMMA.iter().enumerate() {
+            let exact = libm::powf(level as f32 / 255.0, 2.2) * 255.0;
+            assert!((*duty as f32 - exact).abs() <= 0.5 + 1e-3, "{}: {} vs {}", level, duty, exact);
+        }
+        // half brightness is about a fifth of the power
+        assert_eq!(GAMMA[128], 56);
+    }
+}
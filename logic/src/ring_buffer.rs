@@ -0,0 +1,370 @@
+//! Fixed capacity FIFO queue
+//!
+//! Holds up to `N` items of any `Copy` type with no allocation, keeping a
+//! start index and a length so every slot is usable. It only depends on
+//! `core`, so it builds and is tested on the host as well as the board.
+
+use core::mem::MaybeUninit;
+
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buffer: [MaybeUninit<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    pub const fn new() -> Self {
+        RingBuffer { buffer: [MaybeUninit::uninit(); N], head: 0, len: 0 }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    fn slot(&self, i: usize) -> usize {
+        (self.head + i) % N
+    }
+
+    fn read(&self, i: usize) -> T {
+        // every slot from head for len items has been written
+        unsafe { self.buffer[self.slot(i)].assume_init() }
+    }
+
+    /// Add `item` at the back, handing it back if the queue is full
+    pub fn put(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(item);
+        }
+        let slot = self.slot(self.len);
+        self.buffer[slot] = MaybeUninit::new(item);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Add `item` at the back, dropping and returning the oldest item if
+    /// the queue is full
+    pub fn put_overwrite(&mut self, item: T) -> Option<T> {
+        let dropped = if self.is_full() { self.get() } else { None };
+        let _ = self.put(item);
+        dropped
+    }
+
+    /// Remove the item at the front
+    pub fn get(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let item = self.read(0);
+        self.head = self.slot(1);
+        self.len -= 1;
+        Some(item)
+    }
+
+    /// The item `get` would return, left in place
+    pub fn peek(&self) -> Option<T> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.read(0))
+        }
+    }
+
+    /// Add as many of `items` as fit, returning how many were taken
+    pub fn push_slice(&mut self, items: &[T]) -> usize {
+        let count = items.len().min(N - self.len);
+        for item in &items[..count] {
+            let _ = self.put(*item);
+        }
+        count
+    }
+
+    /// Move items from the front into `out`, returning how many were moved
+    pub fn pop_into(&mut self, out: &mut [T]) -> usize {
+        let count = out.len().min(self.len);
+        if count == 0 {
+            return 0;
+        }
+        for (i, o) in out[..count].iter_mut().enumerate() {
+            *o = self.read(i);
+        }
+        self.head = self.slot(count);
+        self.len -= count;
+        count
+    }
+
+    /// Items front to back, left in place
+    pub fn iter(&self) -> Iter<'_, T, N> {
+        Iter { ring: self, index: 0 }
+    }
+
+    /// Items front to back, removed as they are returned
+    pub fn drain(&mut self) -> Drain<'_, T, N> {
+        Drain { ring: self }
+    }
+}
+
+impl<T: Copy, const N: usize> Default for RingBuffer<T, N> {
+    fn default() -> Self {
+        RingBuffer::new()
+    }
+}
+
+impl<'a, T: Copy, const N: usize> IntoIterator for &'a RingBuffer<T, N> {
+    type Item = T;
+    type IntoIter = Iter<'a, T, N>;
+
+    fn into_iter(self) -> Iter<'a, T, N> {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, T: Copy, const N: usize> {
+    ring: &'a RingBuffer<T, N>,
+    index: usize,
+}
+
+impl<'a, T: Copy, const N: usize> Iterator for Iter<'a, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.index == self.ring.len {
+            return None;
+        }
+        let item = self.ring.read(self.index);
+        self.index += 1;
+        Some(item)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let left = self.ring.len - self.index;
+        (left, Some(left))
+    }
+}
+
+impl<'a, T: Copy, const N: usize> ExactSizeIterator for Iter<'a, T, N> {}
+
+pub struct Drain<'a, T: Copy, const N: usize> {
+    ring: &'a mut RingBuffer<T, N>,
+}
+
+impl<'a, T: Copy, const N: usize> Iterator for Drain<'a, T, N> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.ring.get()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.ring.len, Some(self.ring.len))
+    }
+}
+
+impl<'a, T: Copy, const N: usize> ExactSizeIterator for Drain<'a, T, N> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn starts_empty() {
+        let mut ring: RingBuffer<u8, 4> = RingBuffer::new();
+        assert!(ring.is_empty());
+        assert!(!ring.is_full());
+        assert_eq!(ring.len(), 0);
+        assert_eq!(ring.capacity(), 4);
+        assert_eq!(ring.peek(), None);
+        assert_eq!(ring.get(), None);
+        assert_eq!(ring.iter().count(), 0);
+    }
+
+    #[test]
+    fn full_hands_the_item_back() {
+        let mut ring: RingBuffer<u8, 3> = RingBuffer::new();
+        for i in 0..3 {
+            assert_eq!(ring.put(i), Ok(()));
+        }
+        assert!(ring.is_full());
+        assert_eq!(ring.put(9), Err(9));
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.get(), Some(0));
+        assert_eq!(ring.put(9), Ok(()));
+        assert_eq!(ring.drain().collect::<Vec<_>>(), [1, 2, 9]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut ring: RingBuffer<u32, 4> = RingBuffer::new();
+        // walk the head round the buffer several times
+        for i in 0..20 {
+            ring.put(i).unwrap();
+            ring.put(i + 100).unwrap();
+            assert_eq!(ring.get(), Some(i));
+            assert_eq!(ring.peek(), Some(i + 100));
+            assert_eq!(ring.get(), Some(i + 100));
+        }
+        for i in 0..4 {
+            ring.put(i).unwrap();
+        }
+        ring.get();
+        ring.put(4).unwrap();
+        assert_eq!(ring.iter().collect::<Vec<_>>(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn put_overwrite_drops_the_oldest() {
+        let mut ring: RingBuffer<u8, 2> = RingBuffer::new();
+        assert_eq!(ring.put_overwrite(1), None);
+        assert_eq!(ring.put_overwrite(2), None);
+        assert_eq!(ring.put_overwrite(3), Some(1));
+        assert_eq!(ring.put_overwrite(4), Some(2));
+        assert_eq!(ring.iter().collect::<Vec<_>>(), [3, 4]);
+    }
+
+    #[test]
+    fn push_slice_takes_what_fits() {
+        let mut ring: RingBuffer<u8, 5> = RingBuffer::new();
+        assert_eq!(ring.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(ring.push_slice(&[4, 5, 6, 7]), 2);
+        assert_eq!(ring.push_slice(&[8]), 0);
+        assert_eq!(ring.iter().collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn pop_into_across_the_end() {
+        let mut ring: RingBuffer<u8, 4> = RingBuffer::new();
+        ring.push_slice(&[1, 2, 3]);
+        let mut out = [0; 2];
+        assert_eq!(ring.pop_into(&mut out), 2);
+        assert_eq!(out, [1, 2]);
+        // 3 is in the last slot, 4 to 6 wrap to the front
+        assert_eq!(ring.push_slice(&[4, 5, 6]), 3);
+        let mut out = [0; 8];
+        assert_eq!(ring.pop_into(&mut out), 4);
+        assert_eq!(out[..4], [3, 4, 5, 6]);
+        assert_eq!(ring.pop_into(&mut out), 0);
+        assert_eq!(ring.pop_into(&mut []), 0);
+    }
+
+    #[test]
+    fn iterators() {
+        let mut ring: RingBuffer<u8, 4> = RingBuffer::new();
+        ring.push_slice(&[1, 2, 3, 4]);
+        ring.get();
+        ring.put(5).unwrap();
+        let mut iter = ring.iter();
+        assert_eq!(iter.len(), 4);
+        iter.next();
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        assert_eq!((&ring).into_iter().collect::<Vec<_>>(), [2, 3, 4, 5]);
+        // iterating leaves the items, draining takes them
+        assert_eq!(ring.len(), 4);
+        let mut drain = ring.drain();
+        assert_eq!(drain.len(), 4);
+        assert_eq!(drain.next(), Some(2));
+        assert_eq!(drain.size_hint(), (3, Some(3)));
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.drain().collect::<Vec<_>>(), [3, 4, 5]);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn clear_empties() {
+        let mut ring: RingBuffer<u8, 3> = RingBuffer::new();
+        ring.push_slice(&[1, 2, 3]);
+        ring.clear();
+        assert!(ring.is_empty());
+        assert_eq!(ring.push_slice(&[4, 5, 6]), 3);
+        assert_eq!(ring.get(), Some(4));
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Put(u8),
+        PutOverwrite(u8),
+        Get,
+        PushSlice(Vec<u8>),
+        PopInto(usize),
+        Clear,
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => any::<u8>().prop_map(Op::Put),
+            1 => any::<u8>().prop_map(Op::PutOverwrite),
+            3 => Just(Op::Get),
+            2 => proptest::collection::vec(any::<u8>(), 0..10).prop_map(Op::PushSlice),
+            2 => (0..10usize).prop_map(Op::PopInto),
+            1 => Just(Op::Clear),
+        ]
+    }
+
+    proptest! {
+        /// Any sequence of operations does what a `VecDeque` capped at `N`
+        /// would
+        #[test]
+        fn behaves_like_a_bounded_deque(ops in proptest::collection::vec(op(), 0..200)) {
+            const N: usize = 7;
+            let mut ring: RingBuffer<u8, N> = RingBuffer::new();
+            let mut model: VecDeque<u8> = VecDeque::new();
+            for op in ops {
+                match op {
+                    Op::Put(x) => {
+                        let expected = if model.len() < N {
+                            model.push_back(x);
+                            Ok(())
+                        } else {
+                            Err(x)
+                        };
+                        prop_assert_eq!(ring.put(x), expected);
+                    }
+                    Op::PutOverwrite(x) => {
+                        let dropped = if model.len() == N { model.pop_front() } else { None };
+                        model.push_back(x);
+                        prop_assert_eq!(ring.put_overwrite(x), dropped);
+                    }
+                    Op::Get => prop_assert_eq!(ring.get(), model.pop_front()),
+                    Op::PushSlice(items) => {
+                        let taken = items.len().min(N - model.len());
+                        model.extend(&items[..taken]);
+                        prop_assert_eq!(ring.push_slice(&items), taken);
+                    }
+                    Op::PopInto(len) => {
+                        let mut out = vec![0; len];
+                        let count = ring.pop_into(&mut out);
+                        let expected: Vec<u8> = model.drain(..len.min(model.len())).collect();
+                        prop_assert_eq!(&out[..count], &expected[..]);
+                    }
+                    Op::Clear => {
+                        ring.clear();
+                        model.clear();
+                    }
+                }
+                prop_assert_eq!(ring.len(), model.len());
+                prop_assert_eq!(ring.is_empty(), model.is_empty());
+                prop_assert_eq!(ring.is_full(), model.len() == N);
+                prop_assert_eq!(ring.peek(), model.front().copied());
+                prop_assert!(ring.iter().eq(model.iter().copied()));
+            }
+        }
+    }
+}
@@ -0,0 +1,503 @@
+//! Command shell for a serial console
+//!
+//! A `Shell` holds a table of `Command`s registered at startup, each with a
+//! name, a line of help, a description of its arguments and a handler.
+//! `Shell::run` takes a line from `line_editor::LineEditor`, splits it into
+//! words, finds the command and checks and converts its arguments against
+//! the description before calling the handler, so handlers never see
+//! malformed input. `help` is always there and is built from the table.
+//!
+//! Handlers act on an application state `C` and write to any
+//! `core::fmt::Write`; the shell itself needs nothing but `core`.
+
+use core::fmt::{self, Write};
+
+/// Most arguments a command can take
+pub const MAX_ARGS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArgKind {
+    /// Decimal integer from `min` to `max` inclusive
+    Int { min: i32, max: i32 },
+    /// One of the words, passed on as its index
+    Choice(&'static [&'static str]),
+    /// Any word, for the handler to check
+    Word,
+}
+
+/// One argument a command takes, as shown by `help`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    /// May be left out; only trailing arguments should be
+    pub optional: bool,
+}
+
+impl Arg {
+    pub const fn int(name: &'static str, min: i32, max: i32) -> Arg {
+        Arg { name, kind: ArgKind::Int { min, max }, optional: false }
+    }
+
+    pub const fn choice(name: &'static str, choices: &'static [&'static str]) -> Arg {
+        Arg { name, kind: ArgKind::Choice(choices), optional: false }
+    }
+
+    pub const fn word(name: &'static str) -> Arg {
+        Arg { name, kind: ArgKind::Word, optional: false }
+    }
+
+    pub const fn optional(self) -> Arg {
+        Arg { optional: true, ..self }
+    }
+
+    fn parse<'a>(&self, word: &'a str) -> Option<Value<'a>> {
+        match self.kind {
+            ArgKind::Int { min, max } => match word.parse::<i32>() {
+                Ok(n) if n >= min && n <= max => Some(Value::Int(n)),
+                _ => None,
+            },
+            ArgKind::Choice(choices) => {
+                choices.iter().position(|c| *c == word).map(Value::Choice)
+            }
+            ArgKind::Word => Some(Value::Word(word)),
+        }
+    }
+
+    /// What the argument should be, for error messages
+    fn expected(&self, out: &mut dyn Write) -> fmt::Result {
+        match self.kind {
+            ArgKind::Int { min, max } => write!(out, "{} to {}", min, max),
+            ArgKind::Choice(choices) => write_choices(out, choices),
+            ArgKind::Word => out.write_str("a word"),
+        }
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.optional {
+            f.write_char('[')?;
+        }
+        match self.kind {
+            ArgKind::Choice(choices) => write_choices(f, choices)?,
+            _ => write!(f, "<{}>", self.name)?,
+        }
+        if self.optional {
+            f.write_char(']')?;
+        }
+        Ok(())
+    }
+}
+
+fn write_choices(out: &mut dyn Write, choices: &[&str]) -> fmt::Result {
+    for (i, choice) in choices.iter().enumerate() {
+        if i > 0 {
+            out.write_char('|')?;
+        }
+        out.write_str(choice)?;
+    }
+    Ok(())
+}
+
+/// A parsed argument, in the same position as its `Arg`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Value<'a> {
+    Int(i32),
+    Choice(usize),
+    Word(&'a str),
+    /// Optional argument left out
+    Missing,
+}
+
+impl<'a> Value<'a> {
+    pub fn int(self) -> Option<i32> {
+        if let Value::Int(n) = self { Some(n) } else { None }
+    }
+
+    pub fn choice(self) -> Option<usize> {
+        if let Value::Choice(i) = self { Some(i) } else { None }
+    }
+
+    pub fn word(self) -> Option<&'a str> {
+        if let Value::Word(w) = self { Some(w) } else { None }
+    }
+}
+
+/// Handler for a command, given its arguments in order; an `Err` message
+/// is printed after the command name
+pub type Handler<C> = fn(&mut C, &[Value<'_>], &mut dyn Write) -> Result<(), &'static str>;
+
+pub struct Command<C> {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub args: &'static [Arg],
+    pub run: Handler<C>,
+}
+
+// not derived, which would want `C: Copy`
+impl<C> Clone for Command<C> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<C> Copy for Command<C> {}
+
+impl<C> Command<C> {
+    /// Name and arguments, as `help` shows them
+    pub fn usage(&self, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(self.name)?;
+        for arg in self.args {
+            write!(out, " {}", arg)?;
+        }
+        Ok(())
+    }
+
+    fn usage_len(&self) -> usize {
+        let mut counter = Counter(0);
+        self.usage(&mut counter).ok();
+        counter.0
+    }
+}
+
+/// Counts what would be written
+struct Counter(usize);
+
+impl Write for Counter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 += s.len();
+        Ok(())
+    }
+}
+
+/// Why a line didn't run, already reported to the terminal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error<'a> {
+    Unknown(&'a str),
+    /// Missing, extra or invalid arguments
+    Usage,
+    /// The handler's message
+    Failed(&'static str),
+}
+
+/// Up to `M` commands besides `help`
+pub struct Shell<C, const M: usize> {
+    commands: [Option<Command<C>>; M],
+    count: usize,
+}
+
+impl<C, const M: usize> Shell<C, M> {
+    pub const fn new() -> Self {
+        Shell { commands: [None; M], count: 0 }
+    }
+
+    /// Add `command`, handing it back if the table is full, the name is
+    /// taken or it has more than `MAX_ARGS` arguments
+    pub fn register(&mut self, command: Command<C>) -> Result<(), Command<C>> {
+        if self.count == M
+            || command.name == "help"
+            || command.args.len() > MAX_ARGS
+            || self.find(command.name).is_some()
+        {
+            return Err(command);
+        }
+        self.commands[self.count] = Some(command);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// `register` each of `commands`, stopping at the first that doesn't fit
+    pub fn register_all(&mut self, commands: &[Command<C>]) -> Result<(), Command<C>> {
+        for command in commands {
+            self.register(*command)?;
+        }
+        Ok(())
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &Command<C>> {
+        self.commands[..self.count].iter().flatten()
+    }
+
+    pub fn find(&self, name: &str) -> Option<&Command<C>> {
+        self.commands().find(|c| c.name == name)
+    }
+
+    /// Run one line; empty lines do nothing. Errors are printed to `out`
+    /// as well as returned
+    pub fn run<'a>(&self, line: &'a str, context: &mut C,
+                   out: &mut impl Write) -> Result<(), Error<'a>> {
+        let mut words = line.split_ascii_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(()),
+        };
+        if name == "help" {
+            return self.help(words.next(), out);
+        }
+        let command = match self.find(name) {
+            Some(command) => command,
+            None => {
+                write!(out, "unknown command \"{}\", try help\r\n", name).ok();
+                return Err(Error::Unknown(name));
+            }
+        };
+
+        let mut values = [Value::Missing; MAX_ARGS];
+        let args = &command.args[..command.args.len().min(MAX_ARGS)];
+        for (arg, value) in args.iter().zip(values.iter_mut()) {
+            match words.next().map(|word| arg.parse(word)) {
+                Some(Some(parsed)) => *value = parsed,
+                Some(None) => {
+                    write!(out, "{}: <{}> must be ", name, arg.name).ok();
+                    arg.expected(out).ok();
+                    return self.usage_error(command, out);
+                }
+                None if arg.optional => (),
+                None => {
+                    write!(out, "{}: missing <{}>", name, arg.name).ok();
+                    return self.usage_error(command, out);
+                }
+            }
+        }
+        if words.next().is_some() {
+            write!(out, "{}: too many arguments", name).ok();
+            return self.usage_error(command, out);
+        }
+
+        (command.run)(context, &values[..args.len()], out).map_err(|message| {
+            write!(out, "{}: {}\r\n", name, message).ok();
+            Error::Failed(message)
+        })
+    }
+
+    fn usage_error<'a>(&self, command: &Command<C>, out: &mut impl Write) -> Result<(), Error<'a>> {
+        out.write_str("\r\nusage: ").ok();
+        command.usage(out).ok();
+        out.write_str("\r\n").ok();
+        Err(Error::Usage)
+    }
+
+    /// List the commands, or describe one
+    fn help<'a>(&self, name: Option<&'a str>, out: &mut impl Write) -> Result<(), Error<'a>> {
+        if let Some(name) = name {
+            let command = match self.find(name) {
+                Some(command) => command,
+                None => {
+                    write!(out, "help: no command \"{}\"\r\n", name).ok();
+                    return Err(Error::Unknown(name));
+                }
+            };
+            command.usage(out).ok();
+            write!(out, "\r\n  {}\r\n", command.help).ok();
+            return Ok(());
+        }
+        let width = self.commands().map(|c| c.usage_len()).max().unwrap_or(0);
+        for command in self.commands() {
+            out.write_str("  ").ok();
+            command.usage(out).ok();
+            for _ in command.usage_len()..width + 2 {
+                out.write_char(' ').ok();
+            }
+            write!(out, "{}\r\n", command.help).ok();
+        }
+        write!(out, "  help [<command>]\r\n").ok();
+        Ok(())
+    }
+}
+
+impl<C, const M: usize> Default for Shell<C, M> {
+    fn default() -> Self {
+        Shell::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What the handlers were given
+    #[derive(Default)]
+    struct Calls {
+        led: Option<(i32, usize)>,
+        name: Option<String>,
+        count: Option<Option<i32>>,
+    }
+
+    const ON_OFF: &[&str] = &["on", "off"];
+    const LED_ARGS: &[Arg] = &[Arg::int("index", -1, 7), Arg::choice("state", ON_OFF)];
+    const NAME_ARGS: &[Arg] = &[Arg::word("who")];
+    const COUNT_ARGS: &[Arg] = &[Arg::int("n", 0, 10).optional()];
+
+    fn led(calls: &mut Calls, args: &[Value<'_>], _: &mut dyn Write) -> Result<(), &'static str> {
+        calls.led = Some((args[0].int().unwrap(), args[1].choice().unwrap()));
+        Ok(())
+    }
+
+    fn name(calls: &mut Calls, args: &[Value<'_>], out: &mut dyn Write) -> Result<(), &'static str> {
+        let word = args[0].word().unwrap();
+        if word == "bad" {
+            return Err("not that one");
+        }
+        calls.name = Some(word.to_string());
+        write!(out, "hello {}\r\n", word).ok();
+        Ok(())
+    }
+
+    fn count(calls: &mut Calls, args: &[Value<'_>], _: &mut dyn Write) -> Result<(), &'static str> {
+        calls.count = Some(args[0].int());
+        Ok(())
+    }
+
+    fn shell() -> Shell<Calls, 3> {
+        let mut shell = Shell::new();
+        shell
+            .register_all(&[
+                Command {
+                    name: "led",
+                    help: "turn an LED on or off",
+                    args: LED_ARGS,
+                    run: led,
+                },
+                Command { name: "name", help: "say hello", args: NAME_ARGS, run: name },
+                Command { name: "count", help: "count", args: COUNT_ARGS, run: count },
+            ])
+            .ok()
+            .unwrap();
+        shell
+    }
+
+    fn run<'a>(shell: &Shell<Calls, 3>, line: &'a str, calls: &mut Calls) -> (Result<(), Error<'a>>, String) {
+        let mut out = String::new();
+        let result = shell.run(line, calls, &mut out);
+        (result, out)
+    }
+
+    #[test]
+    fn runs_commands() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        assert_eq!(run(&shell, "  led  3 off ", &mut calls), (Ok(()), String::new()));
+        assert_eq!(calls.led, Some((3, 1)));
+        assert_eq!(run(&shell, "name\tworld", &mut calls), (Ok(()), "hello world\r\n".to_string()));
+        assert_eq!(calls.name.as_deref(), Some("world"));
+        // empty lines do nothing
+        assert_eq!(run(&shell, "   ", &mut calls), (Ok(()), String::new()));
+    }
+
+    #[test]
+    fn optional_arguments() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        run(&shell, "count", &mut calls).0.unwrap();
+        assert_eq!(calls.count, Some(None));
+        run(&shell, "count 4", &mut calls).0.unwrap();
+        assert_eq!(calls.count, Some(Some(4)));
+    }
+
+    #[test]
+    fn argument_counts() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        assert_eq!(
+            run(&shell, "led 3", &mut calls),
+            (Err(Error::Usage), "led: missing <state>\r\nusage: led <index> on|off\r\n".to_string())
+        );
+        assert_eq!(
+            run(&shell, "led", &mut calls),
+            (Err(Error::Usage), "led: missing <index>\r\nusage: led <index> on|off\r\n".to_string())
+        );
+        assert_eq!(
+            run(&shell, "count 1 2", &mut calls),
+            (Err(Error::Usage), "count: too many arguments\r\nusage: count [<n>]\r\n".to_string())
+        );
+        assert_eq!(calls.led, None);
+        assert_eq!(calls.count, None);
+    }
+
+    #[test]
+    fn integer_range() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        for line in ["led -1 on", "led 7 on"] {
+            run(&shell, line, &mut calls).0.unwrap();
+        }
+        assert_eq!(calls.led, Some((7, 0)));
+        for line in ["led -2 on", "led 8 on", "led x on", "led 1.5 on", "led 99999999999 on"] {
+            let (result, out) = run(&shell, line, &mut calls);
+            assert_eq!(result, Err(Error::Usage), "{}", line);
+            assert!(out.starts_with("led: <index> must be -1 to 7\r\n"), "{}", out);
+        }
+        assert_eq!(calls.led, Some((7, 0)));
+    }
+
+    #[test]
+    fn choices_match_whole_words() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        for line in ["led 1 o", "led 1 ON", "led 1 offf", "led 1 on|off"] {
+            let (result, out) = run(&shell, line, &mut calls);
+            assert_eq!(result, Err(Error::Usage), "{}", line);
+            assert!(out.starts_with("led: <state> must be on|off\r\n"), "{}", out);
+        }
+        assert_eq!(calls.led, None);
+    }
+
+    #[test]
+    fn unknown_commands_and_failures() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        assert_eq!(
+            run(&shell, "leds 1 on", &mut calls),
+            (Err(Error::Unknown("leds")), "unknown command \"leds\", try help\r\n".to_string())
+        );
+        assert_eq!(
+            run(&shell, "name bad", &mut calls),
+            (Err(Error::Failed("not that one")), "name: not that one\r\n".to_string())
+        );
+    }
+
+    #[test]
+    fn help() {
+        let shell = shell();
+        let mut calls = Calls::default();
+        let (result, out) = run(&shell, "help", &mut calls);
+        result.unwrap();
+        assert_eq!(
+            out,
+            "  led <index> on|off  turn an LED on or off\r\n\
+             \x20 name <who>          say hello\r\n\
+             \x20 count [<n>]         count\r\n\
+             \x20 help [<command>]\r\n"
+        );
+        assert_eq!(
+            run(&shell, "help led", &mut calls),
+            (Ok(()), "led <index> on|off\r\n  turn an LED on or off\r\n".to_string())
+        );
+        assert_eq!(
+            run(&shell, "help nope", &mut calls),
+            (Err(Error::Unknown("nope")), "help: no command \"nope\"\r\n".to_string())
+        );
+    }
+
+    #[test]
+    fn registration() {
+        let mut shell = shell();
+        let extra = Command { name: "extra", help: "", args: &[], run: count };
+        // full
+        assert!(shell.register(extra).is_err());
+        let mut shell: Shell<Calls, 4> = Shell::new();
+        // help is taken, and so is a name already registered
+        assert!(shell.register(Command { name: "help", ..extra }).is_err());
+        assert!(shell.register(extra).is_ok());
+        assert!(shell.register(extra).is_err());
+        // handlers only ever see `MAX_ARGS` values
+        const FIVE_ARGS: &[Arg] = &[Arg::word("a"); MAX_ARGS + 1];
+        let many = Command { name: "many", args: FIVE_ARGS, ..extra };
+        assert!(shell.register(many).is_err());
+        assert!(shell.register(Command { args: &FIVE_ARGS[..MAX_ARGS], ..many }).is_ok());
+        assert_eq!(shell.commands().count(), 2);
+        assert!(shell.find("extra").is_some());
+        assert!(shell.find("ext").is_none());
+    }
+}
@@ -0,0 +1,700 @@
+//! Lock-free single producer, single consumer queue
+//!
+//! `Queue::split` hands out a `Producer` and a `Consumer` that can live in
+//! different contexts, say a task and an interrupt handler, and be used
+//! from both at once with no critical section. Each half only writes its
+//! own index; the other side reads it with acquire ordering, so an item is
+//! always in the buffer before the consumer can see it.
+//!
+//! The indices count modulo `2 * N`, which tells a full queue from an empty
+//! one without giving up a slot. Like `RingBuffer` it only needs `core`.
+//!
+//! Two extras serve transmit queues. The producer can stage items and
+//! publish them all at once with `commit`, or `abandon` them, so a message
+//! goes out whole or not at all. It can also ask for the oldest items to
+//! be thrown away with `request_discard`; the consumer does that the next
+//! time it looks at the front of the queue. The request is kept as an
+//! index, not a count, so it only ever reaches items that were queued when
+//! it was made: any of those the consumer has sent meanwhile, say by DMA,
+//! count towards it, and later ones are left alone. The consumer counts
+//! what it really drops, which the producer reads back with `discarded`.
+//!
+//! Every consumer method that may act on a discard takes `&mut self`, so
+//! a slice from `contiguous` can't still be in use when its slots are
+//! given back to the producer.
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub struct Queue<T: Copy, const N: usize> {
+    buffer: UnsafeCell<[MaybeUninit<T>; N]>,
+    /// Next slot to read, only written by the consumer
+    head: AtomicUsize,
+    /// Next slot to write, only written by the producer
+    tail: AtomicUsize,
+    /// Index the producer wants everything before dropped, or `NO_DISCARD`;
+    /// taken by the consumer as it drops them
+    discard_to: AtomicUsize,
+    /// Items dropped by request so far, wrapping, only written by the
+    /// consumer
+    discarded: AtomicUsize,
+}
+
+/// `discard_to` when no discard has been asked for
+const NO_DISCARD: usize = usize::MAX;
+
+impl<T: Copy, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        assert!(N > 0, "queue needs at least one slot");
+        Queue {
+            buffer: UnsafeCell::new([MaybeUninit::uninit(); N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            discard_to: AtomicUsize::new(NO_DISCARD),
+            discarded: AtomicUsize::new(0),
+        }
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Split into the two ends; borrowing `self` mutably for as long as
+    /// they live guarantees there is only one of each
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (
+            Producer { queue: self, staged: 0, _not_sync: PhantomData },
+            Consumer { queue: self, _not_sync: PhantomData },
+        )
+    }
+
+    fn len(head: usize, tail: usize) -> usize {
+        (tail + 2 * N - head) % (2 * N)
+    }
+
+    fn advance(index: usize, by: usize) -> usize {
+        (index + by) % (2 * N)
+    }
+
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        let buffer = self.buffer.get() as *mut MaybeUninit<T>;
+        unsafe { buffer.add(index % N) }
+    }
+}
+
+impl<T: Copy, const N: usize> Default for Queue<T, N> {
+    fn default() -> Self {
+        Queue::new()
+    }
+}
+
+/// Writing end of a `Queue`
+pub struct Producer<'a, T: Copy, const N: usize> {
+    queue: &'a Queue<T, N>,
+    /// Items written after `tail` but not yet published
+    staged: usize,
+    _not_sync: PhantomData<*const ()>,
+}
+
+// only the producer writes `tail` and the free slots
+unsafe impl<'a, T: Copy + Send, const N: usize> Send for Producer<'a, T, N> {}
+
+impl<'a, T: Copy, const N: usize> Producer<'a, T, N> {
+    pub fn len(&self) -> usize {
+        let head = self.queue.head.load(Ordering::Acquire);
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        Queue::<T, N>::len(head, tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Add `item` at the back, handing it back if the queue is full
+    pub fn put(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(item);
+        }
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        unsafe { self.queue.slot(tail).write(MaybeUninit::new(item)) };
+        self.queue.tail.store(Queue::<T, N>::advance(tail, 1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Add as many of `items` as fit, returning how many were taken
+    pub fn push_slice(&mut self, items: &[T]) -> usize {
+        let count = items.len().min(N - self.len());
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        for (i, item) in items[..count].iter().enumerate() {
+            let index = Queue::<T, N>::advance(tail, i);
+            unsafe { self.queue.slot(index).write(MaybeUninit::new(*item)) };
+        }
+        self.queue.tail.store(Queue::<T, N>::advance(tail, count), Ordering::Release);
+        count
+    }
+
+    /// Slots neither queued nor staged
+    pub fn free(&self) -> usize {
+        N - self.len() - self.staged
+    }
+
+    /// Write `items` after anything already staged without letting the
+    /// consumer see them, or nothing at all if they don't fit. Staged items
+    /// must be committed or abandoned before the next `put` or `push_slice`
+    pub fn stage(&mut self, items: &[T]) -> bool {
+        if items.len() > self.free() {
+            return false;
+        }
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        for (i, item) in items.iter().enumerate() {
+            let index = Queue::<T, N>::advance(tail, self.staged + i);
+            unsafe { self.queue.slot(index).write(MaybeUninit::new(*item)) };
+        }
+        self.staged += items.len();
+        true
+    }
+
+    /// Hand everything staged to the consumer
+    pub fn commit(&mut self) {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        self.queue.tail.store(Queue::<T, N>::advance(tail, self.staged), Ordering::Release);
+        self.staged = 0;
+    }
+
+    /// Forget everything staged
+    pub fn abandon(&mut self) {
+        self.staged = 0;
+    }
+
+    /// Items the consumer has dropped for `request_discard` so far,
+    /// wrapping; less than was asked for when it had already taken some
+    pub fn discarded(&self) -> usize {
+        self.queue.discarded.load(Ordering::Acquire)
+    }
+
+    /// Ask the consumer to drop the oldest `count` of the items queued now,
+    /// the next time it calls `peek`, `get`, `pop_into` or `contiguous`.
+    /// Items it takes in between count towards `count`, and items queued
+    /// or staged after this call are never dropped
+    pub fn request_discard(&mut self, count: usize) {
+        let head = self.queue.head.load(Ordering::Acquire);
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        let count = count.min(Queue::<T, N>::len(head, tail));
+        if count == 0 {
+            return;
+        }
+        let to = Queue::<T, N>::advance(head, count);
+        let mut pending = self.queue.discard_to.load(Ordering::Acquire);
+        loop {
+            // an earlier request still waiting may reach further
+            if pending != NO_DISCARD {
+                let reach = Queue::<T, N>::len(head, pending);
+                if reach > count && reach <= Queue::<T, N>::len(head, tail) {
+                    return;
+                }
+            }
+            // fails only if the consumer took the earlier request meanwhile
+            match self.queue.discard_to.compare_exchange(
+                    pending, to, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => return,
+                Err(now) => pending = now,
+            }
+        }
+    }
+}
+
+/// Reading end of a `Queue`
+pub struct Consumer<'a, T: Copy, const N: usize> {
+    queue: &'a Queue<T, N>,
+    _not_sync: PhantomData<*const ()>,
+}
+
+// only the consumer writes `head` and reads the filled slots
+unsafe impl<'a, T: Copy + Send, const N: usize> Send for Consumer<'a, T, N> {}
+
+impl<'a, T: Copy, const N: usize> Consumer<'a, T, N> {
+    pub fn len(&self) -> usize {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let tail = self.queue.tail.load(Ordering::Acquire);
+        Queue::<T, N>::len(head, tail)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Act on `request_discard`
+    fn discard_requested(&mut self) {
+        let to = self.queue.discard_to.swap(NO_DISCARD, Ordering::AcqRel);
+        if to == NO_DISCARD {
+            return;
+        }
+        // `to` is behind `head`, and seems further than the back of the
+        // queue, once the items it covers have all been consumed
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let count = Queue::<T, N>::len(head, to);
+        if count <= self.len() {
+            self.queue.head.store(to, Ordering::Release);
+            let discarded = self.queue.discarded.load(Ordering::Relaxed);
+            self.queue.discarded.store(discarded.wrapping_add(count), Ordering::Release);
+        }
+    }
+
+    /// The item `get` would return, left in place
+    pub fn peek(&mut self) -> Option<T> {
+        self.discard_requested();
+        if self.is_empty() {
+            return None;
+        }
+        let head = self.queue.head.load(Ordering::Relaxed);
+        Some(unsafe { (*self.queue.slot(head)).assume_init() })
+    }
+
+    /// Remove the item at the front
+    pub fn get(&mut self) -> Option<T> {
+        let item = self.peek()?;
+        let head = self.queue.head.load(Ordering::Relaxed);
+        self.queue.head.store(Queue::<T, N>::advance(head, 1), Ordering::Release);
+        Some(item)
+    }
+
+    /// Move items from the front into `out`, returning how many were moved
+    pub fn pop_into(&mut self, out: &mut [T]) -> usize {
+        self.discard_requested();
+        let count = out.len().min(self.len());
+        let head = self.queue.head.load(Ordering::Relaxed);
+        for (i, o) in out[..count].iter_mut().enumerate() {
+            let index = Queue::<T, N>::advance(head, i);
+            *o = unsafe { (*self.queue.slot(index)).assume_init() };
+        }
+        self.queue.head.store(Queue::<T, N>::advance(head, count), Ordering::Release);
+        count
+    }
+
+    /// The items from the front up to the back of the queue or the end of
+    /// the buffer, whichever comes first, left in place until `consume`;
+    /// for handing straight to a DMA channel. The slice has to go before
+    /// anything else can drop the items under it:
+    ///
+    /// ```compile_fail
+    /// # use stm32f3disc_logic::spsc::Queue;
+    /// let mut queue: Queue<u8, 4> = Queue::new();
+    /// let (mut p, mut c) = queue.split();
+    /// p.push_slice(&[1, 2]);
+    /// let block = c.contiguous();
+    /// p.request_discard(2);
+    /// c.peek();   // would hand `block`'s slots back to the producer
+    /// p.push_slice(&[3, 4, 5, 6]);
+    /// assert_eq!(block, [1, 2]);
+    /// ```
+    pub fn contiguous(&mut self) -> &[T] {
+        self.discard_requested();
+        let len = self.len();
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let count = len.min(N - head % N);
+        // the producer won't touch these slots until `head` moves past them
+        unsafe { core::slice::from_raw_parts(self.queue.slot(head) as *const T, count) }
+    }
+
+    /// Drop `count` items from the front, as many as `contiguous` returned
+    /// at most
+    pub fn consume(&mut self, count: usize) {
+        let count = count.min(self.len());
+        let head = self.queue.head.load(Ordering::Relaxed);
+        self.queue.head.store(Queue::<T, N>::advance(head, count), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use proptest::prelude::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn full_and_empty_use_every_slot() {
+        let mut queue: Queue<u8, 4> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        assert!(p.is_empty() && c.is_empty());
+        assert_eq!(c.peek(), None);
+        for i in 0..4 {
+            assert_eq!(p.put(i), Ok(()));
+        }
+        // head and tail point at the same slot, but the queue is full
+        assert!(p.is_full());
+        assert_eq!(p.len(), 4);
+        assert_eq!(c.len(), 4);
+        assert!(!c.is_empty());
+        assert_eq!(p.put(9), Err(9));
+        assert_eq!(p.free(), 0);
+        for i in 0..4 {
+            assert_eq!(c.get(), Some(i));
+        }
+        assert!(c.is_empty() && p.is_empty() && !p.is_full());
+        assert_eq!(c.get(), None);
+    }
+
+    #[test]
+    fn indices_wrap_at_twice_the_capacity() {
+        let mut queue: Queue<u32, 3> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        // every fill level at every offset, past several wraps of 2N
+        for round in 0..20 {
+            for fill in 0..=3 {
+                for i in 0..fill {
+                    p.put(round * 10 + i).unwrap();
+                }
+                assert_eq!(c.len(), fill as usize);
+                assert_eq!(p.is_full(), fill == 3);
+                for i in 0..fill {
+                    assert_eq!(c.get(), Some(round * 10 + i));
+                }
+                assert!(c.is_empty());
+            }
+            p.put(round).unwrap();
+            assert_eq!(c.get(), Some(round));
+        }
+    }
+
+    #[test]
+    fn push_slice_and_pop_into_take_what_fits() {
+        let mut queue: Queue<u8, 5> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        assert_eq!(p.push_slice(&[1, 2, 3]), 3);
+        assert_eq!(p.push_slice(&[4, 5, 6]), 2);
+        assert_eq!(p.push_slice(&[7]), 0);
+        let mut out = [0; 4];
+        assert_eq!(c.pop_into(&mut out), 4);
+        assert_eq!(out, [1, 2, 3, 4]);
+        // 6 to 9 wrap to the front
+        assert_eq!(p.push_slice(&[6, 7, 8, 9]), 4);
+        let mut out = [0; 8];
+        assert_eq!(c.pop_into(&mut out), 5);
+        assert_eq!(out[..5], [5, 6, 7, 8, 9]);
+        assert_eq!(c.pop_into(&mut out), 0);
+    }
+
+    #[test]
+    fn contiguous_stops_at_the_end_of_the_buffer() {
+        let mut queue: Queue<u8, 4> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        assert_eq!(c.contiguous(), []);
+        p.push_slice(&[1, 2, 3]);
+        c.consume(3);
+        // 4 in the last slot, 5 and 6 wrapped to the front
+        p.push_slice(&[4, 5, 6]);
+        assert_eq!(c.contiguous(), [4]);
+        c.consume(1);
+        assert_eq!(c.contiguous(), [5, 6]);
+        // the second half of the index range, same slots
+        p.push_slice(&[7, 8]);
+        assert_eq!(c.contiguous(), [5, 6, 7, 8]);
+        c.consume(10);
+        assert!(c.is_empty());
+        assert_eq!(c.contiguous(), []);
+    }
+
+    #[test]
+    fn staged_items_stay_hidden_until_committed() {
+        let mut queue: Queue<u8, 4> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        assert!(p.stage(&[1, 2]));
+        assert!(p.stage(&[3]));
+        assert_eq!(p.free(), 1);
+        assert!(!p.stage(&[4, 5]));
+        assert!(c.is_empty());
+        p.commit();
+        assert_eq!(c.len(), 3);
+        assert!(p.stage(&[9]));
+        p.abandon();
+        assert_eq!(p.free(), 1);
+        let mut out = [0; 4];
+        assert_eq!(c.pop_into(&mut out), 3);
+        assert_eq!(out[..3], [1, 2, 3]);
+    }
+
+    #[test]
+    fn across_threads() {
+        let mut queue: Queue<u32, 8> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                for i in 0..10_000 {
+                    while p.put(i).is_err() {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            for i in 0..10_000 {
+                loop {
+                    if let Some(item) = c.get() {
+                        assert_eq!(item, i);
+                        break;
+                    }
+                    std::thread::yield_now();
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn discard_drops_the_oldest() {
+        let mut queue: Queue<u8, 8> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        p.push_slice(&[1, 2, 3, 4, 5]);
+        p.request_discard(2);
+        assert_eq!(c.len(), 5);
+        assert_eq!(c.get(), Some(3));
+        assert_eq!(p.discarded(), 2);
+        // no more than is queued
+        p.request_discard(10);
+        p.push_slice(&[6, 7]);
+        assert_eq!(c.peek(), Some(6));
+        assert_eq!(c.len(), 2);
+        assert_eq!(p.discarded(), 4);
+    }
+
+    #[test]
+    fn discard_while_in_flight_spares_later_items() {
+        let mut queue: Queue<u8, 8> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        p.push_slice(&[1, 2, 3, 4]);
+        // the DMA channel takes all four
+        assert_eq!(c.contiguous(), [1, 2, 3, 4]);
+        // the writer, short of room, asks for the oldest three to go and
+        // queues the next message
+        p.request_discard(3);
+        p.push_slice(&[5, 6, 7]);
+        c.consume(4);
+        // those three went out anyway, the new message is untouched
+        assert_eq!(c.contiguous(), [5, 6, 7]);
+        // and they aren't counted as dropped
+        assert_eq!(p.discarded(), 0);
+    }
+
+    #[test]
+    fn discard_partly_in_flight() {
+        let mut queue: Queue<u8, 8> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        p.push_slice(&[1, 2, 3, 4, 5]);
+        c.consume(5);
+        // 6 to 8 at the end of the buffer, 9 and 10 wrapped round
+        p.push_slice(&[6, 7, 8, 9, 10]);
+        assert_eq!(c.contiguous(), [6, 7, 8]);
+        p.request_discard(4);
+        p.push_slice(&[11]);
+        c.consume(3);
+        // 6 to 8 were sent, 9 is the fourth and goes
+        assert_eq!(c.contiguous(), [10, 11]);
+        assert_eq!(p.discarded(), 1);
+    }
+
+    #[test]
+    fn discard_leaves_staged_items() {
+        let mut queue: Queue<u8, 8> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        p.push_slice(&[1, 2, 3]);
+        assert!(p.stage(&[4, 5]));
+        p.request_discard(5);
+        p.commit();
+        assert_eq!(c.get(), Some(4));
+        assert_eq!(c.len(), 1);
+
+        // abandoned items don't count towards a discard either
+        assert!(p.stage(&[6, 7]));
+        p.abandon();
+        p.push_slice(&[8, 9]);
+        p.request_discard(3);
+        assert!(p.stage(&[10]));
+        p.commit();
+        let mut out = [0; 8];
+        assert_eq!(c.pop_into(&mut out), 1);
+        assert_eq!(out[0], 10);
+    }
+
+    #[test]
+    fn larger_discard_wins() {
+        let mut queue: Queue<u8, 8> = Queue::new();
+        let (mut p, mut c) = queue.split();
+        p.push_slice(&[1, 2, 3, 4, 5, 6]);
+        p.request_discard(4);
+        p.request_discard(1);
+        assert_eq!(c.get(), Some(5));
+        p.request_discard(1);
+        p.request_discard(2);
+        p.put(7).unwrap();
+        assert_eq!(c.get(), Some(7));
+    }
+
+    #[derive(Clone, Debug)]
+    enum Op {
+        Put(u8),
+        Get,
+        Peek,
+        PushSlice(Vec<u8>),
+        PopInto(usize),
+        /// `contiguous`, holding on to what it returned as DMA would
+        Contiguous,
+        /// Done with up to this many of the items from `Contiguous`
+        Consume(usize),
+        Stage(Vec<u8>),
+        Commit,
+        Abandon,
+        Discard(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            4 => any::<u8>().prop_map(Op::Put),
+            3 => Just(Op::Get),
+            1 => Just(Op::Peek),
+            2 => proptest::collection::vec(any::<u8>(), 0..10).prop_map(Op::PushSlice),
+            2 => (0..10usize).prop_map(Op::PopInto),
+            2 => Just(Op::Contiguous),
+            2 => (0..10usize).prop_map(Op::Consume),
+            2 => proptest::collection::vec(any::<u8>(), 0..4).prop_map(Op::Stage),
+            1 => Just(Op::Commit),
+            1 => Just(Op::Abandon),
+            2 => (0..8usize).prop_map(Op::Discard),
+        ]
+    }
+
+    /// The queue as a `VecDeque`, counting items by how many have ever been
+    /// queued so a discard can be kept as a position
+    struct Model {
+        items: VecDeque<u8>,
+        staged: Vec<u8>,
+        /// Items taken off the front so far
+        taken: usize,
+        discard_to: Option<usize>,
+        /// Items dropped by request
+        discarded: usize,
+    }
+
+    impl Model {
+        fn discard_requested(&mut self) {
+            if let Some(to) = self.discard_to.take() {
+                if to > self.taken {
+                    self.items.drain(..to - self.taken);
+                    self.discarded += to - self.taken;
+                    self.taken = to;
+                }
+            }
+        }
+
+        fn take(&mut self, count: usize) -> Vec<u8> {
+            let count = count.min(self.items.len());
+            self.taken += count;
+            self.items.drain(..count).collect()
+        }
+    }
+
+    proptest! {
+        /// Any sequence of operations does what a `VecDeque` capped at `N`
+        /// would, with staging and discards, and with the consumer holding
+        /// items from `contiguous` the way a DMA transfer does
+        #[test]
+        fn behaves_like_a_bounded_deque(ops in proptest::collection::vec(op(), 0..200)) {
+            const N: usize = 5;
+            let mut queue: Queue<u8, N> = Queue::new();
+            let (mut p, mut c) = queue.split();
+            let mut model = Model {
+                items: VecDeque::new(),
+                staged: Vec::new(),
+                taken: 0,
+                discard_to: None,
+                discarded: 0,
+            };
+            let mut in_flight = 0;
+            for op in ops {
+                let staging = !model.staged.is_empty();
+                match op {
+                    // staged items come first
+                    Op::Put(_) | Op::PushSlice(_) if staging => continue,
+                    // only `consume` while a transfer is in flight
+                    Op::Get | Op::Peek | Op::PopInto(_) | Op::Contiguous if in_flight > 0 => continue,
+                    Op::Consume(_) if in_flight == 0 => continue,
+                    Op::Put(x) => {
+                        let expected = if model.items.len() < N {
+                            model.items.push_back(x);
+                            Ok(())
+                        } else {
+                            Err(x)
+                        };
+                        prop_assert_eq!(p.put(x), expected);
+                    }
+                    Op::Get => {
+                        model.discard_requested();
+                        prop_assert_eq!(c.get(), model.take(1).first().copied());
+                    }
+                    Op::Peek => {
+                        model.discard_requested();
+                        prop_assert_eq!(c.peek(), model.items.front().copied());
+                    }
+                    Op::PushSlice(items) => {
+                        let taken = items.len().min(N - model.items.len());
+                        model.items.extend(&items[..taken]);
+                        prop_assert_eq!(p.push_slice(&items), taken);
+                    }
+                    Op::PopInto(len) => {
+                        model.discard_requested();
+                        let mut out = vec![0; len];
+                        let count = c.pop_into(&mut out);
+                        prop_assert_eq!(&out[..count], &model.take(len)[..]);
+                    }
+                    Op::Contiguous => {
+                        model.discard_requested();
+                        let front = c.contiguous();
+                        let to_end = N - model.taken % N;
+                        prop_assert_eq!(front.len(), model.items.len().min(to_end));
+                        prop_assert!(front.iter().eq(model.items.iter().take(front.len())));
+                        in_flight = front.len();
+                    }
+                    Op::Consume(count) => {
+                        let count = count.min(in_flight);
+                        c.consume(count);
+                        model.take(count);
+                        in_flight = 0;
+                    }
+                    Op::Stage(items) => {
+                        let fits = items.len() <= N - model.items.len() - model.staged.len();
+                        if fits {
+                            model.staged.extend(&items);
+                        }
+                        prop_assert_eq!(p.stage(&items), fits);
+                    }
+                    Op::Commit => {
+                        model.items.extend(model.staged.drain(..));
+                        p.commit();
+                    }
+                    Op::Abandon => {
+                        model.staged.clear();
+                        p.abandon();
+                    }
+                    Op::Discard(count) => {
+                        let count = count.min(model.items.len());
+                        if count > 0 {
+                            let to = model.taken + count;
+                            if model.discard_to.is_none_or(|pending| pending <= to) {
+                                model.discard_to = Some(to);
+                            }
+                        }
+                        p.request_discard(count);
+                    }
+                }
+                prop_assert_eq!(p.len(), model.items.len());
+                prop_assert_eq!(c.len(), model.items.len());
+                prop_assert_eq!(p.is_full(), model.items.len() == N);
+                prop_assert_eq!(p.free(), N - model.items.len() - model.staged.len());
+                prop_assert_eq!(p.discarded(), model.discarded);
+            }
+        }
+    }
+}
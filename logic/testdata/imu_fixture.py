@@ -0,0 +1,74 @@
+#!/usr/bin/env python3
+"""Writes imu_fixture.csv, the sensor trace the ahrs tests replay
+
+The trace is synthesised rather than recorded from the board: a recording
+only tells what the filters make of it, while the tests need to know the
+attitude the board really ends in, exactly. At the gyro's 95 Hz the board lies level facing
+magnetic north for 2 s, turns about a single axis for 2 s to roll 30,
+pitch -20, yaw 60 degrees, then stays there for 6 s. The readings are what
+the filters expect: gyro in degrees per second in the board frame, with a
+constant bias and noise; gravity along +z when level, in g; the earth's
+field pointing north and down, in gauss; all with noise. A fixed seed keeps
+the file the same from run to run.
+
+    python3 imu_fixture.py > imu_fixture.csv
+"""
+
+import math
+import random
+
+HZ = 95
+REST, TURN, HOLD = 2.0, 2.0, 6.0
+ROLL, PITCH, YAW = 30.0, -20.0, 60.0
+GYRO_BIAS = (0.4, -0.3, 0.2)
+GYRO_NOISE, ACCEL_NOISE, MAG_NOISE = 0.15, 0.005, 0.004
+FIELD = (0.21, 0.0, -0.43)
+
+
+def multiply(a, b):
+    w1, x1, y1, z1 = a
+    w2, x2, y2, z2 = b
+    return (w1 * w2 - x1 * x2 - y1 * y2 - z1 * z2,
+            w1 * x2 + x1 * w2 + y1 * z2 - z1 * y2,
+            w1 * y2 - x1 * z2 + y1 * w2 + z1 * x2,
+            w1 * z2 + x1 * y2 - y1 * x2 + z1 * w2)
+
+
+def about(axis, degrees):
+    half = math.radians(degrees) / 2
+    return (math.cos(half),) + tuple(a * math.sin(half) for a in axis)
+
+
+def to_board(q, v):
+    """An earth frame vector as the board, at attitude q, measures it"""
+    w, x, y, z = q
+    conjugate = (w, -x, -y, -z)
+    return multiply(multiply(conjugate, (0.0,) + tuple(v)), q)[1:]
+
+
+def main():
+    rng = random.Random(20261018)
+    target = multiply(multiply(about((0, 0, 1), YAW), about((0, 1, 0), PITCH)),
+                      about((1, 0, 0), ROLL))
+    angle = 2 * math.degrees(math.acos(target[0]))
+    axis = tuple(c / math.sin(math.radians(angle) / 2) for c in target[1:])
+    rate = angle / TURN
+
+    print("# synthesised by imu_fixture.py, see there for the motion")
+    print("# ends at roll %.1f pitch %.1f yaw %.1f" % (ROLL, PITCH, YAW))
+    print("gx,gy,gz,ax,ay,az,mx,my,mz")
+    steps = round((REST + TURN + HOLD) * HZ)
+    for step in range(steps):
+        t = step / HZ
+        turned = min(max(t - REST, 0.0), TURN) * rate
+        q = about(axis, turned)
+        turning = REST <= t < REST + TURN
+        gyro = [(rate * a if turning else 0.0) + bias + rng.gauss(0, GYRO_NOISE)
+                for a, bias in zip(axis, GYRO_BIAS)]
+        accel = [c + rng.gauss(0, ACCEL_NOISE) for c in to_board(q, (0, 0, 1))]
+        mag = [c + rng.gauss(0, MAG_NOISE) for c in to_board(q, FIELD)]
+        print(",".join("%.4f" % v for v in gyro + accel + mag))
+
+
+if __name__ == "__main__":
+    main()
@@ -3,7 +3,10 @@ MEMORY
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
   /* TODO Adjust these memory regions to match your device memory layout */
   /* These values correspond to the LM3S6965, one of the few devices QEMU can emulate */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 256K
+  /* The last 2K page of the 256K is kept out of FLASH for the sensor
+     calibration, see `storage::CALIB_ADDR` in src/storage.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 254K
+  CALIB : ORIGIN = 0x0803F800, LENGTH = 2K
   RAM : ORIGIN = 0x20000000, LENGTH = 40K
 }
 
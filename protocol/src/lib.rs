@@ -0,0 +1,330 @@
+//! Messages between a host and the STM32F3DISCOVERY
+//!
+//! Shared by the firmware (`no_std`) and host tools (with the `std`
+//! feature), so that both ends encode the same types the same way. Each
+//! message travels as the payload of one frame of the firmware's
+//! `framing` module: COBS encoded, CRC-16 checked and ended by a zero.
+//!
+//! The host sends `ToDevice`, carrying a `Request` and an `id` of its
+//! choosing. The board answers every request with one
+//! `FromDevice::Response` carrying the same `id`, and may send
+//! `FromDevice::Event`s at any time in between. A request whose body can't
+//! be decoded is still answered, with `ErrorCode::Malformed`, as long as
+//! its `id` can be read.
+//!
+//! The encoding is described in `wire`. Variants are numbered in the
+//! order they are declared, so new ones only ever go at the end, and
+//! anything else that changes the encoding bumps `PROTOCOL_VERSION`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod wire;
+
+use wire::{Decode, Encode, Error, Reader, Writer};
+
+/// Sent in `Version`, so a host can tell whether it speaks the same
+/// protocol as the board
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// Longest encoded message either way, given a firmware version string of
+/// at most 16 bytes
+pub const MAX_MESSAGE_LEN: usize = 64;
+
+/// From the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ToDevice {
+    pub id: u16,
+    pub request: Request,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Answered with `Pong` and the same number
+    Ping(u32),
+    Version,
+    /// The LEDs that are lit, answered with `Leds`
+    GetLeds,
+    /// Light the LEDs whose bits are set and turn the rest off; bit 0 is
+    /// LD3 (north), going clockwise
+    SetLeds(u8),
+    /// One LED, 0 to 7 from north going clockwise
+    SetLed { index: u8, on: bool },
+    /// Answered with `Sensors`
+    ReadSensors,
+}
+
+/// From the board
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FromDevice<'a> {
+    Response { id: u16, response: Response<'a> },
+    Event(Event),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Response<'a> {
+    Pong(u32),
+    Version(Version<'a>),
+    /// Bit 0 is LD3 (north), going clockwise
+    Leds(u8),
+    /// Done what was asked, with nothing to report
+    Done,
+    Sensors(Sensors),
+    Error(ErrorCode),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Version<'a> {
+    /// `PROTOCOL_VERSION` of the firmware
+    pub protocol: u16,
+    /// The firmware's crate version
+    pub firmware: &'a str,
+}
+
+/// One reading of each sensor
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sensors {
+    /// Acceleration in g
+    pub accel: [f32; 3],
+    /// Rotation in degrees per second
+    pub gyro: [f32; 3],
+    /// Magnetic field in gauss
+    pub mag: [f32; 3],
+    /// The gyro's die temperature, uncalibrated and falling as it gets
+    /// warmer
+    pub temperature: i8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The request couldn't be decoded
+    Malformed,
+    /// An argument was out of range, such as an LED index past 7
+    OutOfRange,
+    /// A sensor didn't answer
+    Sensor,
+}
+
+/// Sent unasked
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Button(ButtonEvent),
+}
+
+/// The user button, as the firmware's `button` module reports it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonEvent {
+    Pressed,
+    Released,
+    LongPress,
+    DoubleClick,
+    /// A short press on its own, sent once it can no longer be the start of
+    /// a double click
+    Click,
+}
+
+impl Encode for ToDevice {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        self.id.encode(w)?;
+        self.request.encode(w)
+    }
+}
+
+impl<'de> Decode<'de> for ToDevice {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(ToDevice { id: u16::decode(r)?, request: Request::decode(r)? })
+    }
+}
+
+impl Encode for Request {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Request::Ping(value) => {
+                w.varint(0)?;
+                value.encode(w)
+            }
+            Request::Version => w.varint(1),
+            Request::GetLeds => w.varint(2),
+            Request::SetLeds(mask) => {
+                w.varint(3)?;
+                mask.encode(w)
+            }
+            Request::SetLed { index, on } => {
+                w.varint(4)?;
+                index.encode(w)?;
+                on.encode(w)
+            }
+            Request::ReadSensors => w.varint(5),
+        }
+    }
+}
+
+impl<'de> Decode<'de> for Request {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(match r.varint(32)? {
+            0 => Request::Ping(u32::decode(r)?),
+            1 => Request::Version,
+            2 => Request::GetLeds,
+            3 => Request::SetLeds(u8::decode(r)?),
+            4 => Request::SetLed { index: u8::decode(r)?, on: bool::decode(r)? },
+            5 => Request::ReadSensors,
+            _ => return Err(Error::BadTag),
+        })
+    }
+}
+
+impl Encode for FromDevice<'_> {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            FromDevice::Response { id, response } => {
+                w.varint(0)?;
+                id.encode(w)?;
+                response.encode(w)
+            }
+            FromDevice::Event(event) => {
+                w.varint(1)?;
+                event.encode(w)
+            }
+        }
+    }
+}
+
+impl<'de> Decode<'de> for FromDevice<'de> {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(match r.varint(32)? {
+            0 => FromDevice::Response { id: u16::decode(r)?, response: Response::decode(r)? },
+            1 => FromDevice::Event(Event::decode(r)?),
+            _ => return Err(Error::BadTag),
+        })
+    }
+}
+
+impl Encode for Response<'_> {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Response::Pong(value) => {
+                w.varint(0)?;
+                value.encode(w)
+            }
+            Response::Version(version) => {
+                w.varint(1)?;
+                version.encode(w)
+            }
+            Response::Leds(mask) => {
+                w.varint(2)?;
+                mask.encode(w)
+            }
+            Response::Done => w.varint(3),
+            Response::Sensors(sensors) => {
+                w.varint(4)?;
+                sensors.encode(w)
+            }
+            Response::Error(code) => {
+                w.varint(5)?;
+                code.encode(w)
+            }
+        }
+    }
+}
+
+impl<'de> Decode<'de> for Response<'de> {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(match r.varint(32)? {
+            0 => Response::Pong(u32::decode(r)?),
+            1 => Response::Version(Version::decode(r)?),
+            2 => Response::Leds(u8::decode(r)?),
+            3 => Response::Done,
+            4 => Response::Sensors(Sensors::decode(r)?),
+            5 => Response::Error(ErrorCode::decode(r)?),
+            _ => return Err(Error::BadTag),
+        })
+    }
+}
+
+impl Encode for Version<'_> {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        self.protocol.encode(w)?;
+        self.firmware.encode(w)
+    }
+}
+
+impl<'de> Decode<'de> for Version<'de> {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(Version { protocol: u16::decode(r)?, firmware: <&str>::decode(r)? })
+    }
+}
+
+impl Encode for Sensors {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        self.accel.encode(w)?;
+        self.gyro.encode(w)?;
+        self.mag.encode(w)?;
+        self.temperature.encode(w)
+    }
+}
+
+impl<'de> Decode<'de> for Sensors {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(Sensors {
+            accel: Decode::decode(r)?,
+            gyro: Decode::decode(r)?,
+            mag: Decode::decode(r)?,
+            temperature: i8::decode(r)?,
+        })
+    }
+}
+
+impl Encode for ErrorCode {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(*self as u32)
+    }
+}
+
+impl<'de> Decode<'de> for ErrorCode {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(match r.varint(32)? {
+            0 => ErrorCode::Malformed,
+            1 => ErrorCode::OutOfRange,
+            2 => ErrorCode::Sensor,
+            _ => return Err(Error::BadTag),
+        })
+    }
+}
+
+impl Encode for Event {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            Event::Button(event) => {
+                w.varint(0)?;
+                event.encode(w)
+            }
+        }
+    }
+}
+
+impl<'de> Decode<'de> for Event {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(match r.varint(32)? {
+            0 => Event::Button(ButtonEvent::decode(r)?),
+            _ => return Err(Error::BadTag),
+        })
+    }
+}
+
+impl Encode for ButtonEvent {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(*self as u32)
+    }
+}
+
+impl<'de> Decode<'de> for ButtonEvent {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(match r.varint(32)? {
+            0 => ButtonEvent::Pressed,
+            1 => ButtonEvent::Released,
+            2 => ButtonEvent::LongPress,
+            3 => ButtonEvent::DoubleClick,
+            4 => ButtonEvent::Click,
+            _ => return Err(Error::BadTag),
+        })
+    }
+}
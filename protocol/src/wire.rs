@@ -0,0 +1,370 @@
+//! The compact binary encoding, in the style of postcard
+//!
+//! * `u8`, `i8` and `bool` are one byte, `bool` being 0 or 1
+//! * wider integers are LEB128 varints, seven bits a byte, least
+//!   significant first; signed ones are zigzag encoded first so that small
+//!   negative numbers stay short
+//! * `f32` is four bytes, little endian
+//! * arrays are their elements in order, with no length
+//! * `&str` and `&[u8]` are a varint length then the bytes
+//! * `Option` is a 0 or 1 byte, then the value if there is one
+//! * an enum is its variant's index as a varint, then the variant's fields
+//!
+//! There are no field names or type tags, so both ends must agree on the
+//! types; `PROTOCOL_VERSION` says when they change.
+
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// No room left in the output buffer
+    BufferFull,
+    /// The input ended in the middle of a value
+    UnexpectedEnd,
+    /// A varint too long for its type
+    BadVarint,
+    /// An enum variant or `Option`/`bool` byte that doesn't exist
+    BadTag,
+    /// A string that isn't UTF-8
+    BadUtf8,
+    /// Bytes left over after the message
+    TrailingBytes,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Error::BufferFull => "buffer full",
+            Error::UnexpectedEnd => "message cut short",
+            Error::BadVarint => "varint too long",
+            Error::BadTag => "unknown variant",
+            Error::BadUtf8 => "string not UTF-8",
+            Error::TrailingBytes => "bytes after the message",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// Appends to a byte buffer
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes written so far
+    pub fn finish(self) -> &'a mut [u8] {
+        &mut self.buf[..self.len]
+    }
+
+    pub fn byte(&mut self, byte: u8) -> Result<(), Error> {
+        let slot = self.buf.get_mut(self.len).ok_or(Error::BufferFull)?;
+        *slot = byte;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let end = self.len + bytes.len();
+        self.buf.get_mut(self.len..end).ok_or(Error::BufferFull)?.copy_from_slice(bytes);
+        self.len = end;
+        Ok(())
+    }
+
+    pub fn varint(&mut self, mut value: u32) -> Result<(), Error> {
+        while value >= 0x80 {
+            self.byte(value as u8 | 0x80)?;
+            value >>= 7;
+        }
+        self.byte(value as u8)
+    }
+}
+
+/// Takes values off the front of a byte slice
+pub struct Reader<'de> {
+    buf: &'de [u8],
+}
+
+impl<'de> Reader<'de> {
+    pub fn new(buf: &'de [u8]) -> Reader<'de> {
+        Reader { buf }
+    }
+
+    /// What hasn't been read yet
+    pub fn remaining(&self) -> &'de [u8] {
+        self.buf
+    }
+
+    pub fn byte(&mut self) -> Result<u8, Error> {
+        let (first, rest) = self.buf.split_first().ok_or(Error::UnexpectedEnd)?;
+        self.buf = rest;
+        Ok(*first)
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'de [u8], Error> {
+        if len > self.buf.len() {
+            return Err(Error::UnexpectedEnd);
+        }
+        let (bytes, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Ok(bytes)
+    }
+
+    /// A varint of at most `bits` bits
+    pub fn varint(&mut self, bits: u32) -> Result<u32, Error> {
+        let mut value: u32 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            let part = (byte & 0x7f) as u32;
+            // the last byte may only hold what is left of `bits`
+            if shift >= bits || (bits - shift < 7 && part >> (bits - shift) != 0) {
+                return Err(Error::BadVarint);
+            }
+            value |= part << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+}
+
+pub trait Encode {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error>;
+}
+
+pub trait Decode<'de>: Sized {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error>;
+}
+
+/// Encode `value` into `buf`, giving the part used
+pub fn to_slice<'a, T: Encode + ?Sized>(value: &T, buf: &'a mut [u8]) -> Result<&'a mut [u8], Error> {
+    let mut w = Writer::new(buf);
+    value.encode(&mut w)?;
+    Ok(w.finish())
+}
+
+/// Decode a whole message; anything after it is an error
+pub fn from_bytes<'de, T: Decode<'de>>(bytes: &'de [u8]) -> Result<T, Error> {
+    let mut r = Reader::new(bytes);
+    let value = T::decode(&mut r)?;
+    if !r.remaining().is_empty() {
+        return Err(Error::TrailingBytes);
+    }
+    Ok(value)
+}
+
+#[cfg(feature = "std")]
+pub fn to_vec<T: Encode + ?Sized>(value: &T) -> Vec<u8> {
+    // grow until it fits; messages are small, so this rarely goes round
+    let mut buf = vec![0; 64];
+    loop {
+        match to_slice(value, &mut buf) {
+            Ok(used) => {
+                let len = used.len();
+                buf.truncate(len);
+                return buf;
+            }
+            Err(_) => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+        }
+    }
+}
+
+fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+fn unzigzag(value: u32) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+impl Encode for u8 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.byte(*self)
+    }
+}
+
+impl<'de> Decode<'de> for u8 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        r.byte()
+    }
+}
+
+impl Encode for i8 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.byte(*self as u8)
+    }
+}
+
+impl<'de> Decode<'de> for i8 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(r.byte()? as i8)
+    }
+}
+
+impl Encode for bool {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.byte(*self as u8)
+    }
+}
+
+impl<'de> Decode<'de> for bool {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        match r.byte()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::BadTag),
+        }
+    }
+}
+
+impl Encode for u16 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(*self as u32)
+    }
+}
+
+impl<'de> Decode<'de> for u16 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(r.varint(16)? as u16)
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(*self)
+    }
+}
+
+impl<'de> Decode<'de> for u32 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        r.varint(32)
+    }
+}
+
+impl Encode for i16 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(zigzag(*self as i32))
+    }
+}
+
+impl<'de> Decode<'de> for i16 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(unzigzag(r.varint(16)?) as i16)
+    }
+}
+
+impl Encode for i32 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(zigzag(*self))
+    }
+}
+
+impl<'de> Decode<'de> for i32 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        Ok(unzigzag(r.varint(32)?))
+    }
+}
+
+impl Encode for f32 {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.bytes(&self.to_le_bytes())
+    }
+}
+
+impl<'de> Decode<'de> for f32 {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(r.bytes(4)?);
+        Ok(f32::from_le_bytes(bytes))
+    }
+}
+
+impl<T: Encode, const N: usize> Encode for [T; N] {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        self.iter().try_for_each(|item| item.encode(w))
+    }
+}
+
+impl<'de, T: Decode<'de> + Copy + Default, const N: usize> Decode<'de> for [T; N] {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        let mut items = [T::default(); N];
+        for item in items.iter_mut() {
+            *item = T::decode(r)?;
+        }
+        Ok(items)
+    }
+}
+
+impl Encode for [u8] {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        w.varint(self.len() as u32)?;
+        w.bytes(self)
+    }
+}
+
+impl<'de> Decode<'de> for &'de [u8] {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        let len = r.varint(32)? as usize;
+        r.bytes(len)
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        self.as_bytes().encode(w)
+    }
+}
+
+impl<'de> Decode<'de> for &'de str {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        let bytes = <&[u8]>::decode(r)?;
+        core::str::from_utf8(bytes).map_err(|_| Error::BadUtf8)
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        (**self).encode(w)
+    }
+}
+
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, w: &mut Writer<'_>) -> Result<(), Error> {
+        match self {
+            None => w.byte(0),
+            Some(value) => {
+                w.byte(1)?;
+                value.encode(w)
+            }
+        }
+    }
+}
+
+impl<'de, T: Decode<'de>> Decode<'de> for Option<T> {
+    fn decode(r: &mut Reader<'de>) -> Result<Self, Error> {
+        match r.byte()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(r)?)),
+            _ => Err(Error::BadTag),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+//! Every message must decode to what was encoded, and the encoding must
+//! stay the same, or the board and older hosts stop understanding each
+//! other.
+
+use stm32f3disc_protocol::{
+    wire::{self, Decode, Encode, Error},
+    ButtonEvent, ErrorCode, Event, FromDevice, Request, Response, Sensors, ToDevice, Version,
+    MAX_MESSAGE_LEN, PROTOCOL_VERSION,
+};
+
+fn encode<T: Encode>(value: &T) -> Vec<u8> {
+    let mut buf = [0; 256];
+    wire::to_slice(value, &mut buf).unwrap().to_vec()
+}
+
+/// Encodes `value`, checks it decodes back to the same and that it fits in
+/// a message, and gives the bytes
+fn round_trip<'de, T>(value: &T, buf: &'de mut [u8]) -> &'de [u8]
+where
+    T: Encode + Decode<'de> + PartialEq + std::fmt::Debug,
+{
+    let bytes: &'de [u8] = wire::to_slice(value, buf).unwrap();
+    assert!(bytes.len() <= MAX_MESSAGE_LEN, "{:?} takes {} bytes", value, bytes.len());
+    assert_eq!(&wire::from_bytes::<T>(bytes).unwrap(), value);
+    bytes
+}
+
+fn sensors() -> Sensors {
+    Sensors {
+        accel: [0.01, -0.02, 0.98],
+        gyro: [-1.5, 0.0, 250.0],
+        mag: [0.21, -0.05, -0.43],
+        temperature: -7,
+    }
+}
+
+#[test]
+fn requests() {
+    let requests = [
+        Request::Ping(0),
+        Request::Ping(u32::MAX),
+        Request::Version,
+        Request::GetLeds,
+        Request::SetLeds(0xa5),
+        Request::SetLed { index: 7, on: true },
+        Request::SetLed { index: 0, on: false },
+        Request::ReadSensors,
+    ];
+    for (id, request) in requests.iter().enumerate() {
+        let message = ToDevice { id: id as u16 * 1000, request: *request };
+        round_trip(&message, &mut [0; 256]);
+    }
+}
+
+#[test]
+fn responses() {
+    let responses = [
+        Response::Pong(12345),
+        Response::Version(Version { protocol: PROTOCOL_VERSION, firmware: "0.1.0" }),
+        Response::Leds(0xff),
+        Response::Done,
+        Response::Sensors(sensors()),
+        Response::Error(ErrorCode::Malformed),
+        Response::Error(ErrorCode::OutOfRange),
+        Response::Error(ErrorCode::Sensor),
+    ];
+    for response in responses.iter() {
+        round_trip(&FromDevice::Response { id: u16::MAX, response: *response }, &mut [0; 256]);
+    }
+}
+
+#[test]
+fn events() {
+    let buttons = [
+        ButtonEvent::Pressed,
+        ButtonEvent::Released,
+        ButtonEvent::LongPress,
+        ButtonEvent::DoubleClick,
+        ButtonEvent::Click,
+    ];
+    for button in buttons.iter() {
+        round_trip(&FromDevice::Event(Event::Button(*button)), &mut [0; 256]);
+    }
+}
+
+#[test]
+fn version_borrows_from_the_input() {
+    let message = FromDevice::Response {
+        id: 1,
+        response: Response::Version(Version { protocol: 1, firmware: "1.2.3-rc.1" }),
+    };
+    let mut buf = [0; 64];
+    let bytes = round_trip(&message, &mut buf);
+    match wire::from_bytes::<FromDevice<'_>>(bytes).unwrap() {
+        FromDevice::Response { response: Response::Version(version), .. } => {
+            let range = bytes.as_ptr_range();
+            assert!(range.contains(&version.firmware.as_ptr()));
+        }
+        other => panic!("decoded as {:?}", other),
+    }
+}
+
+#[test]
+fn sensor_floats_are_exact() {
+    let mut readings = sensors();
+    readings.accel = [f32::MIN_POSITIVE, f32::MAX, -0.0];
+    readings.gyro = [f32::INFINITY, f32::NEG_INFINITY, 1.0e-3];
+    let mut buf = [0; 64];
+    round_trip(&Response::Sensors(readings), &mut buf);
+}
+
+#[test]
+fn encoding_is_stable() {
+    // small numbers take one byte, tags are variant indices
+    assert_eq!(encode(&ToDevice { id: 1, request: Request::Version }), [1, 1]);
+    assert_eq!(encode(&ToDevice { id: 2, request: Request::Ping(300) }), [2, 0, 0xac, 0x02]);
+    assert_eq!(
+        encode(&ToDevice { id: 300, request: Request::SetLed { index: 3, on: true } }),
+        [0xac, 0x02, 4, 3, 1]
+    );
+    assert_eq!(encode(&FromDevice::Response { id: 9, response: Response::Leds(0x81) }), [0, 9, 2, 0x81]);
+    assert_eq!(
+        encode(&FromDevice::Response {
+            id: 0,
+            response: Response::Version(Version { protocol: 1, firmware: "0.1" }),
+        }),
+        [0, 0, 1, 1, 3, b'0', b'.', b'1']
+    );
+    assert_eq!(encode(&FromDevice::Event(Event::Button(ButtonEvent::LongPress))), [1, 0, 2]);
+    assert_eq!(encode(&FromDevice::Event(Event::Button(ButtonEvent::Click))), [1, 0, 4]);
+    assert_eq!(encode(&1.0f32), [0x00, 0x00, 0x80, 0x3f]);
+}
+
+#[test]
+fn integers() {
+    let mut buf = [0; 8];
+    for value in [0u32, 1, 127, 128, 16383, 16384, u32::MAX - 1, u32::MAX].iter() {
+        round_trip(value, &mut buf);
+    }
+    assert_eq!(encode(&u32::MAX), [0xff, 0xff, 0xff, 0xff, 0x0f]);
+    for value in [0u16, 127, 128, u16::MAX].iter() {
+        round_trip(value, &mut buf);
+    }
+    for value in [0i32, -1, 1, -64, 64, i32::MIN, i32::MAX].iter() {
+        round_trip(value, &mut buf);
+    }
+    for value in [0i16, -1, i16::MIN, i16::MAX].iter() {
+        round_trip(value, &mut buf);
+    }
+    // zigzag keeps small negative numbers short
+    assert_eq!(encode(&-1i32), [1]);
+    assert_eq!(encode(&-64i32), [127]);
+    assert_eq!(encode(&i8::MIN), [0x80]);
+}
+
+#[test]
+fn options() {
+    let mut buf = [0; 8];
+    round_trip(&Some(5u16), &mut buf);
+    round_trip(&None::<u16>, &mut buf);
+    assert_eq!(wire::from_bytes::<Option<u8>>(&[2]), Err(Error::BadTag));
+}
+
+#[test]
+fn rejects_bad_input() {
+    // cut short anywhere
+    let bytes = encode(&FromDevice::Response { id: 4, response: Response::Sensors(sensors()) });
+    for len in 0..bytes.len() {
+        assert_eq!(wire::from_bytes::<FromDevice<'_>>(&bytes[..len]), Err(Error::UnexpectedEnd), "{}", len);
+    }
+    // something after the message
+    assert_eq!(wire::from_bytes::<ToDevice>(&[1, 1, 0]), Err(Error::TrailingBytes));
+    // variants that don't exist
+    assert_eq!(wire::from_bytes::<ToDevice>(&[1, 6]), Err(Error::BadTag));
+    assert_eq!(wire::from_bytes::<FromDevice<'_>>(&[2]), Err(Error::BadTag));
+    assert_eq!(wire::from_bytes::<FromDevice<'_>>(&[0, 0, 5, 3]), Err(Error::BadTag));
+    assert_eq!(wire::from_bytes::<FromDevice<'_>>(&[1, 0, 5]), Err(Error::BadTag));
+    assert_eq!(wire::from_bytes::<ToDevice>(&[1, 4, 0, 2]), Err(Error::BadTag));
+    // varints too big for their type
+    assert_eq!(wire::from_bytes::<u16>(&[0xff, 0xff, 0x04]), Err(Error::BadVarint));
+    assert_eq!(wire::from_bytes::<u32>(&[0xff, 0xff, 0xff, 0xff, 0x10]), Err(Error::BadVarint));
+    assert_eq!(wire::from_bytes::<u32>(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x00]), Err(Error::BadVarint));
+    // a firmware version that isn't text
+    assert_eq!(wire::from_bytes::<Version<'_>>(&[1, 2, 0xc3, 0x28]), Err(Error::BadUtf8));
+}
+
+#[test]
+fn buffer_too_small() {
+    let message = FromDevice::Response { id: 4, response: Response::Sensors(sensors()) };
+    let len = encode(&message).len();
+    for size in 0..len {
+        let mut buf = vec![0; size];
+        assert_eq!(wire::to_slice(&message, &mut buf).err(), Some(Error::BufferFull), "{}", size);
+    }
+    let mut buf = vec![0; len];
+    assert_eq!(wire::to_slice(&message, &mut buf).unwrap().len(), len);
+}
+
+#[test]
+fn id_survives_a_bad_request() {
+    // the board answers with the id even when it can't make out the rest
+    let bytes = [0xac, 0x02, 0x7f];
+    assert_eq!(wire::from_bytes::<ToDevice>(&bytes), Err(Error::BadTag));
+    let mut r = wire::Reader::new(&bytes);
+    assert_eq!(u16::decode(&mut r), Ok(300));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn to_vec_matches_to_slice() {
+    let message = FromDevice::Response { id: 77, response: Response::Sensors(sensors()) };
+    assert_eq!(wire::to_vec(&message), encode(&message));
+    let long = "x".repeat(1000);
+    let bytes = wire::to_vec(long.as_str());
+    assert_eq!(wire::from_bytes::<&str>(&bytes), Ok(long.as_str()));
+}
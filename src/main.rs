@@ -10,24 +10,183 @@ use panic_rtt_target as _; // logs messages to the host stderr; requires a debug
 
 use core::cell::RefCell;
 
-use cortex_m_rt::entry;
+use cortex_m_rt::{entry, exception};
 use panic_rtt_target as _;
 use rtt_target::{rtt_init_print, rprintln};
 use critical_section::Mutex;
  
 use stm32f3xx_hal::{
     interrupt,
-    gpio::{self, Edge, Input},
-    pac::{Peripherals, NVIC, Interrupt},
-    delay::Delay,
-    prelude::*
+    pac::{self, Peripherals, NVIC, Interrupt},
+    prelude::*,
+    serial::Event::{ReceiveDataRegisterNotEmpty, TransmitDataRegisterEmtpy},
 };
+use stm32f3disc::{
+    baud::{BaudChange, BaudRate},
+    board::{self, Board, SerialType},
+    button::Button,
+    clock,
+    commands::{self, Control},
+    flow::{FlowControl, FlowError, FlowMode},
+    leds::{CompassLeds, Direction},
+    line_editor::{Event, LineEditor},
+    mode::{Action, AppMode, SharedMode},
+    pattern::{Pattern, PatternEngine},
+    pwm::{PwmLeds, PwmLevels},
+    serial_errors::{ErrorCounters, RxError, RxErrors},
+    shell::Shell,
+    spsc::{Consumer, Producer, Queue},
+    uprint::{Backpressure, SerialWriter},
+    uprintln,
+};
+
+/// Brightness steps cycled through in `AppMode::Settings`
+const BRIGHTNESS: [u8; 4] = [255, 128, 64, 16];
+
+static MODE: SharedMode = SharedMode::new();
+
+static BUTTON: Mutex<RefCell<Option<Button>>> = Mutex::new(RefCell::new(None));
+
+// main sets the levels, TIM7 alone owns the LEDs and shows them
+static LEVELS: PwmLevels = PwmLevels::new();
+static mut PWM: Option<PwmLeds> = None;
+
+// serial console queues, split between main and the USART1 interrupt
+static mut XMIT_BUF: Queue<u8, 256> = Queue::new();
+static mut RECV_BUF: Queue<u8, 64> = Queue::new();
+static mut ERROR_BUF: Queue<RxError, 8> = Queue::new();
+
+static ERROR_COUNTS: ErrorCounters = ErrorCounters::new();
+
+// set before USART1 is unmasked, after that only its handler touches it,
+// so it needs no critical section that would hold up TIM7
+static mut SERIAL: Option<SerialIo> = None;
+
+// asks the host to stop with the receive queue three quarters full
+static FLOW: FlowControl = FlowControl::new(16, 48, Interrupt::USART1_EXTI25);
+
+const PROMPT: &str = "stm32f3> ";
 
-type ButtonPin = gpio::PA0<Input>;
+/// USART1 byte at a time from its interrupt: received bytes and errors go
+/// to main through queues, output comes back the same way
+struct SerialIo {
+    serial: SerialType,
+    recv: Producer<'static, u8, 64>,
+    xmit: Consumer<'static, u8, 256>,
+    errors: RxErrors<'static, 8>,
+}
 
-static mut  RUN:bool = true;
+impl SerialIo {
+    fn on_interrupt(&mut self) {
+        // RXNEIE also interrupts on overrun, clear it or the interrupt repeats
+        self.errors.check(unsafe { &*pac::USART1::ptr() });
+        if let Ok(byte) = self.serial.read() {
+            if let Some(byte) = FLOW.filter(byte) {
+                let _ = self.recv.put(byte);   // dropped if main falls behind
+                FLOW.received(self.recv.len());
+            }
+        }
+        if self.serial.triggered_events().contains(TransmitDataRegisterEmtpy) {
+            if let Some(control) = FLOW.next_control() {
+                let _ = self.serial.write(control);
+            } else if FLOW.may_send() {
+                if let Some(byte) = self.xmit.peek() {
+                    if self.serial.write(byte).is_ok() {
+                        let _ = self.xmit.get();
+                    }
+                }
+            }
+        }
+        // keep interrupting on TXE only while there is something to send
+        if !FLOW.wants_transmit(!self.xmit.is_empty()) {
+            self.serial.disable_interrupt(TransmitDataRegisterEmtpy);
+        } else {
+            self.serial.enable_interrupt(TransmitDataRegisterEmtpy);
+        }
+    }
+}
 
-static BUTTON: Mutex<RefCell<Option<ButtonPin>>> = Mutex::new(RefCell::new(None));
+/// What the button and the shell change: the pattern, its brightness
+/// and the serial baud rate
+struct Controls {
+    engine: PatternEngine,
+    /// Index into `BRIGHTNESS`
+    brightness: usize,
+    baud: BaudRate,
+    /// Waiting for the shell's reply to go out
+    baud_change: Option<BaudChange>,
+    /// Likewise
+    flow_change: Option<FlowMode>,
+    reset_requested: bool,
+}
+
+impl Controls {
+    fn level(&self) -> u8 {
+        BRIGHTNESS[self.brightness]
+    }
+
+    fn show(&self) {
+        show(&self.engine, self.level());
+    }
+}
+
+impl Control for Controls {
+    fn set_led(&mut self, index: usize, on: bool) {
+        MODE.set(AppMode::Paused);
+        let level = if on { self.level() } else { 0 };
+        LEVELS.set(Direction::from_index(index), level);
+    }
+
+    fn pattern(&self) -> Pattern {
+        self.engine.pattern()
+    }
+
+    fn set_pattern(&mut self, pattern: Pattern) {
+        self.engine.set_pattern(pattern);
+        MODE.set(AppMode::Running);
+        self.show();
+    }
+
+    fn is_paused(&self) -> bool {
+        MODE.get() == AppMode::Paused
+    }
+
+    fn set_paused(&mut self, paused: bool) {
+        MODE.set(if paused { AppMode::Paused } else { AppMode::Running });
+        self.show();
+    }
+
+    fn uptime_ms(&self) -> u32 {
+        clock::now_ms()
+    }
+
+    fn status(&self, out: &mut dyn core::fmt::Write) {
+        write!(out, "mode {:?}, brightness {}\r\n", MODE.get(), self.level()).ok();
+        write!(out, "receive errors {}\r\n", ERROR_COUNTS.total()).ok();
+    }
+
+    fn reset(&mut self) {
+        self.reset_requested = true;
+    }
+
+    fn baud_rate(&self) -> u32 {
+        self.baud.get()
+    }
+
+    fn change_baud(&mut self, change: BaudChange) {
+        self.baud_change = Some(change);
+    }
+
+    fn flow_mode(&self) -> FlowMode {
+        FLOW.mode()
+    }
+
+    fn change_flow_mode(&mut self, mode: FlowMode) -> Result<(), FlowError> {
+        FLOW.check(mode)?;
+        self.flow_change = Some(mode);
+        Ok(())
+    }
+}
 
 #[entry]
 fn main() -> ! {
@@ -36,78 +195,181 @@ fn main() -> ! {
     let dp = Peripherals::take().unwrap();
     let cp = cortex_m::peripheral::Peripherals::take().unwrap();
 
-    let mut rcc = dp.RCC.constrain();
-    let mut flash = dp.FLASH.constrain();
-    let mut delay = Delay::new(cp.SYST, rcc.cfgr.freeze(&mut flash.acr));
-    let mut syscfg = dp.SYSCFG.constrain(&mut rcc.apb2);
+    let Board { leds, button, mut serial, clocks, delay, mut exti, mut apb1, tim7, .. } =
+            Board::init(dp, cp);
 
-    let mut exti = dp.EXTI;
-    let mut gpioe = dp.GPIOE.split(&mut rcc.ahb);
-    let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
+    // TIM7 drives the LED brightness, the pattern only sets the levels
+    let pwm = PwmLeds::new(CompassLeds::new(leds), &LEVELS, tim7, clocks, &mut apb1);
+    // before TIM7 is unmasked, after that only its handler touches PWM
+    unsafe { PWM = Some(pwm) };
 
-    // Configuring the user button to trigger an interrupt when the button is pressed.
-    let mut user_button = gpioa.pa0.into_pull_down_input(&mut gpioa.moder, &mut gpioa.pupdr);
-    syscfg.select_exti_interrupt_source(&user_button);
-    user_button.trigger_on_edge(&mut exti, Edge::Rising);
-    user_button.enable_interrupt(&mut exti);
-    let interrupt_num = user_button.interrupt(); // hal::pac::Interrupt::EXTI0
+    // Moving ownership to the global BUTTON so the interrupts can feed the debouncer.
+    let button = Button::new(button, &mut exti);
+    critical_section::with(|cs| *BUTTON.borrow(cs).borrow_mut() = Some(button));
 
-    // Moving ownership to the global BUTTON so we can clear the interrupt pending bit.
-    critical_section::with(|cs| *BUTTON.borrow(cs).borrow_mut() = Some(user_button));
+    // Console on USART1, the interrupt moves the bytes
+    let (xmit, xmit_consumer) = unsafe { XMIT_BUF.split() };
+    let (recv_producer, mut recv) = unsafe { RECV_BUF.split() };
+    let (error_producer, mut errors) = unsafe { ERROR_BUF.split() };
+    serial.enable_interrupt(ReceiveDataRegisterNotEmpty);
+    let serial_io = SerialIo {
+        serial,
+        recv: recv_producer,
+        xmit: xmit_consumer,
+        errors: RxErrors::new(&ERROR_COUNTS, error_producer),
+    };
+    unsafe { SERIAL = Some(serial_io) };
+    // main can always be preempted by the USART interrupt, so it can wait
+    // for room rather than lose output
+    let mut out = SerialWriter::new(xmit, Interrupt::USART1_EXTI25, Backpressure::Block);
+    let mut editor: LineEditor<64, 8> = LineEditor::new(PROMPT);
+    let mut shell: Shell<Controls, { commands::SHELL_SIZE }> = Shell::new();
+    shell.register_all(&commands::builtin()).ok();
 
+    // SysTick drives the pattern and button timing instead of the blocking delay
+    clock::start(delay.free(), board::SYSCLK_HZ);
 
     unsafe {
         NVIC::unmask(Interrupt::EXTI0);
+        NVIC::unmask(Interrupt::TIM7);
+        NVIC::unmask(Interrupt::USART1_EXTI25);
     }
 
-
-    let mut leds = [
-        gpioe.pe9.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe10.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe11.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe12.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe13.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe14.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe15.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-        gpioe.pe8.into_push_pull_output(
-                        &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
-    ];
-
-    for led in leds.iter_mut() {
-        led.set_low().unwrap();
-        delay.delay_ms(50u32);
-    }
-
+    let mut controls = Controls {
+        engine: PatternEngine::new(Pattern::Chase),
+        brightness: 0,
+        baud: BaudRate::new(board::PCLK2_HZ),
+        baud_change: None,
+        flow_change: None,
+        reset_requested: false,
+    };
+    controls.show();
+    editor.prompt(&mut out);
+    let mut last = clock::now_ms();
+    let mut input = [0u8; 16];
     loop {
-        for led in leds.iter_mut() {
-            if unsafe {RUN == true} {
-                led.set_high().unwrap();
-                delay.delay_ms(450u16);
-                led.set_low().unwrap();
-                delay.delay_ms(50u16);
+        // sleep until the next tick or interrupt; TIM7 wakes us 25 times
+        // a millisecond, so only go round once the tick has moved
+        cortex_m::asm::wfi();
+        let now = clock::now_ms();
+        if now == last {
+            continue;
+        }
+        let elapsed = now.wrapping_sub(last);
+        last = now;
+        while let Some(event) = critical_section::with(|cs| {
+            BUTTON.borrow(cs).borrow_mut().as_mut().unwrap().pop()
+        }) {
+            let transition = MODE.handle(event);
+            rprintln!("User Button {:?} {:?}", event, transition);
+            match transition.action {
+                Some(Action::NextPattern) => {
+                    controls.engine.set_pattern(controls.engine.pattern().next());
+                }
+                Some(Action::NextSetting) => {
+                    controls.brightness = (controls.brightness + 1) % BRIGHTNESS.len();
+                }
+                None => (),
+            }
+            if transition.to == AppMode::Settings {
+                // all LEDs at the brightness being chosen
+                set_levels(&[controls.level(); 8]);
+            } else {
+                controls.show();
+            }
+        }
+        while let Some(error) = errors.get() {
+            rprintln!("receive error: {}, {} in all", error.name(), ERROR_COUNTS.get(error));
+        }
+        match controls.baud.poll() {
+            Some(Ok(rate)) => {
+                rprintln!("detected {} baud", rate);
+                uprintln!(out, "detected {} baud", rate).ok();
             }
+            Some(Err(_)) => rprintln!("baud rate detection failed, trying again"),
+            None => (),
         }
+        let count = recv.pop_into(&mut input);
+        FLOW.drained(recv.len());
+        for byte in &input[..count] {
+            if let Some(Event::Line(line)) = editor.feed(*byte, &mut out) {
+                rprintln!("command: {}", line);
+                let _ = shell.run(line, &mut controls, &mut out);
+                if controls.reset_requested {
+                    // the queue, then the last byte out of the USART
+                    while out.pending() > 0 {}
+                    let usart = unsafe { &*pac::USART1::ptr() };
+                    while usart.isr.read().tc().bit_is_clear() {}
+                    cortex_m::peripheral::SCB::sys_reset();
+                }
+                if let Some(change) = controls.baud_change.take() {
+                    // the USART interrupt sends the reply at the old rate
+                    while out.pending() > 0 {}
+                    match change {
+                        BaudChange::To(rate) => {
+                            controls.baud.set(rate).ok();
+                        }
+                        BaudChange::Detect => controls.baud.detect(),
+                    }
+                }
+                if let Some(mode) = controls.flow_change.take() {
+                    // and under the old flow control
+                    while out.pending() > 0 {}
+                    FLOW.set_mode(mode).ok();
+                }
+                editor.prompt(&mut out);
+            }
+        }
+        match MODE.get() {
+            AppMode::Running | AppMode::PatternSelect => {
+                if controls.engine.tick(elapsed) {
+                    controls.show();
+                }
+            }
+            AppMode::Paused | AppMode::Settings => (),
+        }
+    }
+}
+
+fn show(engine: &PatternEngine, brightness: u8) {
+    let mut levels = engine.frame().levels;
+    for level in levels.iter_mut() {
+        *level = ((*level as u16 * brightness as u16) / 255) as u8;
     }
+    set_levels(&levels);
+}
+
+fn set_levels(levels: &[u8; 8]) {
+    LEVELS.set_levels(levels);
+}
+
+#[exception]
+fn SysTick() {
+    clock::tick();
+    critical_section::with(|cs| {
+        BUTTON.borrow(cs).borrow_mut().as_mut().unwrap().poll(clock::now_ms());
+    })
+}
+
+#[interrupt]
+fn TIM7() {
+    unsafe { PWM.as_mut() }.unwrap().on_interrupt();
+}
+
+#[interrupt]
+fn USART1_EXTI25() {
+    unsafe { SERIAL.as_mut() }.unwrap().on_interrupt();
 }
 
 #[interrupt]
 fn EXTI0() {
-    rprintln!("User Button");
     critical_section::with(|cs| {
-        // Clear the interrupt pending bit so we don't infinitely call this routine
+        // Clears the interrupt pending bit so we don't infinitely call this routine
         BUTTON
             .borrow(cs)
             .borrow_mut()
             .as_mut()
             .unwrap()
-            .clear_interrupt();
-        unsafe {if RUN { RUN = false } else { RUN = true} };
+            .on_interrupt(clock::now_ms());
     })
 }
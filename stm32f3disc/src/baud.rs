@@ -0,0 +1,82 @@
+//! USART1 baud rate at runtime
+//!
+//! `BaudRate::set` reprograms the divider of the running USART. It waits
+//! for the byte in the shift register to finish first, and doesn't touch
+//! the serial queues or the DMA channels, so nothing already buffered is
+//! lost; it goes out at the new rate. Callers should let the transmit
+//! queue drain beforehand if the far end is to read it at the old rate.
+//!
+//! `BaudRate::detect` uses the USART's auto baud rate detection instead:
+//! the hardware times the start bit of the next character and sets the
+//! divider itself. Any character whose first data bit is 1 will do, CR
+//! (Enter) among them. `poll` says when it is done.
+//!
+//! The USART is assumed to oversample by 16, as the HAL sets it up.
+//! `divider`, `BaudChange` and `BaudError` come from `stm32f3disc_logic`
+//! and are re-exported here.
+
+pub use stm32f3disc_logic::baud::*;
+
+use crate::usart;
+
+pub struct BaudRate {
+    clock_hz: u32,
+    detecting: bool,
+}
+
+impl BaudRate {
+    /// USART1 clocked at `clock_hz`, `board::PCLK2_HZ` on this board
+    pub const fn new(clock_hz: u32) -> BaudRate {
+        BaudRate { clock_hz, detecting: false }
+    }
+
+    /// The rate the divider is set to now
+    pub fn get(&self) -> u32 {
+        let div = usart::usart1().brr.read().bits() & 0xffff;
+        if div == 0 { 0 } else { self.clock_hz / div }
+    }
+
+    /// Switch to `rate`, abandoning any detection in progress
+    pub fn set(&mut self, rate: u32) -> Result<(), BaudError> {
+        let div = divider(self.clock_hz, rate).ok_or(BaudError::OutOfRange)?;
+        // the last byte finishes at the old rate
+        usart::reconfigure(|usart| {
+            usart.cr2.modify(|_, w| w.abren().clear_bit());
+            usart.brr.write(|w| unsafe { w.bits(div as u32) });
+        });
+        self.detecting = false;
+        Ok(())
+    }
+
+    /// Lock on to the rate of the next character received. `ABRMOD` is
+    /// left at its reset value, timing the start bit
+    pub fn detect(&mut self) {
+        usart::reconfigure(|usart| usart.cr2.modify(|_, w| w.abren().set_bit()));
+        usart::usart1().rqr.write(|w| w.abrrq().set_bit());
+        self.detecting = true;
+    }
+
+    pub fn is_detecting(&self) -> bool {
+        self.detecting
+    }
+
+    /// Call while `is_detecting`: the new rate once detection has
+    /// finished, or an error each time a character couldn't be timed
+    pub fn poll(&mut self) -> Option<Result<u32, BaudError>> {
+        if !self.detecting {
+            return None;
+        }
+        let usart = usart::usart1();
+        let isr = usart.isr.read();
+        if isr.abre().bit_is_set() {
+            // try again on the next character
+            usart.rqr.write(|w| w.abrrq().set_bit());
+            return Some(Err(BaudError::Detection));
+        }
+        if isr.abrf().bit_is_set() {
+            self.detecting = false;
+            return Some(Ok(self.get()));
+        }
+        None
+    }
+}
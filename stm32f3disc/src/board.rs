@@ -0,0 +1,239 @@
+//! Board support for the STM32F3DISCOVERY
+//!
+//! `Board::init` takes the device and core peripherals and brings up the
+//! parts of the board every binary uses: the eight user LEDs on PE8-PE15,
+//! the user button on PA0, USART1 on PC4/PC5, and the buses and pins of the
+//! L3GD20 gyro and LSM303DLHC accelerometer/magnetometer, left for their
+//! drivers to configure. Peripherals the library drivers need but not every
+//! binary uses are handed back untouched, among them PA11/PA12, which can
+//! be USART1's CTS and RTS or the user USB connector's data lines.
+
+use embedded_hal::spi::MODE_3;
+use stm32f3xx_hal::{
+    delay::Delay,
+    gpio::{self, gpioa, Edge, Input, OpenDrain, Output, PushPull, PEx, Resistor,
+           PA5, PA6, PA7, PA11, PA12, PB6, PB7, PC4, PC5, AF4, AF5, AF7},
+    i2c::I2c,
+    pac::{self, DMA1, EXTI, I2C1, SPI1, TIM7, USART1},
+    prelude::*,
+    rcc::{Clocks, APB1},
+    serial::Serial,
+    spi::{self, Spi},
+};
+
+use crate::l3gd20::L3gd20;
+use crate::lsm303dlhc::Lsm303dlhc;
+
+/// System clock, run from the 8 MHz HSE supplied by the ST-LINK
+pub const SYSCLK_HZ: u32 = 48_000_000;
+
+/// APB2 clock, which also clocks USART1
+pub const PCLK2_HZ: u32 = 48_000_000;
+
+/// Baud rate USART1 is brought up with, `baud::BaudRate` changes it later
+pub const BAUD_RATE: u32 = 115_200;
+
+pub type Led = PEx<Output<PushPull>>;
+pub type ButtonPin = gpio::PA0<Input>;
+pub type SerialPins = (PC4<AF7<PushPull>>, PC5<AF7<PushPull>>);
+pub type SerialType = Serial<USART1, SerialPins>;
+/// USART1 CTS, and RTS as a plain output for `flow::FlowControl` to drive
+pub type FlowPins = (PA11<AF7<PushPull>>, PA12<Output<PushPull>>);
+/// PA11 and PA12 as reset left them, with the GPIOA registers needed to
+/// make them flow control pins; `Board::init` is done with those
+pub struct FlowPinParts {
+    pa11: PA11<Input>,
+    pa12: PA12<Input>,
+    moder: gpioa::MODER,
+    otyper: gpioa::OTYPER,
+    pupdr: gpioa::PUPDR,
+    afrh: gpioa::AFRH,
+}
+
+impl FlowPinParts {
+    /// CTS pulled down and RTS low, so unconnected they never hold up
+    /// either side
+    pub fn into_flow_pins(mut self) -> FlowPins {
+        let mut cts = self.pa11.into_af_push_pull::<7>(
+                        &mut self.moder, &mut self.otyper, &mut self.afrh);
+        cts.set_internal_resistor(&mut self.pupdr, Resistor::PullDown);
+        let mut rts = self.pa12.into_push_pull_output(
+                        &mut self.moder, &mut self.otyper);
+        rts.set_low().unwrap();
+        (cts, rts)
+    }
+}
+
+pub type GyroSpi = Spi<SPI1, (PA5<AF5<PushPull>>, PA6<AF5<PushPull>>, PA7<AF5<PushPull>>)>;
+pub type Gyro = L3gd20<GyroSpi, gpio::PE3<Output<PushPull>>>;
+pub type GyroDrdyPin = gpio::PE1<Input>;
+pub type SensorI2c = I2c<I2C1, (PB6<AF4<OpenDrain>>, PB7<AF4<OpenDrain>>)>;
+pub type Lsm303 = Lsm303dlhc<SensorI2c>;
+pub type MagDrdyPin = gpio::PE2<Input>;
+pub type AccelInt1Pin = gpio::PE4<Input>;
+pub type AccelInt2Pin = gpio::PE5<Input>;
+
+pub struct Board {
+    /// User LEDs in compass order, clockwise from LD3 (PE9) at north
+    pub leds: [Led; 8],
+    /// User button, raising EXTI0 on the rising edge (NVIC left masked)
+    pub button: ButtonPin,
+    pub serial: SerialType,
+    /// Not configured; `into_flow_pins` for RTS/CTS
+    pub flow_pins: FlowPinParts,
+    /// Gyro driver, call `init` before reading
+    pub gyro: Gyro,
+    /// Gyro DRDY/INT2, raising EXTI1 on the rising edge (NVIC left masked)
+    pub gyro_drdy: GyroDrdyPin,
+    /// Accelerometer/magnetometer driver, call `init` before reading
+    pub lsm303: Lsm303,
+    /// Magnetometer DRDY, raising EXTI2 on the rising edge (NVIC left masked)
+    pub mag_drdy: MagDrdyPin,
+    /// Accelerometer INT1, raising EXTI4 on the rising edge (NVIC left masked)
+    pub accel_int1: AccelInt1Pin,
+    /// Accelerometer INT2, input only
+    pub accel_int2: AccelInt2Pin,
+    pub clocks: Clocks,
+    pub delay: Delay,
+    pub exti: EXTI,
+    pub apb1: APB1,
+    /// DMA controller, clocked; channels 4 and 5 serve USART1
+    pub dma1: DMA1,
+    /// Spare basic timer, used by the LED software PWM
+    pub tim7: TIM7,
+}
+
+impl Board {
+    pub fn init(dp: pac::Peripherals, cp: cortex_m::Peripherals) -> Board {
+        // the HAL has no DMA driver we use, turn on its clock directly
+        dp.RCC.ahbenr.modify(|_, w| w.dma1en().set_bit());
+        let mut rcc = dp.RCC.constrain();
+        let mut flash = dp.FLASH.constrain();
+        let clocks = rcc
+            .cfgr
+            .use_hse(8.MHz())
+            .sysclk(SYSCLK_HZ.Hz())
+            .pclk1(24.MHz())
+            .pclk2(PCLK2_HZ.Hz())
+            .freeze(&mut flash.acr);
+        let delay = Delay::new(cp.SYST, clocks);
+        let mut syscfg = dp.SYSCFG.constrain(&mut rcc.apb2);
+        let mut exti = dp.EXTI;
+
+        // LEDs, all off
+        let mut gpioe = dp.GPIOE.split(&mut rcc.ahb);
+        let mut leds = [
+            gpioe.pe9.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe10.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe11.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe12.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe13.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe14.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe15.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+            gpioe.pe8.into_push_pull_output(
+                            &mut gpioe.moder, &mut gpioe.otyper).downgrade(),
+        ];
+        for led in leds.iter_mut() {
+            led.set_low().unwrap();
+        }
+
+        // User button, interrupt on press
+        let mut gpioa = dp.GPIOA.split(&mut rcc.ahb);
+        let mut button = gpioa.pa0.into_pull_down_input(
+                        &mut gpioa.moder, &mut gpioa.pupdr);
+        syscfg.select_exti_interrupt_source(&button);
+        button.trigger_on_edge(&mut exti, Edge::Rising);
+        button.enable_interrupt(&mut exti);
+
+        // USART1 on PC4 (TX) and PC5 (RX)
+        let mut gpioc = dp.GPIOC.split(&mut rcc.ahb);
+        let tx = gpioc.pc4.into_af_push_pull::<7>(
+                        &mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
+        let rx = gpioc.pc5.into_af_push_pull::<7>(
+                        &mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
+        let serial = Serial::new(
+            dp.USART1,
+            (tx, rx),
+            BAUD_RATE.Bd(),
+            clocks,
+            &mut rcc.apb2,
+        );
+
+        // L3GD20 on SPI1, chip select on PE3, data ready on PE1
+        let sck = gpioa.pa5.into_af_push_pull::<5>(
+                        &mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrl);
+        let miso = gpioa.pa6.into_af_push_pull::<5>(
+                        &mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrl);
+        let mosi = gpioa.pa7.into_af_push_pull::<5>(
+                        &mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrl);
+        let spi = Spi::new(
+            dp.SPI1,
+            (sck, miso, mosi),
+            spi::config::Config::default().frequency(3.MHz()).mode(MODE_3),
+            clocks,
+            &mut rcc.apb2,
+        );
+        let gyro_cs = gpioe.pe3.into_push_pull_output(
+                        &mut gpioe.moder, &mut gpioe.otyper);
+        let gyro = L3gd20::new(spi, gyro_cs);
+        let mut gyro_drdy = gpioe.pe1.into_floating_input(
+                        &mut gpioe.moder, &mut gpioe.pupdr);
+        syscfg.select_exti_interrupt_source(&gyro_drdy);
+        gyro_drdy.trigger_on_edge(&mut exti, Edge::Rising);
+        gyro_drdy.enable_interrupt(&mut exti);
+
+        // LSM303DLHC on I2C1, interrupt lines on PE2, PE4 and PE5
+        let mut gpiob = dp.GPIOB.split(&mut rcc.ahb);
+        let scl = gpiob.pb6.into_af_open_drain::<4>(
+                        &mut gpiob.moder, &mut gpiob.otyper, &mut gpiob.afrl);
+        let sda = gpiob.pb7.into_af_open_drain::<4>(
+                        &mut gpiob.moder, &mut gpiob.otyper, &mut gpiob.afrl);
+        let i2c = I2c::new(dp.I2C1, (scl, sda), 400.kHz(), clocks, &mut rcc.apb1);
+        let lsm303 = Lsm303dlhc::new(i2c);
+        let mut mag_drdy = gpioe.pe2.into_floating_input(
+                        &mut gpioe.moder, &mut gpioe.pupdr);
+        syscfg.select_exti_interrupt_source(&mag_drdy);
+        mag_drdy.trigger_on_edge(&mut exti, Edge::Rising);
+        mag_drdy.enable_interrupt(&mut exti);
+        let mut accel_int1 = gpioe.pe4.into_floating_input(
+                        &mut gpioe.moder, &mut gpioe.pupdr);
+        syscfg.select_exti_interrupt_source(&accel_int1);
+        accel_int1.trigger_on_edge(&mut exti, Edge::Rising);
+        accel_int1.enable_interrupt(&mut exti);
+        let accel_int2 = gpioe.pe5.into_floating_input(
+                        &mut gpioe.moder, &mut gpioe.pupdr);
+
+        Board {
+            leds,
+            button,
+            serial,
+            flow_pins: FlowPinParts {
+                pa11: gpioa.pa11,
+                pa12: gpioa.pa12,
+                moder: gpioa.moder,
+                otyper: gpioa.otyper,
+                pupdr: gpioa.pupdr,
+                afrh: gpioa.afrh,
+            },
+            gyro,
+            gyro_drdy,
+            lsm303,
+            mag_drdy,
+            accel_int1,
+            accel_int2,
+            clocks,
+            delay,
+            exti,
+            apb1: rcc.apb1,
+            dma1: dp.DMA1,
+            tim7: dp.TIM7,
+        }
+    }
+}
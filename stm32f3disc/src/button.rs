@@ -0,0 +1,56 @@
+//! Debounced user button with press, click, long-press and double-click
+//! events
+//!
+//! `Debouncer` (from `stm32f3disc_logic`, re-exported here) holds the
+//! timing logic and only sees pin levels and millisecond timestamps.
+//! `Button` wraps it around the PA0 pin from `Board::init`: EXTI0 fires on
+//! both edges and the handler calls `on_interrupt`, while `poll` must also
+//! run every millisecond or so (from SysTick or a periodic task) to confirm
+//! a level once it has been stable for `DEBOUNCE_MS`, to spot long presses
+//! and to send a `Click` once the double click window has passed. Events
+//! collect in a small queue that the main loop or an RTIC task drains with
+//! `pop`.
+
+use stm32f3xx_hal::{gpio::Edge, pac::EXTI, prelude::*};
+
+pub use stm32f3disc_logic::button::*;
+
+use crate::board::ButtonPin;
+
+pub struct Button {
+    pin: ButtonPin,
+    debouncer: Debouncer,
+    events: EventQueue,
+}
+
+impl Button {
+    /// Takes the button as `Board::init` configured it and moves its EXTI
+    /// line to both edges so releases are seen too
+    pub fn new(mut pin: ButtonPin, exti: &mut EXTI) -> Button {
+        pin.trigger_on_edge(exti, Edge::RisingFalling);
+        Button { pin, debouncer: Debouncer::new(), events: EventQueue::new() }
+    }
+
+    /// Call from the EXTI0 handler
+    pub fn on_interrupt(&mut self, now_ms: u32) {
+        self.pin.clear_interrupt();
+        self.poll(now_ms);
+    }
+
+    /// Call about every millisecond
+    pub fn poll(&mut self, now_ms: u32) {
+        let level = self.pin.is_high().unwrap();
+        let events = &mut self.events;
+        self.debouncer.update(level, now_ms, |event| {
+            events.put_overwrite(event);
+        });
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        self.debouncer.is_pressed()
+    }
+
+    pub fn pop(&mut self) -> Option<ButtonEvent> {
+        self.events.get()
+    }
+}
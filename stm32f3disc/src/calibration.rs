@@ -0,0 +1,18 @@
+//! Sensor calibration kept in flash
+//!
+//! The calibration itself and the guided `Calibrator` are in
+//! `stm32f3disc_logic` and re-exported here. The record goes in the flash
+//! page `memory.x` reserves as CALIB.
+
+pub use stm32f3disc_logic::calibration::*;
+
+use crate::storage::{self, FlashError};
+
+/// The calibration stored in flash, if there is a valid one
+pub fn load() -> Option<Calibration> {
+    Calibration::from_bytes(storage::calib_page())
+}
+
+pub fn save(cal: &Calibration) -> Result<(), FlashError> {
+    storage::write_calib_page(&cal.to_bytes())
+}
@@ -0,0 +1,30 @@
+//! Millisecond time base on SysTick for the bare-metal binaries
+//!
+//! The application owns the `SysTick` exception handler and calls `tick()`
+//! from it; the handler can't live here because the RTIC example links this
+//! crate and its SysTick monotonic defines the same symbol.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use cortex_m::peripheral::{syst::SystClkSource, SYST};
+
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+/// Start SysTick interrupting once a millisecond
+pub fn start(mut syst: SYST, sysclk_hz: u32) {
+    syst.set_clock_source(SystClkSource::Core);
+    syst.set_reload(sysclk_hz / 1_000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+    syst.enable_interrupt();
+}
+
+/// Count one millisecond, call from the `SysTick` handler
+pub fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Milliseconds since `start`, wraps after about 49 days
+pub fn now_ms() -> u32 {
+    TICKS.load(Ordering::Relaxed)
+}
@@ -0,0 +1,225 @@
+//! Built-in shell commands for driving the board from a terminal
+//!
+//! Each binary implements `Control` over whatever owns its LEDs and
+//! pattern and registers `builtin()` with its `shell::Shell`:
+//!
+//! | command                       | does                                      |
+//! |-------------------------------|-------------------------------------------|
+//! | `led <n> on\|off`             | set one LED, 0 north clockwise to 7; pauses |
+//! | `pattern [<name>]`            | play a pattern, or list them              |
+//! | `pause`                       | freeze the pattern, or start it again     |
+//! | `status`                      | pattern, pause state, uptime, serial setup |
+//! | `uptime`                      | time since reset                          |
+//! | `reset`                       | restart the board                         |
+//! | `baud [<rate\|auto>]`         | show or change the USART1 baud rate       |
+//! | `flow [none\|rtscts\|xonxoff]` | show or set USART1 flow control           |
+
+use core::fmt::Write;
+
+use crate::baud::{self, BaudChange};
+use crate::board;
+use crate::flow::{FlowError, FlowMode};
+use crate::pattern::Pattern;
+use crate::shell::{Arg, Command, Value};
+
+/// What the built-in commands need from the application
+pub trait Control {
+    /// Light or put out LED `index`, compass order from north. The pattern
+    /// pauses so the LED stays that way
+    fn set_led(&mut self, index: usize, on: bool);
+    fn pattern(&self) -> Pattern;
+    /// Play `pattern` from its first frame, unpausing
+    fn set_pattern(&mut self, pattern: Pattern);
+    fn is_paused(&self) -> bool;
+    fn set_paused(&mut self, paused: bool);
+    /// Milliseconds since reset
+    fn uptime_ms(&self) -> u32;
+    /// Anything `status` should show besides the pattern and uptime
+    fn status(&self, _out: &mut dyn Write) {}
+    /// Reset the board once the reply has gone out
+    fn reset(&mut self);
+    /// USART1 baud rate now
+    fn baud_rate(&self) -> u32;
+    /// Make `change` once the reply has gone out at the old rate; the rate
+    /// has been checked
+    fn change_baud(&mut self, change: BaudChange);
+    fn flow_mode(&self) -> FlowMode;
+    /// Switch to `mode` once the reply has gone out under the old one;
+    /// fails at once for `FlowMode::Hardware` unless the binary has set up
+    /// the pins
+    fn change_flow_mode(&mut self, mode: FlowMode) -> Result<(), FlowError>;
+}
+
+const LED_ARGS: [Arg; 2] = [Arg::int("n", 0, 7), Arg::choice("state", &["on", "off"])];
+const PATTERN_ARGS: [Arg; 1] = [Arg::word("name").optional()];
+const BAUD_ARGS: [Arg; 1] = [Arg::word("rate|auto").optional()];
+/// In `FlowMode::ALL` order
+const FLOW_ARGS: [Arg; 1] = [Arg::choice("mode", &["none", "rtscts", "xonxoff"]).optional()];
+
+/// How many commands `builtin` returns
+pub const BUILTIN_COUNT: usize = 8;
+/// Shell size for the built-in commands and a few of a binary's own
+pub const SHELL_SIZE: usize = BUILTIN_COUNT + 4;
+
+/// The commands in the table above, for `Shell::register_all`
+pub fn builtin<C: Control>() -> [Command<C>; BUILTIN_COUNT] {
+    [
+        Command {
+            name: "led",
+            help: "turn an LED on or off, pausing the pattern",
+            args: &LED_ARGS,
+            run: led::<C>,
+        },
+        Command {
+            name: "pattern",
+            help: "play a pattern, or list them",
+            args: &PATTERN_ARGS,
+            run: pattern::<C>,
+        },
+        Command {
+            name: "pause",
+            help: "pause or resume the pattern",
+            args: &[],
+            run: pause::<C>,
+        },
+        Command {
+            name: "status",
+            help: "show what the board is doing",
+            args: &[],
+            run: status::<C>,
+        },
+        Command {
+            name: "uptime",
+            help: "time since reset",
+            args: &[],
+            run: uptime::<C>,
+        },
+        Command {
+            name: "reset",
+            help: "restart the board",
+            args: &[],
+            run: reset::<C>,
+        },
+        Command {
+            name: "baud",
+            help: "show or change the baud rate, auto to match the next Enter",
+            args: &BAUD_ARGS,
+            run: baud::<C>,
+        },
+        Command {
+            name: "flow",
+            help: "show or set flow control, RTS/CTS on PA12/PA11 or XON/XOFF",
+            args: &FLOW_ARGS,
+            run: flow::<C>,
+        },
+    ]
+}
+
+fn led<C: Control>(control: &mut C, args: &[Value<'_>],
+                   _out: &mut dyn Write) -> Result<(), &'static str> {
+    let index = args[0].int().unwrap_or(0) as usize;
+    control.set_led(index, args[1].choice() == Some(0));
+    Ok(())
+}
+
+fn pattern<C: Control>(control: &mut C, args: &[Value<'_>],
+                       out: &mut dyn Write) -> Result<(), &'static str> {
+    let name = match args[0].word() {
+        Some(name) => name,
+        None => {
+            for pattern in Pattern::BUILTIN.iter() {
+                let mark = if *pattern == control.pattern() { '*' } else { ' ' };
+                write!(out, "{} {}\r\n", mark, pattern.name()).ok();
+            }
+            return Ok(());
+        }
+    };
+    match Pattern::from_name(name) {
+        Some(pattern) => {
+            control.set_pattern(pattern);
+            Ok(())
+        }
+        None => Err("no such pattern, pattern on its own lists them"),
+    }
+}
+
+fn pause<C: Control>(control: &mut C, _args: &[Value<'_>],
+                     out: &mut dyn Write) -> Result<(), &'static str> {
+    let paused = !control.is_paused();
+    control.set_paused(paused);
+    out.write_str(if paused { "paused\r\n" } else { "running\r\n" }).ok();
+    Ok(())
+}
+
+fn status<C: Control>(control: &mut C, _args: &[Value<'_>],
+                      out: &mut dyn Write) -> Result<(), &'static str> {
+    let state = if control.is_paused() { "paused" } else { "running" };
+    write!(out, "pattern {}, {}\r\nuptime ", control.pattern().name(), state).ok();
+    write_uptime(out, control.uptime_ms());
+    write!(out, "\r\n{} baud, flow control {}\r\n",
+           control.baud_rate(), control.flow_mode().name()).ok();
+    control.status(out);
+    Ok(())
+}
+
+fn uptime<C: Control>(control: &mut C, _args: &[Value<'_>],
+                      out: &mut dyn Write) -> Result<(), &'static str> {
+    write_uptime(out, control.uptime_ms());
+    out.write_str("\r\n").ok();
+    Ok(())
+}
+
+fn reset<C: Control>(control: &mut C, _args: &[Value<'_>],
+                     out: &mut dyn Write) -> Result<(), &'static str> {
+    out.write_str("resetting\r\n").ok();
+    control.reset();
+    Ok(())
+}
+
+fn baud<C: Control>(control: &mut C, args: &[Value<'_>],
+                    out: &mut dyn Write) -> Result<(), &'static str> {
+    let change = match args[0].word() {
+        None => {
+            write!(out, "{} baud\r\n", control.baud_rate()).ok();
+            return Ok(());
+        }
+        Some("auto") => BaudChange::Detect,
+        Some(word) => match word.parse::<u32>() {
+            Ok(rate) if baud::divider(board::PCLK2_HZ, rate).is_some() => BaudChange::To(rate),
+            Ok(_) => return Err("rate out of range"),
+            Err(_) => return Err("give a rate or auto"),
+        },
+    };
+    match change {
+        BaudChange::To(rate) => write!(out, "switching to {} baud\r\n", rate).ok(),
+        BaudChange::Detect => out.write_str("press Enter at the new rate\r\n").ok(),
+    };
+    control.change_baud(change);
+    Ok(())
+}
+
+fn flow<C: Control>(control: &mut C, args: &[Value<'_>],
+                    out: &mut dyn Write) -> Result<(), &'static str> {
+    let mode = match args[0].choice() {
+        Some(index) => FlowMode::ALL[index],
+        None => {
+            write!(out, "flow control {}\r\n", control.flow_mode().name()).ok();
+            return Ok(());
+        }
+    };
+    control.change_flow_mode(mode)
+        .map_err(|FlowError::NoPins| "no RTS/CTS pins in this build")?;
+    write!(out, "switching to flow control {}\r\n", mode.name()).ok();
+    Ok(())
+}
+
+/// `[<days>d ]hh:mm:ss.mmm`
+fn write_uptime(out: &mut dyn Write, ms: u32) {
+    let secs = ms / 1000;
+    let days = secs / 86_400;
+    if days > 0 {
+        write!(out, "{}d ", days).ok();
+    }
+    write!(out, "{:02}:{:02}:{:02}.{:03}",
+           secs / 3600 % 24, secs / 60 % 60, secs % 60, ms % 1000).ok();
+}
@@ -0,0 +1,189 @@
+//! Flow control for USART1
+//!
+//! The receive queue has a high and a low watermark. When it fills past
+//! the high one `FlowControl` asks the far end to stop sending, and once
+//! the application has drained it to the low one, to go on:
+//!
+//! * `FlowMode::Hardware` raises RTS on PA12 to stop the far end and lowers
+//!   it again to let it send, and has the USART hold its own transmission
+//!   while CTS on PA11 is high. RTS is driven as a GPIO because the USART's
+//!   RTS only covers its one byte receive register, which DMA empties at
+//!   once
+//! * `FlowMode::XonXoff` sends XOFF and XON ahead of any queued output, and
+//!   stops transmitting when the far end sends XOFF until it sends XON.
+//!   Those two bytes never reach the receive queue
+//!
+//! The receive side passes each byte through `filter` and calls `received`
+//! with the queue length after adding to it; the application calls
+//! `drained` after taking from it. A transmit path doing XON/XOFF sends
+//! `next_control` before anything else and asks `may_send` before sending
+//! data. Everything takes `&self`, so a `FlowControl` can be a `static`
+//! shared by the interrupts and the application.
+//!
+//! `Board::init` leaves PA11 and PA12 alone, as they are also the user USB
+//! connector's data lines. A binary that wants RTS/CTS turns them into
+//! flow control pins with `board::FlowPinParts::into_flow_pins` and hands
+//! them to `use_pins`; until then `set_mode` refuses `FlowMode::Hardware`.
+//!
+//! The decisions are made by `FlowState`, from `stm32f3disc_logic` and
+//! re-exported here with `FlowMode`, `XON` and `XOFF`.
+
+use core::cell::Cell;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use cortex_m::peripheral::NVIC;
+use critical_section::Mutex;
+use stm32f3xx_hal::pac::{self, Interrupt};
+
+pub use stm32f3disc_logic::flow::*;
+
+use crate::board::FlowPins;
+use crate::usart;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowError {
+    /// `FlowMode::Hardware` without `use_pins`
+    NoPins,
+}
+
+pub struct FlowControl {
+    /// The transmit interrupt, pended when there is a control byte to send
+    /// or the far end lets us go on
+    kick: Interrupt,
+    /// PA11 and PA12 are CTS and RTS
+    pins: AtomicBool,
+    state: Mutex<Cell<FlowState>>,
+}
+
+impl FlowControl {
+    /// Stop the far end at `high` bytes queued, let it go on at `low`. Starts
+    /// in `FlowMode::None`
+    pub const fn new(low: usize, high: usize, kick: Interrupt) -> FlowControl {
+        FlowControl {
+            kick,
+            pins: AtomicBool::new(false),
+            state: Mutex::new(Cell::new(FlowState::new(low, high))),
+        }
+    }
+
+    fn with<R>(&self, f: impl FnOnce(&mut FlowState) -> R) -> R {
+        critical_section::with(|cs| {
+            let cell = self.state.borrow(cs);
+            let mut state = cell.get();
+            let res = f(&mut state);
+            cell.set(state);
+            res
+        })
+    }
+
+    /// Run `f` on the state and carry out what it asks for
+    fn update(&self, f: impl FnOnce(&mut FlowState) -> Signal) {
+        let signal = self.with(|state| {
+            let signal = f(state);
+            // inside the critical section so the pin can't end up out of
+            // step with the state
+            if let Signal::Rts(stop) = signal {
+                self.set_rts(stop);
+            }
+            signal
+        });
+        if signal == Signal::Kick {
+            NVIC::pend(self.kick);
+        }
+    }
+
+    /// Make `FlowMode::Hardware` available, on pins set up for it. They
+    /// stay that way for good
+    pub fn use_pins(&self, _pins: FlowPins) {
+        self.pins.store(true, Ordering::Relaxed);
+    }
+
+    pub fn mode(&self) -> FlowMode {
+        self.with(|state| state.mode())
+    }
+
+    /// Whether `set_mode` would take `mode`
+    pub fn check(&self, mode: FlowMode) -> Result<(), FlowError> {
+        if mode == FlowMode::Hardware && !self.pins.load(Ordering::Relaxed) {
+            return Err(FlowError::NoPins);
+        }
+        Ok(())
+    }
+
+    /// Switch mode, letting the far end send again whatever the old mode
+    /// had told it. Let queued output drain first, as for a baud rate
+    /// change, so it all goes out under the mode it was written for
+    pub fn set_mode(&self, mode: FlowMode) -> Result<(), FlowError> {
+        self.check(mode)?;
+        let hardware = mode == FlowMode::Hardware;
+        // CTSE can only be changed with the USART disabled
+        if usart::usart1().cr3.read().ctse().bit_is_set() != hardware {
+            usart::reconfigure(|usart| usart.cr3.modify(|_, w| w.ctse().bit(hardware)));
+        }
+        self.with(|state| state.set_mode(mode));
+        self.set_rts(false);
+        NVIC::pend(self.kick);
+        Ok(())
+    }
+
+    /// We have asked the far end to stop
+    pub fn is_throttled(&self) -> bool {
+        self.with(|state| state.is_throttled())
+    }
+
+    /// The far end has asked us to stop
+    pub fn is_stopped(&self) -> bool {
+        self.with(|state| state.is_stopped())
+    }
+
+    /// Call from the receive side after queuing bytes, with the queue length
+    pub fn received(&self, queued: usize) {
+        self.update(|state| state.received(queued));
+    }
+
+    /// Call from the application after taking bytes, with the queue length
+    pub fn drained(&self, queued: usize) {
+        self.update(|state| state.drained(queued));
+    }
+
+    /// Received `byte`, unless it was XON or XOFF for us
+    pub fn filter(&self, byte: u8) -> Option<u8> {
+        let mut res = None;
+        self.update(|state| {
+            let (byte, signal) = state.filter(byte);
+            res = byte;
+            signal
+        });
+        res
+    }
+
+    /// XON or XOFF to send now, ahead of any data
+    pub fn next_control(&self) -> Option<u8> {
+        self.with(|state| state.next_control())
+    }
+
+    /// Data may be sent; the USART itself waits for CTS in hardware mode
+    pub fn may_send(&self) -> bool {
+        self.with(|state| state.may_send())
+    }
+
+    /// Whether the transmit interrupt has anything to do, with
+    /// `data_queued` true if there is output waiting
+    pub fn wants_transmit(&self, data_queued: bool) -> bool {
+        self.with(|state| state.wants_transmit(data_queued))
+    }
+
+    /// RTS is active low: high asks the far end to stop. Left alone unless
+    /// the pins are ours
+    fn set_rts(&self, stop: bool) {
+        if !self.pins.load(Ordering::Relaxed) {
+            return;
+        }
+        let gpioa = unsafe { &*pac::GPIOA::ptr() };
+        if stop {
+            gpioa.bsrr.write(|w| w.bs12().set_bit());
+        } else {
+            gpioa.bsrr.write(|w| w.br12().set_bit());
+        }
+    }
+}
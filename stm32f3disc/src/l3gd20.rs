@@ -0,0 +1,212 @@
+//! L3GD20 three axis gyroscope on SPI
+//!
+//! On the discovery board the gyro is on SPI1 (PA5 SCK, PA6 MISO, PA7 MOSI)
+//! with chip select on PE3 and its DRDY/INT2 output on PE1; `Board::init`
+//! sets all of that up. Later board revisions fit the register compatible
+//! I3G4250D, which is accepted as well.
+//!
+//! The driver is generic over the embedded-hal SPI and pin traits. The bus
+//! must be in SPI mode 3 at no more than 10 MHz.
+
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+
+const WHO_AM_I: u8 = 0x0f;
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG3: u8 = 0x22;
+const CTRL_REG4: u8 = 0x23;
+const OUT_TEMP: u8 = 0x26;
+const STATUS_REG: u8 = 0x27;
+const OUT_X_L: u8 = 0x28;
+
+const READ: u8 = 0x80;
+const AUTO_INCREMENT: u8 = 0x40;
+
+/// WHO_AM_I of the L3GD20
+pub const L3GD20_ID: u8 = 0xd4;
+/// WHO_AM_I of the I3G4250D on newer boards
+pub const I3G4250D_ID: u8 = 0xd3;
+
+const CTRL1_POWER_ON: u8 = 0x08;
+const CTRL1_XYZ_ENABLE: u8 = 0x07;
+const CTRL3_I2_DRDY: u8 = 0x08;
+const CTRL4_BDU: u8 = 0x80;
+const STATUS_ZYXDA: u8 = 0x08;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    /// Chip select couldn't be driven
+    Pin,
+    /// WHO_AM_I didn't match, holds what was read
+    WrongDevice(u8),
+}
+
+/// Full scale range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scale {
+    Dps250,
+    Dps500,
+    Dps2000,
+}
+
+impl Scale {
+    fn bits(self) -> u8 {
+        match self {
+            Scale::Dps250 => 0x00,
+            Scale::Dps500 => 0x10,
+            Scale::Dps2000 => 0x20,
+        }
+    }
+
+    /// Degrees per second per LSB
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            Scale::Dps250 => 0.008_75,
+            Scale::Dps500 => 0.017_5,
+            Scale::Dps2000 => 0.07,
+        }
+    }
+}
+
+/// Output data rate, each with the lowest bandwidth setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Odr {
+    Hz95,
+    Hz190,
+    Hz380,
+    Hz760,
+}
+
+impl Odr {
+    fn bits(self) -> u8 {
+        match self {
+            Odr::Hz95 => 0x00,
+            Odr::Hz190 => 0x40,
+            Odr::Hz380 => 0x80,
+            Odr::Hz760 => 0xc0,
+        }
+    }
+
+    pub fn hz(self) -> u32 {
+        match self {
+            Odr::Hz95 => 95,
+            Odr::Hz190 => 190,
+            Odr::Hz380 => 380,
+            Odr::Hz760 => 760,
+        }
+    }
+}
+
+pub struct L3gd20<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    scale: Scale,
+}
+
+impl<SPI, CS, E> L3gd20<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+    CS: OutputPin,
+{
+    /// Wrap the bus and chip select, the sensor is left untouched until `init`
+    pub fn new(spi: SPI, mut cs: CS) -> L3gd20<SPI, CS> {
+        cs.set_high().ok();
+        L3gd20 { spi, cs, scale: Scale::Dps250 }
+    }
+
+    /// Check the device is there and start it measuring on all three axes
+    pub fn init(&mut self, odr: Odr, scale: Scale) -> Result<(), Error<E>> {
+        let id = self.who_am_i()?;
+        if id != L3GD20_ID && id != I3G4250D_ID {
+            return Err(Error::WrongDevice(id));
+        }
+        self.set_scale(scale)?;
+        self.write_register(CTRL_REG1,
+                odr.bits() | CTRL1_POWER_ON | CTRL1_XYZ_ENABLE)
+    }
+
+    pub fn who_am_i(&mut self) -> Result<u8, Error<E>> {
+        self.read_register(WHO_AM_I)
+    }
+
+    pub fn set_odr(&mut self, odr: Odr) -> Result<(), Error<E>> {
+        let ctrl1 = self.read_register(CTRL_REG1)?;
+        self.write_register(CTRL_REG1, (ctrl1 & 0x0f) | odr.bits())
+    }
+
+    pub fn set_scale(&mut self, scale: Scale) -> Result<(), Error<E>> {
+        // block data update so the high and low bytes always match
+        self.write_register(CTRL_REG4, CTRL4_BDU | scale.bits())?;
+        self.scale = scale;
+        Ok(())
+    }
+
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// Drive DRDY/INT2 high whenever a new sample is ready
+    pub fn enable_drdy_interrupt(&mut self, enable: bool) -> Result<(), Error<E>> {
+        let ctrl3 = self.read_register(CTRL_REG3)?;
+        let ctrl3 = if enable { ctrl3 | CTRL3_I2_DRDY } else { ctrl3 & !CTRL3_I2_DRDY };
+        self.write_register(CTRL_REG3, ctrl3)
+    }
+
+    /// A new sample is waiting on all three axes
+    pub fn data_ready(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.read_register(STATUS_REG)? & STATUS_ZYXDA != 0)
+    }
+
+    /// Angular rate in LSBs, x, y, z
+    pub fn raw(&mut self) -> Result<[i16; 3], Error<E>> {
+        let mut buf = [0; 7];
+        buf[0] = READ | AUTO_INCREMENT | OUT_X_L;
+        self.transfer(&mut buf)?;
+        Ok([
+            i16::from_le_bytes([buf[1], buf[2]]),
+            i16::from_le_bytes([buf[3], buf[4]]),
+            i16::from_le_bytes([buf[5], buf[6]]),
+        ])
+    }
+
+    /// Angular rate in degrees per second, x, y, z
+    pub fn gyro(&mut self) -> Result<[f32; 3], Error<E>> {
+        let raw = self.raw()?;
+        let sensitivity = self.scale.sensitivity();
+        Ok([
+            raw[0] as f32 * sensitivity,
+            raw[1] as f32 * sensitivity,
+            raw[2] as f32 * sensitivity,
+        ])
+    }
+
+    /// Die temperature, uncalibrated and falling as it gets warmer
+    pub fn temperature(&mut self) -> Result<i8, Error<E>> {
+        Ok(self.read_register(OUT_TEMP)? as i8)
+    }
+
+    pub fn free(self) -> (SPI, CS) {
+        (self.spi, self.cs)
+    }
+
+    fn read_register(&mut self, reg: u8) -> Result<u8, Error<E>> {
+        let mut buf = [READ | reg, 0];
+        self.transfer(&mut buf)?;
+        Ok(buf[1])
+    }
+
+    fn write_register(&mut self, reg: u8, value: u8) -> Result<(), Error<E>> {
+        self.cs.set_low().map_err(|_| Error::Pin)?;
+        let res = self.spi.write(&[reg, value]).map_err(Error::Spi);
+        self.cs.set_high().map_err(|_| Error::Pin)?;
+        res
+    }
+
+    fn transfer(&mut self, buf: &mut [u8]) -> Result<(), Error<E>> {
+        self.cs.set_low().map_err(|_| Error::Pin)?;
+        let res = self.spi.transfer(buf).map(|_| ()).map_err(Error::Spi);
+        self.cs.set_high().map_err(|_| Error::Pin)?;
+        res
+    }
+}
@@ -0,0 +1,102 @@
+//! The eight user LEDs as a compass rose
+//!
+//! The LEDs are laid out in a ring around the board, so rather than indexing
+//! the array from `Board::init` by number, `CompassLeds` addresses them by
+//! `Direction` or by bearing. Bit `n` of a mask is `Direction` index `n`,
+//! i.e. bit 0 is north and the bits run clockwise.
+
+use stm32f3xx_hal::prelude::*;
+
+use crate::board::Led;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    N,      // LD3 PE9 red
+    NE,     // LD5 PE10 orange
+    E,      // LD7 PE11 green
+    SE,     // LD9 PE12 blue
+    S,      // LD10 PE13 red
+    SW,     // LD8 PE14 orange
+    W,      // LD6 PE15 green
+    NW,     // LD4 PE8 blue
+}
+
+impl Direction {
+    /// All directions, clockwise from north
+    pub const ALL: [Direction; 8] = [
+        Direction::N,
+        Direction::NE,
+        Direction::E,
+        Direction::SE,
+        Direction::S,
+        Direction::SW,
+        Direction::W,
+        Direction::NW,
+    ];
+
+    /// Direction for an index into `ALL`, wrapping past 7
+    pub fn from_index(index: usize) -> Direction {
+        Direction::ALL[index % 8]
+    }
+
+    /// Direction nearest to a bearing in degrees clockwise from north
+    pub fn from_bearing(degrees: f32) -> Direction {
+        let mut bearing = degrees % 360.0;
+        if bearing < 0.0 {
+            bearing += 360.0;
+        }
+        Direction::from_index((bearing / 45.0 + 0.5) as usize)
+    }
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn mask(self) -> u8 {
+        1 << self.index()
+    }
+
+    pub fn opposite(self) -> Direction {
+        Direction::from_index(self.index() + 4)
+    }
+}
+
+pub struct CompassLeds {
+    leds: [Led; 8],
+}
+
+impl CompassLeds {
+    /// Takes the LEDs in the compass order `Board::init` returns them in
+    pub fn new(leds: [Led; 8]) -> CompassLeds {
+        CompassLeds { leds }
+    }
+
+    pub fn set(&mut self, dir: Direction, on: bool) {
+        let led = &mut self.leds[dir.index()];
+        if on {
+            led.set_high().unwrap();
+        } else {
+            led.set_low().unwrap();
+        }
+    }
+
+    /// Light the LEDs whose bits are set, turn the rest off
+    pub fn set_mask(&mut self, mask: u8) {
+        for dir in Direction::ALL {
+            self.set(dir, mask & dir.mask() != 0);
+        }
+    }
+
+    pub fn all_off(&mut self) {
+        self.set_mask(0);
+    }
+
+    /// Light only the LED nearest to a bearing in degrees clockwise from north
+    pub fn point_at(&mut self, degrees: f32) {
+        self.set_mask(Direction::from_bearing(degrees).mask());
+    }
+
+    pub fn free(self) -> [Led; 8] {
+        self.leds
+    }
+}
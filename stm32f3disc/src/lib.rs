@@ -0,0 +1,29 @@
+//! Board support and drivers for the STM32F3DISCOVERY
+//!
+//! Shared by `src/main.rs`, the examples and the RTIC example so that every
+//! binary brings the board up the same way. The hardware-free modules come
+//! from `stm32f3disc_logic` and are re-exported under the same names.
+
+#![no_std]
+
+// the parts that don't touch the hardware, built and tested on the host
+pub use stm32f3disc_logic::{
+    ahrs, crc, framing, heading, line_editor, mode, pattern, ring_buffer, shell, spsc,
+};
+
+pub mod baud;
+pub mod board;
+pub mod button;
+pub mod calibration;
+pub mod clock;
+pub mod commands;
+pub mod flow;
+pub mod l3gd20;
+pub mod leds;
+pub mod lsm303dlhc;
+pub mod pwm;
+pub mod serial_dma;
+pub mod serial_errors;
+pub mod storage;
+pub mod uprint;
+mod usart;
@@ -0,0 +1,304 @@
+//! LSM303DLHC accelerometer and magnetometer on I2C
+//!
+//! On the discovery board the sensor is on I2C1 (PB6 SCL, PB7 SDA). The
+//! magnetometer's DRDY is on PE2 and the accelerometer's INT1 and INT2 on
+//! PE4 and PE5; `Board::init` sets all of that up.
+//!
+//! The two halves of the chip answer on separate I2C addresses and differ
+//! in register layout: accelerometer samples are little endian and left
+//! justified, magnetometer samples big endian and ordered X, Z, Y.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+const ACCEL_ADDR: u8 = 0x19;
+const MAG_ADDR: u8 = 0x1e;
+
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG3_A: u8 = 0x22;
+const CTRL_REG4_A: u8 = 0x23;
+const STATUS_REG_A: u8 = 0x27;
+const OUT_X_L_A: u8 = 0x28;
+
+const CRA_REG_M: u8 = 0x00;
+const CRB_REG_M: u8 = 0x01;
+const MR_REG_M: u8 = 0x02;
+const OUT_X_H_M: u8 = 0x03;
+const SR_REG_M: u8 = 0x09;
+const IRA_REG_M: u8 = 0x0a;
+
+/// Set in the accelerometer sub address to read several registers
+const AUTO_INCREMENT: u8 = 0x80;
+
+/// IRA_REG_M of the LSM303DLHC, ASCII 'H'
+pub const MAG_ID: u8 = 0x48;
+
+const CTRL1_XYZ_ENABLE: u8 = 0x07;
+const CTRL3_I1_DRDY1: u8 = 0x10;
+const CTRL4_BDU: u8 = 0x80;
+const CTRL4_HR: u8 = 0x08;
+const STATUS_ZYXDA: u8 = 0x08;
+const MR_CONTINUOUS: u8 = 0x00;
+const SR_DRDY: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// IRA_REG_M didn't match, holds what was read
+    WrongDevice(u8),
+}
+
+/// Accelerometer full scale range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelRange {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccelRange {
+    fn bits(self) -> u8 {
+        match self {
+            AccelRange::G2 => 0x00,
+            AccelRange::G4 => 0x10,
+            AccelRange::G8 => 0x20,
+            AccelRange::G16 => 0x30,
+        }
+    }
+
+    /// mg per LSB of the 12 bit high resolution sample
+    pub fn sensitivity(self) -> f32 {
+        match self {
+            AccelRange::G2 => 1.0,
+            AccelRange::G4 => 2.0,
+            AccelRange::G8 => 4.0,
+            AccelRange::G16 => 12.0,
+        }
+    }
+}
+
+/// Accelerometer output data rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccelOdr {
+    Hz1,
+    Hz10,
+    Hz25,
+    Hz50,
+    Hz100,
+    Hz200,
+    Hz400,
+}
+
+impl AccelOdr {
+    fn bits(self) -> u8 {
+        let odr = match self {
+            AccelOdr::Hz1 => 1,
+            AccelOdr::Hz10 => 2,
+            AccelOdr::Hz25 => 3,
+            AccelOdr::Hz50 => 4,
+            AccelOdr::Hz100 => 5,
+            AccelOdr::Hz200 => 6,
+            AccelOdr::Hz400 => 7,
+        };
+        odr << 4
+    }
+}
+
+/// Magnetometer full scale range in gauss
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MagGain {
+    G1_3,
+    G1_9,
+    G2_5,
+    G4_0,
+    G4_7,
+    G5_6,
+    G8_1,
+}
+
+impl MagGain {
+    fn bits(self) -> u8 {
+        let gain = match self {
+            MagGain::G1_3 => 1,
+            MagGain::G1_9 => 2,
+            MagGain::G2_5 => 3,
+            MagGain::G4_0 => 4,
+            MagGain::G4_7 => 5,
+            MagGain::G5_6 => 6,
+            MagGain::G8_1 => 7,
+        };
+        gain << 5
+    }
+
+    /// LSB per gauss for the X/Y axes and for Z, which differ
+    pub fn lsb_per_gauss(self) -> (f32, f32) {
+        match self {
+            MagGain::G1_3 => (1100.0, 980.0),
+            MagGain::G1_9 => (855.0, 760.0),
+            MagGain::G2_5 => (670.0, 600.0),
+            MagGain::G4_0 => (450.0, 400.0),
+            MagGain::G4_7 => (400.0, 355.0),
+            MagGain::G5_6 => (330.0, 295.0),
+            MagGain::G8_1 => (230.0, 205.0),
+        }
+    }
+}
+
+/// Magnetometer output data rate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MagOdr {
+    Hz0_75,
+    Hz1_5,
+    Hz3,
+    Hz7_5,
+    Hz15,
+    Hz30,
+    Hz75,
+    Hz220,
+}
+
+impl MagOdr {
+    fn bits(self) -> u8 {
+        (self as u8) << 2
+    }
+}
+
+pub struct Lsm303dlhc<I2C> {
+    i2c: I2C,
+    accel_range: AccelRange,
+    mag_gain: MagGain,
+}
+
+impl<I2C, E> Lsm303dlhc<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    /// Wrap the bus, the sensor is left untouched until `init`
+    pub fn new(i2c: I2C) -> Lsm303dlhc<I2C> {
+        Lsm303dlhc { i2c, accel_range: AccelRange::G2, mag_gain: MagGain::G1_3 }
+    }
+
+    /// Check the device is there and start both halves measuring
+    /// continuously on all three axes
+    pub fn init(&mut self, accel_odr: AccelOdr, accel_range: AccelRange,
+                mag_odr: MagOdr, mag_gain: MagGain) -> Result<(), Error<E>> {
+        let id = self.read_register(MAG_ADDR, IRA_REG_M)?;
+        if id != MAG_ID {
+            return Err(Error::WrongDevice(id));
+        }
+        self.set_accel_odr(accel_odr)?;
+        self.set_accel_range(accel_range)?;
+        self.set_mag_odr(mag_odr)?;
+        self.set_mag_gain(mag_gain)?;
+        self.write_register(MAG_ADDR, MR_REG_M, MR_CONTINUOUS)
+    }
+
+    pub fn set_accel_odr(&mut self, odr: AccelOdr) -> Result<(), Error<E>> {
+        self.write_register(ACCEL_ADDR, CTRL_REG1_A, odr.bits() | CTRL1_XYZ_ENABLE)
+    }
+
+    pub fn set_accel_range(&mut self, range: AccelRange) -> Result<(), Error<E>> {
+        // block data update so the high and low bytes always match
+        self.write_register(ACCEL_ADDR, CTRL_REG4_A,
+                CTRL4_BDU | CTRL4_HR | range.bits())?;
+        self.accel_range = range;
+        Ok(())
+    }
+
+    pub fn accel_range(&self) -> AccelRange {
+        self.accel_range
+    }
+
+    pub fn set_mag_odr(&mut self, odr: MagOdr) -> Result<(), Error<E>> {
+        self.write_register(MAG_ADDR, CRA_REG_M, odr.bits())
+    }
+
+    pub fn set_mag_gain(&mut self, gain: MagGain) -> Result<(), Error<E>> {
+        self.write_register(MAG_ADDR, CRB_REG_M, gain.bits())?;
+        self.mag_gain = gain;
+        Ok(())
+    }
+
+    pub fn mag_gain(&self) -> MagGain {
+        self.mag_gain
+    }
+
+    /// Drive INT1 (PE4) high whenever a new accelerometer sample is ready
+    pub fn enable_accel_drdy_interrupt(&mut self, enable: bool) -> Result<(), Error<E>> {
+        self.write_register(ACCEL_ADDR, CTRL_REG3_A,
+                if enable { CTRL3_I1_DRDY1 } else { 0 })
+    }
+
+    /// A new accelerometer sample is waiting on all three axes
+    pub fn accel_data_ready(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.read_register(ACCEL_ADDR, STATUS_REG_A)? & STATUS_ZYXDA != 0)
+    }
+
+    /// A new magnetometer sample is waiting, also signalled on DRDY (PE2)
+    pub fn mag_data_ready(&mut self) -> Result<bool, Error<E>> {
+        Ok(self.read_register(MAG_ADDR, SR_REG_M)? & SR_DRDY != 0)
+    }
+
+    /// Acceleration as 12 bit samples, x, y, z
+    pub fn accel_raw(&mut self) -> Result<[i16; 3], Error<E>> {
+        let mut buf = [0; 6];
+        self.i2c
+            .write_read(ACCEL_ADDR, &[OUT_X_L_A | AUTO_INCREMENT], &mut buf)
+            .map_err(Error::I2c)?;
+        Ok([
+            i16::from_le_bytes([buf[0], buf[1]]) >> 4,
+            i16::from_le_bytes([buf[2], buf[3]]) >> 4,
+            i16::from_le_bytes([buf[4], buf[5]]) >> 4,
+        ])
+    }
+
+    /// Acceleration in mg, x, y, z
+    pub fn accel(&mut self) -> Result<[f32; 3], Error<E>> {
+        let raw = self.accel_raw()?;
+        let sensitivity = self.accel_range.sensitivity();
+        Ok([
+            raw[0] as f32 * sensitivity,
+            raw[1] as f32 * sensitivity,
+            raw[2] as f32 * sensitivity,
+        ])
+    }
+
+    /// Magnetic field in LSBs, x, y, z
+    pub fn mag_raw(&mut self) -> Result<[i16; 3], Error<E>> {
+        let mut buf = [0; 6];
+        self.i2c
+            .write_read(MAG_ADDR, &[OUT_X_H_M], &mut buf)
+            .map_err(Error::I2c)?;
+        // the registers run X, Z, Y
+        Ok([
+            i16::from_be_bytes([buf[0], buf[1]]),
+            i16::from_be_bytes([buf[4], buf[5]]),
+            i16::from_be_bytes([buf[2], buf[3]]),
+        ])
+    }
+
+    /// Magnetic field in milligauss, x, y, z
+    pub fn mag(&mut self) -> Result<[f32; 3], Error<E>> {
+        let raw = self.mag_raw()?;
+        let (xy, z) = self.mag_gain.lsb_per_gauss();
+        Ok([
+            raw[0] as f32 * 1000.0 / xy,
+            raw[1] as f32 * 1000.0 / xy,
+            raw[2] as f32 * 1000.0 / z,
+        ])
+    }
+
+    pub fn free(self) -> I2C {
+        self.i2c
+    }
+
+    fn read_register(&mut self, addr: u8, reg: u8) -> Result<u8, Error<E>> {
+        let mut buf = [0];
+        self.i2c.write_read(addr, &[reg], &mut buf).map_err(Error::I2c)?;
+        Ok(buf[0])
+    }
+
+    fn write_register(&mut self, addr: u8, reg: u8, value: u8) -> Result<(), Error<E>> {
+        self.i2c.write(addr, &[reg, value]).map_err(Error::I2c)
+    }
+}
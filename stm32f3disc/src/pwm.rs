@@ -0,0 +1,106 @@
+//! Software PWM brightness for the eight user LEDs
+//!
+//! Only four of the LEDs sit on TIM1 channels, so instead TIM7 interrupts
+//! every `PWM_PERIOD_US` and `SoftPwm::step` decides which LEDs are on for
+//! that slot. A PWM cycle is 255 slots, about 98 Hz, fast enough not to
+//! flicker. Levels go through a gamma 2.2 table so that equal steps in level
+//! look like equal steps in brightness. `SoftPwm` and `GAMMA` come from
+//! `stm32f3disc_logic` and are re-exported here.
+//!
+//! TIM7 interrupts 25 000 times a second, so its handler owns `PwmLeds`
+//! outright and never takes a critical section. The application sets
+//! levels through a shared `PwmLevels`, one atomic per LED, which the
+//! handler reads at the start of each cycle.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use stm32f3xx_hal::{
+    pac::TIM7,
+    prelude::*,
+    rcc::{Clocks, APB1},
+    timer::{Event, Timer},
+};
+
+pub use stm32f3disc_logic::pwm::*;
+
+use crate::leds::{CompassLeds, Direction};
+
+/// Timer interrupt period, one PWM slot
+pub const PWM_PERIOD_US: u32 = 40;
+
+/// Duty cycles handed from the application to the TIM7 interrupt
+pub struct PwmLevels {
+    duty: [AtomicU8; 8],
+}
+
+impl PwmLevels {
+    pub const fn new() -> PwmLevels {
+        PwmLevels {
+            duty: [AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0),
+                   AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0), AtomicU8::new(0)],
+        }
+    }
+
+    /// Set one LED to a perceived brightness
+    pub fn set(&self, dir: Direction, level: u8) {
+        self.duty[dir.index()].store(GAMMA[level as usize], Ordering::Relaxed);
+    }
+
+    /// Brightness of every LED, in compass order
+    pub fn set_levels(&self, levels: &[u8; 8]) {
+        for (duty, level) in self.duty.iter().zip(levels.iter()) {
+            duty.store(GAMMA[*level as usize], Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for PwmLevels {
+    fn default() -> Self {
+        PwmLevels::new()
+    }
+}
+
+/// The compass LEDs driven by `SoftPwm` from the TIM7 interrupt
+pub struct PwmLeds {
+    pwm: SoftPwm,
+    levels: &'static PwmLevels,
+    leds: CompassLeds,
+    timer: Timer<TIM7>,
+    mask: u8,
+}
+
+impl PwmLeds {
+    /// Start TIM7 showing `levels`; the caller moves this somewhere only
+    /// its handler touches, unmasks `Interrupt::TIM7` and calls
+    /// `on_interrupt` from the handler
+    pub fn new(mut leds: CompassLeds, levels: &'static PwmLevels, tim7: TIM7,
+               clocks: Clocks, apb1: &mut APB1) -> PwmLeds {
+        leds.all_off();
+        let mut timer = Timer::new(tim7, clocks, apb1);
+        timer.enable_interrupt(Event::Update);
+        timer.start(PWM_PERIOD_US.microseconds());
+        PwmLeds { pwm: SoftPwm::new(), levels, leds, timer, mask: 0 }
+    }
+
+    pub fn on_interrupt(&mut self) {
+        self.timer.clear_event(Event::Update);
+        // new levels only between cycles, so no cycle is half one and half
+        // the other
+        if self.pwm.cycle_start() {
+            for (index, duty) in self.levels.duty.iter().enumerate() {
+                self.pwm.set_duty(index, duty.load(Ordering::Relaxed));
+            }
+        }
+        let mask = self.pwm.step();
+        if mask != self.mask {
+            self.leds.set_mask(mask);
+            self.mask = mask;
+        }
+    }
+
+    /// Stop the timer and hand back the LEDs and TIM7
+    pub fn free(mut self) -> (CompassLeds, TIM7) {
+        self.timer.disable_interrupt(Event::Update);
+        (self.leds, self.timer.free())
+    }
+}
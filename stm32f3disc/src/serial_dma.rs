@@ -0,0 +1,182 @@
+//! USART1 transmit and receive through DMA
+//!
+//! DMA1 channel 4 is wired to USART1 TX. `DmaTx` takes the consumer end of
+//! an `spsc::Queue`, hands the USART the longest contiguous run of queued
+//! bytes in one go and only hears back when the whole run has gone, so the
+//! CPU sees one interrupt per block rather than one per byte.
+//!
+//! Channel 5, USART1 RX, runs `DmaRx` in circular mode over a fixed buffer
+//! and never stops. Whatever it has written since the last look is handed
+//! on when the buffer is half or completely full, when the line goes idle
+//! for a character time, or when the receiver timeout expires, so bursts
+//! arrive as variable length frames without any overruns in between.
+//!
+//! The HAL's serial driver keeps the USART; this only sets its DMA bits and
+//! receive interrupts and programs the channels through the PAC.
+
+use core::ops::Range;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use stm32f3xx_hal::pac::{self, DMA1};
+
+use crate::serial_errors::RxErrors;
+use crate::spsc::Consumer;
+
+/// The DMA1 channels serving USART1, taken from `Board::dma1`
+pub struct Channels {
+    pub tx: TxChannel,
+    pub rx: RxChannel,
+}
+
+impl Channels {
+    pub fn new(_dma1: DMA1) -> Channels {
+        Channels { tx: TxChannel { _private: () }, rx: RxChannel { _private: () } }
+    }
+}
+
+/// DMA1 channel 4, USART1 TX
+pub struct TxChannel {
+    _private: (),
+}
+
+/// DMA1 channel 5, USART1 RX
+pub struct RxChannel {
+    _private: (),
+}
+
+pub struct DmaTx<'a, const N: usize> {
+    _channel: TxChannel,
+    queue: Consumer<'a, u8, N>,
+    /// Bytes at the front of the queue the channel is sending
+    in_flight: usize,
+}
+
+impl<'a, const N: usize> DmaTx<'a, N> {
+    /// Point channel 4 at the USART1 data register and route TX requests
+    /// to it; unmask `DMA1_CH4` for `on_interrupt` to run
+    pub fn new(channel: TxChannel, queue: Consumer<'a, u8, N>) -> DmaTx<'a, N> {
+        let dma = unsafe { &*pac::DMA1::ptr() };
+        let usart = unsafe { &*pac::USART1::ptr() };
+        dma.ch4.cr.write(|w| w.dir().set_bit()     // memory to peripheral
+                              .minc().set_bit()
+                              .tcie().set_bit()
+                              .teie().set_bit());
+        dma.ch4.par.write(|w| unsafe { w.bits(&usart.tdr as *const _ as u32) });
+        usart.cr3.modify(|_, w| w.dmat().set_bit());
+        DmaTx { _channel: channel, queue, in_flight: 0 }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        self.in_flight != 0
+    }
+
+    /// Call from the DMA1_CH4 handler. Also pend that interrupt after
+    /// queuing bytes, it starts the channel if it is idle
+    pub fn on_interrupt(&mut self) {
+        let dma = unsafe { &*pac::DMA1::ptr() };
+        let isr = dma.isr.read();
+        // on a transfer error the block is dropped rather than retried
+        if isr.tcif4().bit_is_set() || isr.teif4().bit_is_set() {
+            dma.ifcr.write(|w| w.cgif4().set_bit());
+            dma.ch4.cr.modify(|_, w| w.en().clear_bit());
+            self.queue.consume(self.in_flight);
+            self.in_flight = 0;
+        }
+        self.start();
+    }
+
+    fn start(&mut self) {
+        if self.is_busy() {
+            return;
+        }
+        let block = self.queue.contiguous();
+        let block = &block[..block.len().min(0xffff)];
+        if block.is_empty() {
+            return;
+        }
+        let dma = unsafe { &*pac::DMA1::ptr() };
+        dma.ch4.mar.write(|w| unsafe { w.bits(block.as_ptr() as u32) });
+        dma.ch4.ndtr.write(|w| unsafe { w.bits(block.len() as u32) });
+        self.in_flight = block.len();
+        dma.ch4.cr.modify(|_, w| w.en().set_bit());
+    }
+}
+
+pub struct DmaRx<const N: usize> {
+    _channel: RxChannel,
+    buffer: &'static mut [u8; N],
+    /// Where the next unread byte will be
+    last: usize,
+}
+
+impl<const N: usize> DmaRx<N> {
+    /// Start channel 5 filling `buffer` round and round and enable the idle
+    /// line and receiver timeout interrupts, the timeout in bit times.
+    /// Unmask `DMA1_CH5` and `USART1_EXTI25` and call `on_interrupt` from
+    /// both, at the same priority; don't enable `ReceiveDataRegisterNotEmpty`.
+    /// Receive errors raise the USART interrupt too but are left for
+    /// `check_errors` to clear; an overrun stops the channel until then
+    pub fn new(channel: RxChannel, buffer: &'static mut [u8; N], timeout_bits: u32) -> DmaRx<N> {
+        let dma = unsafe { &*pac::DMA1::ptr() };
+        let usart = unsafe { &*pac::USART1::ptr() };
+        dma.ch5.par.write(|w| unsafe { w.bits(&usart.rdr as *const _ as u32) });
+        dma.ch5.mar.write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+        dma.ch5.ndtr.write(|w| unsafe { w.bits(N as u32) });
+        dma.ch5.cr.write(|w| w.circ().set_bit()
+                              .minc().set_bit()
+                              .htie().set_bit()
+                              .tcie().set_bit()
+                              .en().set_bit());
+        usart.rtor.write(|w| unsafe { w.bits(timeout_bits & 0x00ff_ffff) });
+        usart.cr2.modify(|_, w| w.rtoen().set_bit());
+        usart.icr.write(|w| w.idlecf().set_bit().rtocf().set_bit());
+        usart.cr1.modify(|_, w| w.idleie().set_bit().rtoie().set_bit());
+        usart.cr3.modify(|_, w| w.dmar().set_bit().eie().set_bit());
+        usart.cr1.modify(|_, w| w.peie().set_bit());
+        DmaRx { _channel: channel, buffer, last: 0 }
+    }
+
+    /// Count, report and clear USART1 receive errors, call from the same
+    /// interrupts as `on_interrupt`
+    pub fn check_errors<const M: usize>(&self, errors: &mut RxErrors<'_, M>) -> bool {
+        errors.check(unsafe { &*pac::USART1::ptr() })
+    }
+
+    /// Pass the bytes received since the last call to `data`, in order and
+    /// in at most two pieces. True if the line has gone quiet, ending a
+    /// frame; callers that care queue the frame's length for the
+    /// application, the bytes alone don't show where it ended
+    pub fn on_interrupt(&mut self, mut data: impl FnMut(&[u8])) -> bool {
+        let dma = unsafe { &*pac::DMA1::ptr() };
+        let usart = unsafe { &*pac::USART1::ptr() };
+        let isr = usart.isr.read();
+        let idle = isr.idle().bit_is_set() || isr.rtof().bit_is_set();
+        usart.icr.write(|w| w.idlecf().set_bit().rtocf().set_bit());
+        dma.ifcr.write(|w| w.cgif5().set_bit());
+
+        let pos = N - dma.ch5.ndtr.read().bits() as usize;
+        // don't let reads of the buffer move before the count that says
+        // the DMA has written them
+        compiler_fence(Ordering::Acquire);
+        let (first, second) = runs(self.last, pos, N);
+        for run in [first, second] {
+            if !run.is_empty() {
+                data(&self.buffer[run]);
+            }
+        }
+        self.last = pos % N;
+        idle
+    }
+}
+
+/// Where a circular DMA buffer of `len` bytes has been written, moving
+/// from `last` to `pos`: at most two ranges, the second empty unless the
+/// writes wrapped round the end
+pub fn runs(last: usize, pos: usize, len: usize) -> (Range<usize>, Range<usize>) {
+    let pos = pos % len;
+    if pos >= last {
+        (last..pos, 0..0)
+    } else {
+        (last..len, 0..pos)
+    }
+}
@@ -0,0 +1,135 @@
+//! USART receive error detection
+//!
+//! The USART flags overrun, framing, noise and parity errors in its status
+//! register and keeps them until software clears them; an overrun left set
+//! stops reception altogether. `RxErrors::check` reads the flags, clears
+//! them, adds each one to a set of `ErrorCounters` and passes it on as an
+//! `RxError` through an `spsc` queue, so the interrupt that sees an error
+//! and the code that logs or reports it can be in different contexts.
+//!
+//! Call `check` from the receive path before reading data. With DMA
+//! reception use `DmaRx::check_errors` from the receive interrupts instead.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use stm32f3xx_hal::pac::usart1;
+
+use crate::spsc::Producer;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RxError {
+    /// A byte arrived before the last was read; at least one was lost
+    Overrun,
+    /// No stop bit where one was expected, often a baud rate mismatch
+    Framing,
+    /// Noise seen while sampling a bit
+    Noise,
+    /// Parity bit wrong, only with parity enabled
+    Parity,
+}
+
+impl RxError {
+    pub const ALL: [RxError; 4] = [RxError::Overrun, RxError::Framing,
+                                   RxError::Noise, RxError::Parity];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RxError::Overrun => "overrun",
+            RxError::Framing => "framing",
+            RxError::Noise => "noise",
+            RxError::Parity => "parity",
+        }
+    }
+}
+
+/// Running totals, one per kind of error; can be a `static` and read from
+/// anywhere
+pub struct ErrorCounters {
+    counts: [AtomicU32; 4],
+}
+
+impl ErrorCounters {
+    pub const fn new() -> ErrorCounters {
+        ErrorCounters {
+            counts: [AtomicU32::new(0), AtomicU32::new(0),
+                     AtomicU32::new(0), AtomicU32::new(0)],
+        }
+    }
+
+    fn index(error: RxError) -> usize {
+        match error {
+            RxError::Overrun => 0,
+            RxError::Framing => 1,
+            RxError::Noise => 2,
+            RxError::Parity => 3,
+        }
+    }
+
+    pub fn add(&self, error: RxError) {
+        self.counts[ErrorCounters::index(error)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, error: RxError) -> u32 {
+        self.counts[ErrorCounters::index(error)].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u32 {
+        RxError::ALL.iter().fold(0, |sum, e| sum.wrapping_add(self.get(*e)))
+    }
+
+    pub fn reset(&self) {
+        for count in &self.counts {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for ErrorCounters {
+    fn default() -> Self {
+        ErrorCounters::new()
+    }
+}
+
+pub struct RxErrors<'a, const N: usize> {
+    counters: &'a ErrorCounters,
+    /// Errors for the application; when it falls behind they are still
+    /// counted but not queued
+    events: Producer<'a, RxError, N>,
+}
+
+impl<'a, const N: usize> RxErrors<'a, N> {
+    pub fn new(counters: &'a ErrorCounters, events: Producer<'a, RxError, N>) -> Self {
+        RxErrors { counters, events }
+    }
+
+    /// Handle any error flags `usart` has set, true if there were some
+    pub fn check(&mut self, usart: &usart1::RegisterBlock) -> bool {
+        let isr = usart.isr.read();
+        let flags = [
+            (isr.ore().bit_is_set(), RxError::Overrun),
+            (isr.fe().bit_is_set(), RxError::Framing),
+            (isr.nf().bit_is_set(), RxError::Noise),
+            (isr.pe().bit_is_set(), RxError::Parity),
+        ];
+        let mut any = false;
+        for (set, error) in flags.iter() {
+            if *set {
+                self.record(*error);
+                any = true;
+            }
+        }
+        if any {
+            usart.icr.write(|w| w.orecf().set_bit()
+                                 .fecf().set_bit()
+                                 .ncf().set_bit()
+                                 .pecf().set_bit());
+        }
+        any
+    }
+
+    /// Count and queue an error found some other way
+    pub fn record(&mut self, error: RxError) {
+        self.counters.add(error);
+        let _ = self.events.put(error);
+    }
+}
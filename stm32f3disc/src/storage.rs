@@ -0,0 +1,104 @@
+//! Erase and program the internal flash
+//!
+//! Only the page `memory.x` reserves as CALIB is meant to be written here;
+//! the rest of flash holds the program. The STM32F303 erases in 2K pages
+//! and programs a half word at a time. The CPU stalls on any flash read
+//! while an operation is in progress, so these calls also block
+//! interrupts from running out of flash for a few milliseconds (a page
+//! erase is 20-40 ms).
+
+use core::ptr;
+
+use stm32f3xx_hal::pac;
+
+/// Start of the reserved calibration page, must match `CALIB` in memory.x
+pub const CALIB_ADDR: u32 = 0x0803_f800;
+pub const PAGE_SIZE: usize = 2048;
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xcdef_89ab;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// Data longer than the page or not a whole number of half words
+    Length,
+    /// Programmed a half word that wasn't erased
+    Programming,
+    WriteProtected,
+    /// Read back didn't match what was written
+    Verify,
+}
+
+/// The calibration page as it is now
+pub fn calib_page() -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(CALIB_ADDR as *const u8, PAGE_SIZE) }
+}
+
+/// Erase the calibration page and program `data` at its start
+pub fn write_calib_page(data: &[u8]) -> Result<(), FlashError> {
+    if data.len() > PAGE_SIZE || data.len() % 2 != 0 {
+        return Err(FlashError::Length);
+    }
+    let flash = unsafe { &*pac::FLASH::ptr() };
+    unlock(flash);
+    let res = erase_page(flash, CALIB_ADDR).and_then(|_| program(flash, CALIB_ADDR, data));
+    lock(flash);
+    res?;
+    if &calib_page()[..data.len()] != data {
+        return Err(FlashError::Verify);
+    }
+    Ok(())
+}
+
+fn unlock(flash: &pac::flash::RegisterBlock) {
+    if flash.cr.read().lock().bit_is_set() {
+        flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+        flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+    }
+}
+
+fn lock(flash: &pac::flash::RegisterBlock) {
+    flash.cr.modify(|_, w| w.lock().set_bit());
+}
+
+fn wait(flash: &pac::flash::RegisterBlock) -> Result<(), FlashError> {
+    while flash.sr.read().bsy().bit_is_set() {}
+    let sr = flash.sr.read();
+    // the status flags clear by writing one to them
+    flash.sr.write(|w| w.eop().set_bit().pgerr().set_bit().wrprt().set_bit());
+    if sr.wrprt().bit_is_set() {
+        Err(FlashError::WriteProtected)
+    } else if sr.pgerr().bit_is_set() {
+        Err(FlashError::Programming)
+    } else {
+        Ok(())
+    }
+}
+
+fn erase_page(flash: &pac::flash::RegisterBlock, addr: u32) -> Result<(), FlashError> {
+    wait(flash)?;
+    flash.cr.modify(|_, w| w.per().set_bit());
+    flash.ar.write(|w| unsafe { w.bits(addr) });
+    flash.cr.modify(|_, w| w.strt().set_bit());
+    let res = wait(flash);
+    flash.cr.modify(|_, w| w.per().clear_bit());
+    res
+}
+
+fn program(flash: &pac::flash::RegisterBlock, addr: u32, data: &[u8]) -> Result<(), FlashError> {
+    wait(flash)?;
+    flash.cr.modify(|_, w| w.pg().set_bit());
+    let mut res = Ok(());
+    for (i, pair) in data.chunks(2).enumerate() {
+        let half = u16::from_le_bytes([pair[0], pair[1]]);
+        unsafe {
+            ptr::write_volatile((addr as usize + i * 2) as *mut u16, half);
+        }
+        res = wait(flash);
+        if res.is_err() {
+            break;
+        }
+    }
+    flash.cr.modify(|_, w| w.pg().clear_bit());
+    res
+}
@@ -0,0 +1,204 @@
+//! Formatted output straight into a serial transmit queue
+//!
+//! `SerialWriter` owns the producer end of the queue a transmit interrupt
+//! drains and implements `core::fmt::Write`, so `write!`, or the
+//! `uprint!`/`uprintln!` shorthands here, format directly into the queue
+//! with no intermediate buffer. After each piece it pends the interrupt
+//! that moves the queue to the USART: `DMA1_CH4` for `serial_dma::DmaTx`,
+//! the USART's own for byte at a time transmit.
+//!
+//! What happens when the queue is full is up to the writer's
+//! `Backpressure` policy. Bytes it throws away are counted in `dropped`.
+
+use core::fmt;
+
+use cortex_m::peripheral::NVIC;
+use stm32f3xx_hal::pac::Interrupt;
+
+use crate::spsc::Producer;
+
+/// What a `SerialWriter` does when the transmit queue is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait for the transmit interrupt to make room; only safe from code
+    /// that interrupt can preempt
+    Block,
+    /// Queue what fits and drop the rest
+    DropNewest,
+    /// Make room by dropping the oldest bytes not yet handed to the USART.
+    /// Where the transmit interrupt can't preempt the writer, or DMA holds
+    /// the old bytes, the room comes too late and the rest of this write is
+    /// dropped as well. Old bytes that go out anyway aren't counted
+    DropOldest,
+    /// Queue a whole `write` or formatted message, or none of it
+    AllOrNothing,
+}
+
+pub struct SerialWriter<'a, const N: usize> {
+    queue: Producer<'a, u8, N>,
+    kick: Interrupt,
+    policy: Backpressure,
+    dropped: u32,
+    /// `Producer::discarded` at `new`, the consumer counts the old bytes
+    /// it really drops
+    discarded_at_new: usize,
+    /// Inside an `AllOrNothing` `write_fmt`
+    staging: bool,
+    /// Bytes of the message so far
+    message_len: usize,
+    /// A piece of the message didn't fit
+    overflow: bool,
+}
+
+impl<'a, const N: usize> SerialWriter<'a, N> {
+    /// Write into `queue`, pending `kick` to start transmission
+    pub fn new(queue: Producer<'a, u8, N>, kick: Interrupt, policy: Backpressure) -> Self {
+        SerialWriter {
+            discarded_at_new: queue.discarded(),
+            queue,
+            kick,
+            policy,
+            dropped: 0,
+            staging: false,
+            message_len: 0,
+            overflow: false,
+        }
+    }
+
+    pub fn policy(&self) -> Backpressure {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: Backpressure) {
+        self.policy = policy;
+    }
+
+    /// Bytes thrown away since `new`, new or old
+    pub fn dropped(&self) -> u32 {
+        let discarded = self.queue.discarded().wrapping_sub(self.discarded_at_new);
+        self.dropped.wrapping_add(discarded as u32)
+    }
+
+    /// Bytes queued that the transmit interrupt hasn't taken yet
+    pub fn pending(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Queue `bytes` according to the policy, `Err` if any of them were
+    /// dropped
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        let res = self.queue_bytes(bytes);
+        NVIC::pend(self.kick);
+        res
+    }
+
+    /// Queue as much of `bytes` as fits now, whatever the policy, and
+    /// return how much that was; for callers that wait for room themselves
+    pub fn write_some(&mut self, bytes: &[u8]) -> usize {
+        let queued = self.queue.push_slice(bytes);
+        NVIC::pend(self.kick);
+        queued
+    }
+
+    fn drop_bytes(&mut self, count: usize) -> Result<(), ()> {
+        self.dropped = self.dropped.wrapping_add(count as u32);
+        if count == 0 { Ok(()) } else { Err(()) }
+    }
+
+    fn queue_bytes(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        match self.policy {
+            Backpressure::Block => {
+                let mut rest = bytes;
+                loop {
+                    rest = &rest[self.queue.push_slice(rest)..];
+                    if rest.is_empty() {
+                        return Ok(());
+                    }
+                    NVIC::pend(self.kick);
+                }
+            }
+            Backpressure::DropNewest => {
+                let queued = self.queue.push_slice(bytes);
+                self.drop_bytes(bytes.len() - queued)
+            }
+            Backpressure::DropOldest => {
+                // only the last N bytes could ever fit
+                let skip = bytes.len().saturating_sub(N);
+                let bytes = &bytes[skip..];
+                let short = bytes.len().saturating_sub(self.queue.free());
+                if short > 0 {
+                    // counted by the consumer as it drops them, some may
+                    // be with the DMA channel already
+                    self.queue.request_discard(short);
+                    // the consumer acts on it now if it can preempt us
+                    NVIC::pend(self.kick);
+                }
+                let queued = self.queue.push_slice(bytes);
+                self.drop_bytes(skip + bytes.len() - queued)
+            }
+            Backpressure::AllOrNothing => {
+                if bytes.len() > self.queue.free() {
+                    return self.drop_bytes(bytes.len());
+                }
+                self.queue.push_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl<'a, const N: usize> fmt::Write for SerialWriter<'a, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if self.staging {
+            self.message_len += s.len();
+            self.overflow = self.overflow || !self.queue.stage(s.as_bytes());
+            return if self.overflow { Err(fmt::Error) } else { Ok(()) };
+        }
+        let res = self.queue_bytes(s.as_bytes());
+        NVIC::pend(self.kick);
+        res.map_err(|_| fmt::Error)
+    }
+
+    fn write_fmt(&mut self, args: fmt::Arguments) -> fmt::Result {
+        if self.policy != Backpressure::AllOrNothing {
+            return fmt::write(self, args);
+        }
+        self.staging = true;
+        self.message_len = 0;
+        self.overflow = false;
+        let res = fmt::write(self, args);
+        self.staging = false;
+        if res.is_ok() {
+            self.queue.commit();
+            NVIC::pend(self.kick);
+            Ok(())
+        } else {
+            self.queue.abandon();
+            // formatting stops at the piece that didn't fit, so this counts
+            // the message up to there
+            let _ = self.drop_bytes(self.message_len);
+            Err(fmt::Error)
+        }
+    }
+}
+
+/// `write!` to anything implementing `core::fmt::Write`, usually a
+/// `SerialWriter`
+#[macro_export]
+macro_rules! uprint {
+    ($writer:expr, $($arg:tt)*) => {{
+        use core::fmt::Write as _;
+        $writer.write_fmt(format_args!($($arg)*))
+    }};
+}
+
+/// `uprint!` with a CR LF line ending, for serial terminals
+#[macro_export]
+macro_rules! uprintln {
+    ($writer:expr) => {
+        $crate::uprint!($writer, "\r\n")
+    };
+    ($writer:expr, $($arg:tt)*) => {
+        $crate::uprint!($writer, "{}\r\n", format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,47 @@
+//! Changing USART1's settings while it runs
+
+use stm32f3xx_hal::pac::{self, usart1};
+
+pub(crate) fn usart1() -> &'static usart1::RegisterBlock {
+    unsafe { &*pac::USART1::ptr() }
+}
+
+/// A byte is arriving or waiting in RDR, and would be lost with UE clear
+fn receiving(usart: &usart1::RegisterBlock) -> bool {
+    let isr = usart.isr.read();
+    isr.busy().bit_is_set() || isr.rxne().bit_is_set()
+}
+
+/// Run `change` with USART1 disabled, as writing BRR, CR2 or most of CR3
+/// needs, once the byte being sent has finished at the old settings and
+/// nothing is being received. The transmit interrupt and DMA requests are
+/// held meanwhile, so no byte reaches TDR between the last one finishing
+/// and UE going low. Received bytes have to be taken by DMA or by an
+/// interrupt that can preempt the caller, or this waits for ever
+pub(crate) fn reconfigure(change: impl FnOnce(&usart1::RegisterBlock)) {
+    let usart = usart1();
+    let mut change = Some(change);
+    while change.is_some() {
+        // let the receive interrupt or DMA channel take anything arriving
+        while receiving(usart) {}
+        critical_section::with(|_| {
+            let txeie = usart.cr1.read().txeie().bit_is_set();
+            let dmat = usart.cr3.read().dmat().bit_is_set();
+            usart.cr1.modify(|_, w| w.txeie().clear_bit());
+            usart.cr3.modify(|_, w| w.dmat().clear_bit());
+            while usart.isr.read().tc().bit_is_clear() {}
+            // a byte may have started arriving while that one went out;
+            // if so give the receiver another go and try again
+            if !receiving(usart) {
+                usart.cr1.modify(|_, w| w.ue().clear_bit());
+                if let Some(change) = change.take() {
+                    change(usart);
+                }
+                usart.cr1.modify(|_, w| w.ue().set_bit());
+            }
+            // TXE is set again with UE, so both pick up where they were
+            usart.cr3.modify(|_, w| w.dmat().bit(dmat));
+            usart.cr1.modify(|_, w| w.txeie().bit(txeie));
+        });
+    }
+}